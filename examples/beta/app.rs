@@ -108,7 +108,8 @@ for Technique<R> {
 type Transform<S> = cgmath::Decomposed<S, cgmath::Vector3<S>, cgmath::Quaternion<S>>;
 
 impl gfx_scene::ViewInfo<f32, Transform<f32>> for ViewInfo {
-    fn new(_: cgmath::Matrix4<f32>, _: Transform<f32>, model: Transform<f32>) -> ViewInfo {
+    fn new(_: cgmath::Matrix4<f32>, _: Transform<f32>, model: Transform<f32>,
+           _: &[cgmath::Matrix4<f32>], _: &[gfx_scene::LightInfo<f32>]) -> ViewInfo {
         ViewInfo(cgmath::Vector2::new(model.disp.x, model.disp.y))
     }
 }
@@ -174,6 +175,7 @@ impl<R: gfx::Resources + 'static> App<R> where
             gfx_scene::Entity {
                 name: format!("entity-{}", i),
                 visible: true,
+                layers: gfx_scene::ALL_LAYERS,
                 mesh: mesh.clone(),
                 node: scene.world.add(offset),
                 skeleton: None,
@@ -195,7 +197,8 @@ impl<R: gfx::Resources + 'static> App<R> where
                 bottom: -SCALE, top: SCALE,
                 near: -1f32, far: 1f32,
             },
-            node: scene.world.add(cgmath::Vector2::new(0.0, 0.0))
+            node: scene.world.add(cgmath::Vector2::new(0.0, 0.0)),
+            layers: gfx_scene::ALL_LAYERS,
         };
 
         App {
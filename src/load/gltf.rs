@@ -0,0 +1,559 @@
+//! glTF 2.0 loading. Only the JSON flavor is supported, with buffers
+//! either embedded as base64 data URIs or stored in external files.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::mem;
+use std::path::Path;
+use rustc_serialize::base64::FromBase64;
+use rustc_serialize::json::Json;
+use cgmath;
+use gfx;
+use super::{Entity, Error, Geometry, MaterialInfo, Transform, Vertex, WorldBuilder};
+
+const MODE_TRIANGLES: u64 = 4;
+
+fn error<T>(msg: String) -> Result<T, Error> {
+    Err(Error::Gltf(msg))
+}
+
+fn get_array<'a>(json: &'a Json, key: &str) -> &'a [Json] {
+    match json.find(key).and_then(|j| j.as_array()) {
+        Some(a) => &a[..],
+        None => &[],
+    }
+}
+
+fn get_index(json: &Json, key: &str) -> Option<usize> {
+    json.find(key).and_then(|j| j.as_u64()).map(|v| v as usize)
+}
+
+fn get_floats(json: &Json, key: &str, out: &mut [f32]) -> bool {
+    let values = get_array(json, key);
+    if values.len() != out.len() {
+        return false
+    }
+    for (o, v) in out.iter_mut().zip(values.iter()) {
+        *o = v.as_f64().unwrap_or(0.0) as f32;
+    }
+    true
+}
+
+fn get_float(json: &Json, key: &str, default: f32) -> f32 {
+    json.find(key).and_then(|j| j.as_f64()).map(|v| v as f32).unwrap_or(default)
+}
+
+fn read_u16(b: &[u8]) -> u16 {
+    b[0] as u16 | (b[1] as u16) << 8
+}
+
+fn read_u32(b: &[u8]) -> u32 {
+    b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24
+}
+
+/// Convert an orthonormal rotation matrix, given by rows, into a quaternion.
+fn rotation_to_quaternion(r: [[f32; 3]; 3]) -> cgmath::Quaternion<f32> {
+    let trace = r[0][0] + r[1][1] + r[2][2];
+    if trace > 0.0 {
+        let s = (trace + 1.0).sqrt() * 2.0;
+        cgmath::Quaternion::new(0.25 * s, (r[2][1] - r[1][2]) / s,
+            (r[0][2] - r[2][0]) / s, (r[1][0] - r[0][1]) / s)
+    }else if r[0][0] > r[1][1] && r[0][0] > r[2][2] {
+        let s = (1.0 + r[0][0] - r[1][1] - r[2][2]).sqrt() * 2.0;
+        cgmath::Quaternion::new((r[2][1] - r[1][2]) / s, 0.25 * s,
+            (r[0][1] + r[1][0]) / s, (r[0][2] + r[2][0]) / s)
+    }else if r[1][1] > r[2][2] {
+        let s = (1.0 + r[1][1] - r[0][0] - r[2][2]).sqrt() * 2.0;
+        cgmath::Quaternion::new((r[0][2] - r[2][0]) / s, (r[0][1] + r[1][0]) / s,
+            0.25 * s, (r[1][2] + r[2][1]) / s)
+    }else {
+        let s = (1.0 + r[2][2] - r[0][0] - r[1][1]).sqrt() * 2.0;
+        cgmath::Quaternion::new((r[1][0] - r[0][1]) / s, (r[0][2] + r[2][0]) / s,
+            (r[1][2] + r[2][1]) / s, 0.25 * s)
+    }
+}
+
+/// Get the local transformation of a node. Non-uniform scale is not
+/// supported by the transform type, so it's reported as an error.
+fn get_transform(node: &Json, index: usize) -> Result<Transform, Error> {
+    let is_uniform = |s: [f32; 3]| (s[1] - s[0]).abs() <= s[0].abs() * 1e-4 &&
+                                   (s[2] - s[0]).abs() <= s[0].abs() * 1e-4;
+    let mut m = [0.0f32; 16];
+    if get_floats(node, "matrix", &mut m) {
+        // column-major matrix
+        let column = |c: usize| (m[c*4]*m[c*4] + m[c*4+1]*m[c*4+1] + m[c*4+2]*m[c*4+2]).sqrt();
+        let scales = [column(0), column(1), column(2)];
+        if !is_uniform(scales) {
+            return error(format!("node {} has a non-uniform scale {:?}", index, scales))
+        }
+        let scale = scales[0];
+        let inv = if scale > 0.0 {1.0 / scale} else {0.0};
+        let rows = [
+            [m[0]*inv, m[4]*inv, m[8]*inv],
+            [m[1]*inv, m[5]*inv, m[9]*inv],
+            [m[2]*inv, m[6]*inv, m[10]*inv],
+        ];
+        return Ok(cgmath::Decomposed {
+            scale: scale,
+            rot: rotation_to_quaternion(rows),
+            disp: cgmath::Vector3::new(m[12], m[13], m[14]),
+        })
+    }
+    let mut t = super::identity();
+    let mut v = [0.0f32; 4];
+    if get_floats(node, "translation", &mut v[..3]) {
+        t.disp = cgmath::Vector3::new(v[0], v[1], v[2]);
+    }
+    if get_floats(node, "rotation", &mut v) {
+        // glTF stores quaternions as `[x, y, z, w]`
+        t.rot = cgmath::Quaternion::new(v[3], v[0], v[1], v[2]);
+    }
+    if get_floats(node, "scale", &mut v[..3]) {
+        if !is_uniform([v[0], v[1], v[2]]) {
+            return error(format!("node {} has a non-uniform scale {:?}", index, &v[..3]))
+        }
+        t.scale = v[0];
+    }
+    Ok(t)
+}
+
+struct Importer<'a, R: gfx::Resources> {
+    json: &'a Json,
+    buffers: Vec<Vec<u8>>,
+    materials: Vec<MaterialInfo>,
+    meshes: HashMap<usize, (Geometry, gfx::Mesh<R>, gfx::Slice<R>)>,
+}
+
+impl<'a, R: gfx::Resources> Importer<'a, R> {
+    fn get_image_uri(&self, texture: Option<&Json>) -> Option<String> {
+        let source = texture.and_then(|t| get_index(t, "index"))
+                            .and_then(|i| get_array(self.json, "textures").get(i))
+                            .and_then(|t| get_index(t, "source"));
+        source.and_then(|i| get_array(self.json, "images").get(i))
+              .and_then(|img| img.find("uri"))
+              .and_then(|uri| uri.as_string())
+              .map(|uri| uri.to_string())
+    }
+
+    fn parse_materials(&mut self) {
+        let mut materials = Vec::new();
+        for (i, mat) in get_array(self.json, "materials").iter().enumerate() {
+            let name = match mat.find("name").and_then(|n| n.as_string()) {
+                Some(name) => name.to_string(),
+                None => format!("material-{}", i),
+            };
+            let mut info = MaterialInfo::new(&name);
+            if let Some(pbr) = mat.find("pbrMetallicRoughness") {
+                get_floats(pbr, "baseColorFactor", &mut info.base_color);
+                info.metallic = get_float(pbr, "metallicFactor", 1.0);
+                info.roughness = get_float(pbr, "roughnessFactor", 1.0);
+                info.base_color_map = self.get_image_uri(pbr.find("baseColorTexture"));
+            }
+            info.normal_map = self.get_image_uri(mat.find("normalTexture"));
+            materials.push(info);
+        }
+        self.materials = materials;
+    }
+
+    /// Read an accessor, converting all components into floats.
+    /// Normalized integer components are mapped into `[0, 1]` or `[-1, 1]`.
+    /// Returns the number of components per element and the values.
+    fn read_accessor(&self, index: usize) -> Result<(usize, Vec<f32>), Error> {
+        let acc = match get_array(self.json, "accessors").get(index) {
+            Some(a) => a,
+            None => return error(format!("accessor {} is missing", index)),
+        };
+        let count = get_index(acc, "count").unwrap_or(0);
+        let num = match acc.find("type").and_then(|t| t.as_string()) {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            Some("MAT4") => 16,
+            other => return error(format!("accessor {} has unsupported type {:?}", index, other)),
+        };
+        let ctype = get_index(acc, "componentType").unwrap_or(0);
+        let normalized = acc.find("normalized").and_then(|n| n.as_boolean()).unwrap_or(false);
+        let size = match ctype {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            _ => return error(format!("accessor {} has unsupported component type {}", index, ctype)),
+        };
+        let view = match get_index(acc, "bufferView")
+                         .and_then(|i| get_array(self.json, "bufferViews").get(i)) {
+            Some(v) => v,
+            // accessors without a view are filled with zeros
+            None => return Ok((num, vec![0.0; count * num])),
+        };
+        let data = match get_index(view, "buffer").and_then(|i| self.buffers.get(i)) {
+            Some(d) => d,
+            None => return error(format!("accessor {} refers to a missing buffer", index)),
+        };
+        let offset = get_index(view, "byteOffset").unwrap_or(0) +
+                     get_index(acc, "byteOffset").unwrap_or(0);
+        let stride = match get_index(view, "byteStride") {
+            Some(s) if s > 0 => s,
+            _ => size * num,
+        };
+        if count > 0 && offset + (count - 1) * stride + size * num > data.len() {
+            return error(format!("accessor {} is out of the buffer bounds", index))
+        }
+        let mut values = Vec::with_capacity(count * num);
+        for i in 0 .. count {
+            for c in 0 .. num {
+                let b = &data[offset + i * stride + c * size ..];
+                values.push(match (ctype, normalized) {
+                    (5120, false) => b[0] as i8 as f32,
+                    (5120, true) => (b[0] as i8 as f32 / 127.0).max(-1.0),
+                    (5121, false) => b[0] as f32,
+                    (5121, true) => b[0] as f32 / 255.0,
+                    (5122, false) => read_u16(b) as i16 as f32,
+                    (5122, true) => (read_u16(b) as i16 as f32 / 32767.0).max(-1.0),
+                    (5123, false) => read_u16(b) as f32,
+                    (5123, true) => read_u16(b) as f32 / 65535.0,
+                    (5125, _) => read_u32(b) as f32,
+                    _ => unsafe { mem::transmute::<u32, f32>(read_u32(b)) },
+                });
+            }
+        }
+        Ok((num, values))
+    }
+
+    fn read_geometry(&self, mesh_id: usize) -> Result<Geometry, Error> {
+        let mesh = match get_array(self.json, "meshes").get(mesh_id) {
+            Some(m) => m,
+            None => return error(format!("mesh {} is missing", mesh_id)),
+        };
+        let mut geometry = Geometry::new();
+        for prim in get_array(mesh, "primitives").iter() {
+            if prim.find("mode").and_then(|m| m.as_u64()).unwrap_or(MODE_TRIANGLES) != MODE_TRIANGLES {
+                continue // only triangle lists are supported
+            }
+            let attributes = match prim.find("attributes") {
+                Some(a) => a,
+                None => continue,
+            };
+            let positions = match get_index(attributes, "POSITION") {
+                Some(i) => try!(self.read_accessor(i)).1,
+                None => return error(format!("mesh {} has no positions", mesh_id)),
+            };
+            let count = positions.len() / 3;
+            let normals = match get_index(attributes, "NORMAL") {
+                Some(i) => try!(self.read_accessor(i)).1,
+                None => vec![0.0; count * 3],
+            };
+            let tex_coords = match get_index(attributes, "TEXCOORD_0") {
+                Some(i) => try!(self.read_accessor(i)).1,
+                None => vec![0.0; count * 2],
+            };
+            if normals.len() != count * 3 || tex_coords.len() != count * 2 {
+                return error(format!("mesh {} has inconsistent attributes", mesh_id))
+            }
+            let base = geometry.vertices.len() as u32;
+            for i in 0 .. count {
+                geometry.vertices.push(Vertex {
+                    position: [positions[i*3], positions[i*3+1], positions[i*3+2]],
+                    normal: [normals[i*3], normals[i*3+1], normals[i*3+2]],
+                    tex_coord: [tex_coords[i*2], tex_coords[i*2+1]],
+                });
+            }
+            geometry.begin_group(get_index(prim, "material")
+                .and_then(|i| if i < self.materials.len() {Some(i)} else {None}));
+            match get_index(prim, "indices") {
+                Some(i) => {
+                    for v in try!(self.read_accessor(i)).1.into_iter() {
+                        let v = v as u32;
+                        if v as usize >= count {
+                            return error(format!("mesh {} has an index out of range", mesh_id))
+                        }
+                        geometry.push_index(base + v);
+                    }
+                },
+                None => for v in 0 .. count as u32 {
+                    geometry.push_index(base + v);
+                },
+            }
+        }
+        Ok(geometry)
+    }
+
+    fn visit_node<F, M, W, C>(&mut self, index: usize, depth: usize,
+                  parent: Option<&W::NodePtr>, factory: &mut F, world: &mut W,
+                  make_material: &mut C, entities: &mut Vec<Entity<R, M, W>>)
+                  -> Result<(), Error> where
+        F: gfx::Factory<R>,
+        W: WorldBuilder,
+        W::NodePtr: Clone,
+        C: FnMut(&MaterialInfo) -> M,
+    {
+        let json = self.json;
+        let nodes = get_array(json, "nodes");
+        if depth > nodes.len() {
+            return error(format!("node {} is part of a cycle", index))
+        }
+        let node = match nodes.get(index) {
+            Some(n) => n,
+            None => return error(format!("node {} is missing", index)),
+        };
+        let name = match node.find("name").and_then(|n| n.as_string()) {
+            Some(name) => name.to_string(),
+            None => format!("node-{}", index),
+        };
+        let transform = try!(get_transform(node, index));
+        let ptr = world.add_node(&name, parent, transform);
+        if let Some(mesh_id) = get_index(node, "mesh") {
+            if !self.meshes.contains_key(&mesh_id) {
+                let geometry = try!(self.read_geometry(mesh_id));
+                let (mesh, slice) = geometry.upload(factory);
+                self.meshes.insert(mesh_id, (geometry, mesh, slice));
+            }
+            let &(ref geometry, ref mesh, ref slice) = &self.meshes[&mesh_id];
+            entities.push(geometry.make_entity(&name, ptr.clone(), mesh, slice,
+                                               &self.materials, make_material));
+        }
+        for child in get_array(node, "children").iter() {
+            let child = match child.as_u64() {
+                Some(c) => c as usize,
+                None => return error(format!("node {} has an invalid child", index)),
+            };
+            try!(self.visit_node(child, depth + 1, Some(&ptr), factory, world,
+                                 make_material, entities));
+        }
+        Ok(())
+    }
+}
+
+/// Load entities from the contents of a glTF file. Every node with a mesh
+/// becomes an entity, with one fragment per primitive, and the node
+/// hierarchy of the default scene is recreated under the given parent.
+/// External buffers are read with the given function by URI.
+pub fn load_gltf_str<R, F, M, W, B, C>(text: &str, mut read_uri: B, factory: &mut F,
+                     world: &mut W, parent: Option<&W::NodePtr>, mut make_material: C)
+                     -> Result<Vec<Entity<R, M, W>>, Error> where
+    R: gfx::Resources,
+    F: gfx::Factory<R>,
+    W: WorldBuilder,
+    W::NodePtr: Clone,
+    B: FnMut(&str) -> Result<Vec<u8>, Error>,
+    C: FnMut(&MaterialInfo) -> M,
+{
+    let json = try!(Json::from_str(text));
+    let mut buffers = Vec::new();
+    for (i, buf) in get_array(&json, "buffers").iter().enumerate() {
+        let uri = match buf.find("uri").and_then(|u| u.as_string()) {
+            Some(uri) => uri,
+            None => return error(format!("buffer {} has no URI", i)),
+        };
+        let data = if uri.starts_with("data:") {
+            match uri.find(";base64,") {
+                Some(pos) => match uri[pos + 8 ..].from_base64() {
+                    Ok(d) => d,
+                    Err(e) => return error(format!("buffer {} is malformed: {}", i, e)),
+                },
+                None => return error(format!("buffer {} is not base64", i)),
+            }
+        }else {
+            try!(read_uri(uri))
+        };
+        buffers.push(data);
+    }
+    let mut importer = Importer {
+        json: &json,
+        buffers: buffers,
+        materials: Vec::new(),
+        meshes: HashMap::new(),
+    };
+    importer.parse_materials();
+    let scene = match get_index(&json, "scene")
+                      .or(Some(0))
+                      .and_then(|i| get_array(&json, "scenes").get(i)) {
+        Some(s) => s,
+        None => return error("no scene found".to_string()),
+    };
+    let mut entities = Vec::new();
+    for node in get_array(scene, "nodes").iter() {
+        let node = match node.as_u64() {
+            Some(n) => n as usize,
+            None => return error("scene has an invalid node".to_string()),
+        };
+        try!(importer.visit_node(node, 0, parent, factory, world,
+                                 &mut make_material, &mut entities));
+    }
+    Ok(entities)
+}
+
+/// Load entities from a glTF file. External buffers are looked up
+/// relative to the file.
+pub fn load_gltf<R, F, M, W, C>(path: &Path, factory: &mut F, world: &mut W,
+                 parent: Option<&W::NodePtr>, make_material: C)
+                 -> Result<Vec<Entity<R, M, W>>, Error> where
+    R: gfx::Resources,
+    F: gfx::Factory<R>,
+    W: WorldBuilder,
+    W::NodePtr: Clone,
+    C: FnMut(&MaterialInfo) -> M,
+{
+    let mut text = String::new();
+    try!(try!(File::open(path)).read_to_string(&mut text));
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let read_uri = |uri: &str| -> Result<Vec<u8>, Error> {
+        let mut data = Vec::new();
+        try!(try!(File::open(&dir.join(uri))).read_to_end(&mut data));
+        Ok(data)
+    };
+    load_gltf_str(&text, read_uri, factory, world, parent, make_material)
+}
+
+#[cfg(test)]
+mod test {
+    use std::mem;
+    use std::collections::HashMap;
+    use rustc_serialize::base64::{ToBase64, STANDARD};
+    use rustc_serialize::json::Json;
+    use gfx_mock;
+    use super::super::{Error, MaterialInfo};
+    use super::super::test::World;
+    use super::{load_gltf_str, Importer};
+
+    fn push_u32(data: &mut Vec<u8>, v: u32) {
+        data.extend([v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8].iter());
+    }
+
+    /// A triangle with 3 positions and 16-bit indices, in a base64 buffer.
+    fn make_gltf() -> String {
+        let mut data = Vec::new();
+        for &v in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 2.0, 0.0].iter() {
+            push_u32(&mut data, unsafe { mem::transmute::<f32, u32>(v) });
+        }
+        for &i in [0u8, 1, 2].iter() {
+            data.extend([i, 0].iter());
+        }
+        format!(r#"{{
+            "scene": 0,
+            "scenes": [{{"nodes": [0]}}],
+            "nodes": [
+                {{"name": "root", "translation": [0.0, 0.0, 5.0], "children": [1]}},
+                {{"name": "triangle", "mesh": 0}}
+            ],
+            "meshes": [{{"primitives": [{{
+                "attributes": {{"POSITION": 0}},
+                "indices": 1,
+                "material": 0
+            }}]}}],
+            "materials": [{{
+                "name": "gold",
+                "pbrMetallicRoughness": {{
+                    "baseColorFactor": [1.0, 0.8, 0.0, 1.0],
+                    "metallicFactor": 1.0,
+                    "roughnessFactor": 0.25
+                }}
+            }}],
+            "accessors": [
+                {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"}},
+                {{"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"}}
+            ],
+            "bufferViews": [
+                {{"buffer": 0, "byteOffset": 0, "byteLength": 36}},
+                {{"buffer": 0, "byteOffset": 36, "byteLength": 6}}
+            ],
+            "buffers": [{{
+                "byteLength": {},
+                "uri": "data:application/octet-stream;base64,{}"
+            }}]
+        }}"#, data.len(), data.to_base64(STANDARD))
+    }
+
+    fn no_uri(uri: &str) -> Result<Vec<u8>, Error> {
+        panic!("Unexpected external buffer {}", uri)
+    }
+
+    #[test]
+    fn embedded() {
+        let mut factory = gfx_mock::Factory::new();
+        let mut world = World::new();
+        let mut materials = Vec::new();
+        let entities = load_gltf_str(&make_gltf(), no_uri, &mut factory, &mut world, None,
+            |info: &MaterialInfo| { materials.push(info.clone()); info.name.clone() }
+            ).unwrap();
+        assert_eq!(world.nodes.len(), 2);
+        assert_eq!(world.nodes[0].1.disp.z, 5.0);
+        assert_eq!(entities.len(), 1);
+        let ent = &entities[0];
+        assert_eq!(ent.name, "triangle");
+        assert_eq!(ent.node, 1);
+        assert_eq!(ent.mesh.num_vertices, 3);
+        assert_eq!((ent.bound.max.x, ent.bound.max.y), (1.0, 2.0));
+        assert_eq!(ent.fragments.len(), 1);
+        assert_eq!(ent.fragments[0].material, "gold");
+        assert_eq!((ent.fragments[0].slice.start, ent.fragments[0].slice.end), (0, 3));
+        assert_eq!(materials.len(), 1);
+        assert_eq!(materials[0].base_color, [1.0, 0.8, 0.0, 1.0]);
+        assert_eq!(materials[0].roughness, 0.25);
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        let text = make_gltf().replace(r#""count": 3, "type": "SCALAR""#,
+                                       r#""count": 4, "type": "SCALAR""#);
+        let mut factory = gfx_mock::Factory::new();
+        let mut world = World::new();
+        match load_gltf_str(&text, no_uri, &mut factory, &mut world, None,
+                            |info: &MaterialInfo| info.name.clone()) {
+            Err(Error::Gltf(_)) => (),
+            other => panic!("Unexpected result {:?}", other.map(|e| e.len())),
+        }
+    }
+
+    #[test]
+    fn normalized() {
+        let json = Json::from_str(r#"{
+            "accessors": [
+                {"bufferView": 0, "componentType": 5121, "count": 2, "type": "VEC2",
+                 "normalized": true},
+                {"bufferView": 0, "componentType": 5121, "count": 2, "type": "VEC2"},
+                {"bufferView": 0, "byteOffset": 4, "componentType": 5122, "count": 1,
+                 "type": "SCALAR", "normalized": true}
+            ],
+            "bufferViews": [{"buffer": 0, "byteLength": 6}]
+        }"#).unwrap();
+        let importer = Importer::<gfx_mock::Resources> {
+            json: &json,
+            buffers: vec![vec![0, 255, 51, 255, 0x01, 0x80]],
+            materials: Vec::new(),
+            meshes: HashMap::new(),
+        };
+        let (num, values) = importer.read_accessor(0).unwrap();
+        assert_eq!(num, 2);
+        let expected = [0.0, 1.0, 0.2, 1.0];
+        for (v, e) in values.iter().zip(expected.iter()) {
+            assert!((v - e).abs() < 1e-6, "{:?} != {:?}", values, expected);
+        }
+        assert_eq!(importer.read_accessor(1).unwrap().1, vec![0.0, 255.0, 51.0, 255.0]);
+        assert_eq!(importer.read_accessor(2).unwrap().1, vec![-1.0]);
+    }
+
+    #[test]
+    fn matrix_scale() {
+        let node = r#"{"name": "triangle", "mesh": 0}"#;
+        let uniform = make_gltf().replace(node, r#"{"name": "triangle", "mesh": 0, "matrix":
+            [0.0, 2.0, 0.0, 0.0, -2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 2.0, 0.0, 1.0, 0.0, 0.0, 1.0]}"#);
+        let mut factory = gfx_mock::Factory::new();
+        let mut world = World::new();
+        load_gltf_str(&uniform, no_uri, &mut factory, &mut world, None,
+                      |info: &MaterialInfo| info.name.clone()).unwrap();
+        let t = &world.nodes[1].1;
+        assert_eq!((t.scale, t.disp.x), (2.0, 1.0));
+        // rotation by 90 degrees around Z
+        assert!((t.rot.s - 0.5f32.sqrt()).abs() < 1e-6 && (t.rot.v.z - 0.5f32.sqrt()).abs() < 1e-6);
+        let stretched = make_gltf().replace(node, r#"{"name": "triangle", "mesh": 0, "matrix":
+            [1.0, 0.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0]}"#);
+        let mut world = World::new();
+        match load_gltf_str(&stretched, no_uri, &mut factory, &mut world, None,
+                            |info: &MaterialInfo| info.name.clone()) {
+            Err(Error::Gltf(_)) => (),
+            other => panic!("Unexpected result {:?}", other.map(|e| e.len())),
+        }
+    }
+}
@@ -0,0 +1,261 @@
+#![deny(missing_docs)]
+
+//! Scene loaders for gfx_scene. Parse Wavefront OBJ (with MTL) and
+//! glTF 2.0 files into entities, creating the GPU resources through
+//! a given `gfx::Factory`.
+
+#[macro_use]
+extern crate gfx;
+extern crate gfx_scene;
+extern crate cgmath;
+extern crate rustc_serialize;
+#[cfg(test)]
+extern crate gfx_mock;
+
+use std::{fmt, io};
+use rustc_serialize::json;
+
+mod gltf;
+mod obj;
+
+pub use self::gltf::{load_gltf, load_gltf_str};
+pub use self::obj::{load_obj, load_obj_str, parse_mtl};
+pub use self::vertex::Vertex;
+
+#[allow(missing_docs)]
+mod vertex {
+    gfx_vertex!( Vertex {
+        a_Position@ position: [f32; 3],
+        a_Normal@ normal: [f32; 3],
+        a_TexCoord@ tex_coord: [f32; 2],
+    });
+}
+
+/// Node transformation produced by the loaders.
+pub type Transform = cgmath::Decomposed<f32, cgmath::Vector3<f32>, cgmath::Quaternion<f32>>;
+
+/// Bound type of the loaded entities.
+pub type Bound = cgmath::Aabb3<f32>;
+
+/// Loaded entity type.
+pub type Entity<R, M, W> = gfx_scene::Entity<R, M, W, Bound>;
+
+/// A world that loaders can add nodes into.
+pub trait WorldBuilder: gfx_scene::World<Scalar = f32> {
+    /// Add a new named node with a transformation relative to the parent.
+    fn add_node(&mut self, name: &str, parent: Option<&Self::NodePtr>, Transform)
+                -> Self::NodePtr;
+}
+
+/// Loading error.
+#[derive(Debug)]
+pub enum Error {
+    /// Error in reading a file.
+    Io(io::Error),
+    /// Malformed OBJ or MTL line, with the line number.
+    Obj(usize, String),
+    /// Malformed JSON.
+    Json(json::ParserError),
+    /// Invalid glTF contents.
+    Gltf(String),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<json::ParserError> for Error {
+    fn from(e: json::ParserError) -> Error {
+        Error::Json(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "IO error: {}", e),
+            Error::Obj(line, ref msg) => write!(f, "OBJ error at line {}: {}", line, msg),
+            Error::Json(ref e) => write!(f, "JSON error: {}", e),
+            Error::Gltf(ref msg) => write!(f, "glTF error: {}", msg),
+        }
+    }
+}
+
+/// Material properties shared by the supported formats, to be converted
+/// into the user material type.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaterialInfo {
+    /// Name of the material.
+    pub name: String,
+    /// Base (diffuse) color with alpha.
+    pub base_color: [f32; 4],
+    /// Specular color.
+    pub specular: [f32; 3],
+    /// Specular exponent.
+    pub shininess: f32,
+    /// Metallic factor.
+    pub metallic: f32,
+    /// Roughness factor.
+    pub roughness: f32,
+    /// Path of the base color texture.
+    pub base_color_map: Option<String>,
+    /// Path of the normal map.
+    pub normal_map: Option<String>,
+}
+
+impl MaterialInfo {
+    /// Create a new material with default properties.
+    pub fn new(name: &str) -> MaterialInfo {
+        MaterialInfo {
+            name: name.to_string(),
+            base_color: [1.0; 4],
+            specular: [0.0; 3],
+            shininess: 1.0,
+            metallic: 0.0,
+            roughness: 1.0,
+            base_color_map: None,
+            normal_map: None,
+        }
+    }
+}
+
+/// Geometry of a single entity, accumulated by a loader.
+struct Geometry {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+    /// Material index with the start and end of the index range.
+    groups: Vec<(Option<usize>, u32, u32)>,
+}
+
+impl Geometry {
+    fn new() -> Geometry {
+        Geometry {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            groups: Vec::new(),
+        }
+    }
+
+    /// Start a new material group, dropping the previous one if it's empty.
+    fn begin_group(&mut self, material: Option<usize>) {
+        let start = self.indices.len() as u32;
+        if let Some(&(_, s, _)) = self.groups.last() {
+            if s == start {
+                self.groups.pop();
+            }
+        }
+        self.groups.push((material, start, start));
+    }
+
+    fn push_index(&mut self, index: u32) {
+        if self.groups.is_empty() {
+            self.groups.push((None, 0, 0));
+        }
+        self.indices.push(index);
+        self.groups.last_mut().unwrap().2 += 1;
+    }
+
+    fn compute_bound<I: Iterator<Item = u32>>(&self, indices: I) -> Bound {
+        use cgmath::{Aabb, Point3};
+        let mut bound: Option<Bound> = None;
+        for i in indices {
+            let p = self.vertices[i as usize].position;
+            let p = Point3::new(p[0], p[1], p[2]);
+            bound = Some(match bound {
+                Some(b) => b.grow(&p),
+                None => cgmath::Aabb3::new(p, p),
+            });
+        }
+        bound.unwrap_or(cgmath::Aabb3::new(Point3::new(0.0, 0.0, 0.0),
+                                           Point3::new(0.0, 0.0, 0.0)))
+    }
+
+    /// Create the GPU resources: a mesh and the full index slice.
+    fn upload<R, F>(&self, factory: &mut F) -> (gfx::Mesh<R>, gfx::Slice<R>) where
+        R: gfx::Resources,
+        F: gfx::Factory<R>,
+    {
+        use gfx::traits::ToSlice;
+        let mesh = factory.create_mesh(&self.vertices);
+        let slice = self.indices[..].to_slice(factory, gfx::PrimitiveType::TriangleList);
+        (mesh, slice)
+    }
+
+    /// Make an entity out of uploaded resources, with one fragment
+    /// per material group.
+    fn make_entity<R, M, W, C>(&self, name: &str, node: W::NodePtr,
+                               mesh: &gfx::Mesh<R>, slice: &gfx::Slice<R>,
+                               materials: &[MaterialInfo], make_material: &mut C)
+                               -> Entity<R, M, W> where
+        R: gfx::Resources,
+        W: WorldBuilder,
+        C: FnMut(&MaterialInfo) -> M,
+    {
+        let bound = self.compute_bound(self.indices.iter().map(|&i| i));
+        let mut ent = gfx_scene::Entity::new(mesh.clone(), node, bound);
+        ent.name = name.to_string();
+        let default = MaterialInfo::new("");
+        for &(mat_id, start, end) in self.groups.iter() {
+            if start == end {
+                continue
+            }
+            let info = match mat_id {
+                Some(id) => &materials[id],
+                None => &default,
+            };
+            let mut frag_slice = slice.clone();
+            frag_slice.start = start;
+            frag_slice.end = end;
+            let mut frag = gfx_scene::Fragment::new(make_material(info), frag_slice);
+            frag.bound = Some(self.compute_bound(
+                self.indices[start as usize .. end as usize].iter().map(|&i| i)));
+            ent.fragments.push(frag);
+        }
+        ent
+    }
+}
+
+fn identity() -> Transform {
+    cgmath::Decomposed {
+        scale: 1.0,
+        rot: cgmath::Quaternion::identity(),
+        disp: cgmath::Vector3::new(0.0, 0.0, 0.0),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use gfx_scene;
+    use super::{Transform, WorldBuilder};
+
+    /// Flat world of named nodes, with parents ignored.
+    pub struct World {
+        pub nodes: Vec<(String, Transform)>,
+    }
+
+    impl World {
+        pub fn new() -> World {
+            World { nodes: Vec::new() }
+        }
+    }
+
+    impl gfx_scene::World for World {
+        type Scalar = f32;
+        type Transform = Transform;
+        type NodePtr = usize;
+        type SkeletonPtr = ();
+        fn get_transform(&self, node: &usize) -> Transform {
+            self.nodes[*node].1
+        }
+    }
+
+    impl WorldBuilder for World {
+        fn add_node(&mut self, name: &str, _: Option<&usize>, t: Transform) -> usize {
+            self.nodes.push((name.to_string(), t));
+            self.nodes.len() - 1
+        }
+    }
+}
@@ -0,0 +1,395 @@
+//! Wavefront OBJ and MTL loading.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use gfx;
+use super::{Entity, Error, Geometry, MaterialInfo, Vertex, WorldBuilder};
+
+fn read_file(path: &Path) -> Result<String, Error> {
+    let mut text = String::new();
+    let mut file = try!(File::open(path));
+    try!(file.read_to_string(&mut text));
+    Ok(text)
+}
+
+/// Parse exactly as many numbers as the output has, ignoring the extra words.
+fn parse_floats<'a, I: Iterator<Item = &'a str>>(words: I, line: usize, out: &mut [f32])
+                -> Result<(), Error> {
+    let mut count = 0;
+    for (w, o) in words.zip(out.iter_mut()) {
+        *o = match f32::from_str(w) {
+            Ok(v) => v,
+            Err(_) => return Err(Error::Obj(line, format!("invalid number `{}`", w))),
+        };
+        count += 1;
+    }
+    if count < out.len() {
+        return Err(Error::Obj(line, format!("expected {} numbers, got {}", out.len(), count)))
+    }
+    Ok(())
+}
+
+fn split_words<'a>(line: &'a str) -> Vec<&'a str> {
+    line.split(|c: char| c.is_whitespace())
+        .filter(|w| !w.is_empty())
+        .collect()
+}
+
+/// Parse the contents of an MTL file.
+pub fn parse_mtl(text: &str) -> Result<Vec<MaterialInfo>, Error> {
+    let mut materials: Vec<MaterialInfo> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let words = split_words(line);
+        if words.is_empty() || words[0].starts_with("#") {
+            continue
+        }
+        if words[0] == "newmtl" {
+            let name = words[1..].connect(" ");
+            materials.push(MaterialInfo::new(&name));
+            continue
+        }
+        let mat = match materials.last_mut() {
+            Some(m) => m,
+            None => return Err(Error::Obj(i+1, "property outside of a material".to_string())),
+        };
+        let args = words[1..].iter().map(|w| *w);
+        match words[0] {
+            "Kd" => { try!(parse_floats(args, i+1, &mut mat.base_color[..3])); },
+            "Ks" => { try!(parse_floats(args, i+1, &mut mat.specular)); },
+            "Ns" => {
+                let mut v = [0.0];
+                try!(parse_floats(args, i+1, &mut v));
+                mat.shininess = v[0];
+            },
+            "d" => {
+                let mut v = [0.0];
+                try!(parse_floats(args, i+1, &mut v));
+                mat.base_color[3] = v[0];
+            },
+            "Tr" => {
+                let mut v = [0.0];
+                try!(parse_floats(args, i+1, &mut v));
+                mat.base_color[3] = 1.0 - v[0];
+            },
+            "map_Kd" => mat.base_color_map = words.last().map(|w| w.to_string()),
+            "map_Bump" | "map_bump" | "bump" | "norm" =>
+                mat.normal_map = words.last().map(|w| w.to_string()),
+            _ => (), // unsupported properties are ignored
+        }
+    }
+    Ok(materials)
+}
+
+/// Resolve a 1-based or negative OBJ index into a 0-based one.
+fn resolve_index(word: &str, count: usize, line: usize) -> Result<usize, Error> {
+    let i = match isize::from_str(word) {
+        Ok(i) => i,
+        Err(_) => return Err(Error::Obj(line, format!("invalid index `{}`", word))),
+    };
+    let index = if i < 0 {count as isize + i} else {i - 1};
+    if index < 0 || index as usize >= count {
+        Err(Error::Obj(line, format!("index {} out of range", i)))
+    }else {
+        Ok(index as usize)
+    }
+}
+
+struct ObjState<'a> {
+    materials: &'a [MaterialInfo],
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    tex_coords: Vec<[f32; 2]>,
+    objects: Vec<(String, Geometry)>,
+    vertex_map: HashMap<(usize, Option<usize>, Option<usize>), u32>,
+}
+
+impl<'a> ObjState<'a> {
+    fn begin_object(&mut self, name: String) {
+        let material = self.objects.last().and_then(|&(_, ref g)|
+            g.groups.last().and_then(|&(m, _, _)| m));
+        self.objects.push((name, Geometry::new()));
+        self.objects.last_mut().unwrap().1.begin_group(material);
+        self.vertex_map.clear();
+    }
+
+    fn add_vertex(&mut self, word: &str, line: usize) -> Result<u32, Error> {
+        let mut parts = word.split('/');
+        let v = try!(resolve_index(parts.next().unwrap_or(""), self.positions.len(), line));
+        let t = match parts.next() {
+            Some(w) if !w.is_empty() =>
+                Some(try!(resolve_index(w, self.tex_coords.len(), line))),
+            _ => None,
+        };
+        let n = match parts.next() {
+            Some(w) if !w.is_empty() =>
+                Some(try!(resolve_index(w, self.normals.len(), line))),
+            _ => None,
+        };
+        let key = (v, t, n);
+        if let Some(&index) = self.vertex_map.get(&key) {
+            return Ok(index)
+        }
+        let vertex = Vertex {
+            position: self.positions[v],
+            normal: match n {
+                Some(n) => self.normals[n],
+                None => [0.0; 3],
+            },
+            tex_coord: match t {
+                Some(t) => self.tex_coords[t],
+                None => [0.0; 2],
+            },
+        };
+        let geometry = &mut self.objects.last_mut().unwrap().1;
+        let index = geometry.vertices.len() as u32;
+        geometry.vertices.push(vertex);
+        self.vertex_map.insert(key, index);
+        Ok(index)
+    }
+}
+
+/// Load entities from the contents of an OBJ file, given the list of
+/// materials it refers to. Every object becomes an entity with a node
+/// under the given parent, and every material group becomes a fragment.
+pub fn load_obj_str<R, F, M, W, C>(text: &str, materials: &[MaterialInfo],
+                    factory: &mut F, world: &mut W, parent: Option<&W::NodePtr>,
+                    mut make_material: C) -> Result<Vec<Entity<R, M, W>>, Error> where
+    R: gfx::Resources,
+    F: gfx::Factory<R>,
+    W: WorldBuilder,
+    C: FnMut(&MaterialInfo) -> M,
+{
+    let mut state = ObjState {
+        materials: materials,
+        positions: Vec::new(),
+        normals: Vec::new(),
+        tex_coords: Vec::new(),
+        objects: Vec::new(),
+        vertex_map: HashMap::new(),
+    };
+    state.begin_object(String::new());
+    for (i, line) in text.lines().enumerate() {
+        let words = split_words(line);
+        if words.is_empty() || words[0].starts_with("#") {
+            continue
+        }
+        let args = words[1..].iter().map(|w| *w);
+        match words[0] {
+            "v" => {
+                let mut v = [0.0; 3];
+                try!(parse_floats(args, i+1, &mut v));
+                state.positions.push(v);
+            },
+            "vn" => {
+                let mut v = [0.0; 3];
+                try!(parse_floats(args, i+1, &mut v));
+                state.normals.push(v);
+            },
+            "vt" => {
+                let mut v = [0.0; 2];
+                try!(parse_floats(args, i+1, &mut v));
+                state.tex_coords.push(v);
+            },
+            "f" => {
+                if words.len() < 4 {
+                    return Err(Error::Obj(i+1, "face with less than 3 vertices".to_string()))
+                }
+                let mut face = Vec::with_capacity(words.len() - 1);
+                for w in words[1..].iter() {
+                    face.push(try!(state.add_vertex(w, i+1)));
+                }
+                // triangulate as a fan
+                let geometry = &mut state.objects.last_mut().unwrap().1;
+                for k in 1 .. face.len() - 1 {
+                    geometry.push_index(face[0]);
+                    geometry.push_index(face[k]);
+                    geometry.push_index(face[k+1]);
+                }
+            },
+            "o" => state.begin_object(words[1..].connect(" ")),
+            "usemtl" => {
+                let name = words[1..].connect(" ");
+                let id = match state.materials.iter().position(|m| m.name == name) {
+                    Some(id) => id,
+                    None => return Err(Error::Obj(i+1, format!("unknown material `{}`", name))),
+                };
+                state.objects.last_mut().unwrap().1.begin_group(Some(id));
+            },
+            _ => (), // groups, smoothing, and libraries are not needed here
+        }
+    }
+    let mut entities = Vec::new();
+    for &(ref name, ref geometry) in state.objects.iter() {
+        if geometry.indices.is_empty() {
+            continue
+        }
+        let node = world.add_node(name, parent, super::identity());
+        let (mesh, slice) = geometry.upload(factory);
+        entities.push(geometry.make_entity(name, node, &mesh, &slice,
+                                           materials, &mut make_material));
+    }
+    Ok(entities)
+}
+
+/// Load entities from an OBJ file, together with the MTL libraries
+/// it refers to, which are looked up relative to the OBJ file.
+pub fn load_obj<R, F, M, W, C>(path: &Path, factory: &mut F, world: &mut W,
+                parent: Option<&W::NodePtr>, make_material: C)
+                -> Result<Vec<Entity<R, M, W>>, Error> where
+    R: gfx::Resources,
+    F: gfx::Factory<R>,
+    W: WorldBuilder,
+    C: FnMut(&MaterialInfo) -> M,
+{
+    let text = try!(read_file(path));
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut materials = Vec::new();
+    for line in text.lines() {
+        let words = split_words(line);
+        if words.len() > 1 && words[0] == "mtllib" {
+            for lib in words[1..].iter() {
+                let mtl = try!(read_file(&dir.join(lib)));
+                materials.extend(try!(parse_mtl(&mtl)).into_iter());
+            }
+        }
+    }
+    load_obj_str(&text, &materials, factory, world, parent, make_material)
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::io::Write;
+    use gfx_mock;
+    use super::super::{Error, MaterialInfo};
+    use super::super::test::World;
+    use super::{load_obj, load_obj_str, parse_mtl, resolve_index};
+
+    static MTL: &'static str = "
+        # two materials
+        newmtl red
+        Kd 1.0 0.0 0.0
+        d 0.5
+        map_Kd red.png
+        newmtl blue
+        Kd 0.0 0.0 1.0
+        Ns 20
+    ";
+
+    static OBJ: &'static str = "
+        v 0 0 0
+        v 1 0 0
+        v 1 1 0
+        v 0 1 0
+        vt 0 0
+        vn 0 0 1
+        o plane
+        usemtl red
+        f 1/1/1 2/1/1 3/1/1 4/1/1
+        usemtl blue
+        f -4 -2 -1
+    ";
+
+    fn get_name(info: &MaterialInfo) -> String {
+        info.name.clone()
+    }
+
+    #[test]
+    fn mtl() {
+        let materials = parse_mtl(MTL).unwrap();
+        assert_eq!(materials.len(), 2);
+        assert_eq!(materials[0].name, "red");
+        assert_eq!(materials[0].base_color, [1.0, 0.0, 0.0, 0.5]);
+        assert_eq!(materials[0].base_color_map, Some("red.png".to_string()));
+        assert_eq!(materials[1].base_color, [0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(materials[1].shininess, 20.0);
+        match parse_mtl("Kd 1 1 1") {
+            Err(Error::Obj(1, _)) => (),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn indices() {
+        assert_eq!(resolve_index("1", 4, 1).unwrap(), 0);
+        assert_eq!(resolve_index("4", 4, 1).unwrap(), 3);
+        assert_eq!(resolve_index("-1", 4, 1).unwrap(), 3);
+        assert_eq!(resolve_index("-4", 4, 1).unwrap(), 0);
+        assert!(resolve_index("0", 4, 1).is_err());
+        assert!(resolve_index("5", 4, 1).is_err());
+        assert!(resolve_index("-5", 4, 1).is_err());
+        assert!(resolve_index("x", 4, 1).is_err());
+    }
+
+    #[test]
+    fn faces() {
+        let materials = parse_mtl(MTL).unwrap();
+        let mut factory = gfx_mock::Factory::new();
+        let mut world = World::new();
+        let entities = load_obj_str(OBJ, &materials, &mut factory, &mut world,
+                                    None, get_name).unwrap();
+        assert_eq!(entities.len(), 1);
+        let ent = &entities[0];
+        assert_eq!(ent.name, "plane");
+        assert_eq!(world.nodes[ent.node].0, "plane");
+        // the quad references new vertices with a texture coordinate and
+        // a normal, while the triangle adds plain positions
+        assert_eq!(ent.mesh.num_vertices, 7);
+        assert_eq!(ent.fragments.len(), 2);
+        // the quad is triangulated as a fan
+        assert_eq!(ent.fragments[0].material, "red");
+        assert_eq!((ent.fragments[0].slice.start, ent.fragments[0].slice.end), (0, 6));
+        // negative indices resolve to the 1st, 3rd, and 4th positions
+        assert_eq!(ent.fragments[1].material, "blue");
+        assert_eq!((ent.fragments[1].slice.start, ent.fragments[1].slice.end), (6, 9));
+        let bound = ent.fragments[1].bound.as_ref().unwrap();
+        assert_eq!((bound.min.x, bound.min.y, bound.max.x, bound.max.y), (0.0, 0.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn errors() {
+        let mut factory = gfx_mock::Factory::new();
+        let mut world = World::new();
+        let bad_face = "v 0 0 0\nf 1 2 3";
+        match load_obj_str(bad_face, &[], &mut factory, &mut world, None, get_name) {
+            Err(Error::Obj(2, _)) => (),
+            other => panic!("Unexpected result {:?}", other.map(|e| e.len())),
+        }
+        let unknown = "v 0 0 0\nusemtl gold\nf 1 1 1";
+        match load_obj_str(unknown, &[], &mut factory, &mut world, None, get_name) {
+            Err(Error::Obj(2, _)) => (),
+            other => panic!("Unexpected result {:?}", other.map(|e| e.len())),
+        }
+        let short_position = "v 0 0 0\nv 1 0\nf 1 1 1";
+        match load_obj_str(short_position, &[], &mut factory, &mut world, None, get_name) {
+            Err(Error::Obj(2, _)) => (),
+            other => panic!("Unexpected result {:?}", other.map(|e| e.len())),
+        }
+        let short_tex_coord = "v 0 0 0\nvt 0.5\nf 1/1 1/1 1/1";
+        match load_obj_str(short_tex_coord, &[], &mut factory, &mut world, None, get_name) {
+            Err(Error::Obj(2, _)) => (),
+            other => panic!("Unexpected result {:?}", other.map(|e| e.len())),
+        }
+    }
+
+    #[test]
+    fn missing_mtl() {
+        let path = ::std::env::temp_dir().join("gfx_scene_load_missing_mtl.obj");
+        {
+            let mut file = File::create(&path).unwrap();
+            file.write_all(b"mtllib gfx_scene_load_missing.mtl\nv 0 0 0\nf 1 1 1\n").unwrap();
+        }
+        let mut factory = gfx_mock::Factory::new();
+        let mut world = World::new();
+        let result = load_obj(&path, &mut factory, &mut world, None, get_name);
+        match result {
+            Err(Error::Io(_)) => (),
+            other => panic!("Unexpected result {:?}", other.map(|e| e.len())),
+        }
+        let _ = ::std::fs::remove_file(&path);
+    }
+}
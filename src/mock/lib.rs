@@ -0,0 +1,480 @@
+#![deny(missing_docs)]
+
+//! Headless gfx backend for testing. Provides mock resources, a fake
+//! factory, and a stream that records every draw call instead of
+//! sending it to the GPU, so that phases and scenes can be checked
+//! against golden draw lists on a machine without a GPU.
+
+#[cfg_attr(test, macro_use)]
+extern crate gfx;
+#[cfg(test)]
+extern crate gfx_phase;
+
+use gfx::device as d;
+use gfx::device::draw::{DataPointer, InstanceOption};
+use gfx::device::handle;
+
+/// Raw name of a mock resource.
+pub type Name = u32;
+
+/// Mock resource types.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Resources {}
+
+impl gfx::Resources for Resources {
+    type Buffer = Name;
+    type ArrayBuffer = Name;
+    type Shader = Name;
+    type Program = Name;
+    type FrameBuffer = Name;
+    type Surface = Name;
+    type Texture = Name;
+    type Sampler = Name;
+}
+
+/// Command buffer that ignores everything.
+pub struct CommandBuffer;
+
+#[allow(unused_variables)]
+impl d::draw::CommandBuffer<Resources> for CommandBuffer {
+    fn new() -> CommandBuffer { CommandBuffer }
+    fn clear(&mut self) {}
+    fn bind_program(&mut self, _: Name) {}
+    fn bind_array_buffer(&mut self, _: Name) {}
+    fn bind_attribute(&mut self, _: d::AttributeSlot, _: Name, _: d::attrib::Format) {}
+    fn bind_index(&mut self, _: Name) {}
+    fn bind_frame_buffer(&mut self, _: d::target::Access, _: Name, _: d::Gamma) {}
+    fn unbind_target(&mut self, _: d::target::Access, _: d::target::Target) {}
+    fn bind_target_surface(&mut self, _: d::target::Access, _: d::target::Target, _: Name) {}
+    fn bind_target_texture(&mut self, _: d::target::Access, _: d::target::Target, _: Name,
+                           _: d::target::Level, _: Option<d::target::Layer>) {}
+    fn bind_uniform_block(&mut self, _: Name, _: d::UniformBufferSlot,
+                          _: d::UniformBlockIndex, _: Name) {}
+    fn bind_uniform(&mut self, _: d::shade::Location, _: d::shade::UniformValue) {}
+    fn bind_texture(&mut self, _: d::TextureSlot, _: d::tex::TextureKind, _: Name,
+                    _: Option<(Name, d::tex::SamplerInfo)>) {}
+    fn set_draw_color_buffers(&mut self, _: usize) {}
+    fn set_primitive(&mut self, _: d::state::Primitive) {}
+    fn set_viewport(&mut self, _: d::target::Rect) {}
+    fn set_multi_sample(&mut self, _: Option<d::state::MultiSample>) {}
+    fn set_scissor(&mut self, _: Option<d::target::Rect>) {}
+    fn set_depth_stencil(&mut self, _: Option<d::state::Depth>,
+                         _: Option<d::state::Stencil>, _: d::state::CullFace) {}
+    fn set_blend(&mut self, _: Option<d::state::Blend>) {}
+    fn set_color_mask(&mut self, _: d::state::ColorMask) {}
+    fn update_buffer(&mut self, _: Name, _: DataPointer, _: usize) {}
+    fn update_texture(&mut self, _: d::tex::TextureKind, _: Name,
+                      _: d::tex::ImageInfo, _: DataPointer) {}
+    fn call_clear(&mut self, _: d::target::ClearData, _: d::target::Mask) {}
+    fn call_draw(&mut self, _: d::PrimitiveType, _: d::VertexCount,
+                 _: d::VertexCount, _: InstanceOption) {}
+    fn call_draw_indexed(&mut self, _: d::PrimitiveType, _: d::IndexType,
+                         _: d::VertexCount, _: d::VertexCount,
+                         _: d::VertexCount, _: InstanceOption) {}
+    fn call_blit(&mut self, _: d::target::Rect, _: d::target::Rect,
+                 _: d::target::Mirror, _: d::target::Mask) {}
+}
+
+/// Buffer mapping that is not supported by the mock backend.
+#[derive(Clone)]
+pub struct Mapper;
+
+impl d::mapping::RawMapping for Mapper {
+    unsafe fn set<T>(&self, _: usize, _: T) {
+        panic!("Buffer mapping is not supported by the mock backend")
+    }
+    unsafe fn to_slice<T>(&self, _: usize) -> &[T] {
+        panic!("Buffer mapping is not supported by the mock backend")
+    }
+    unsafe fn to_mut_slice<T>(&self, _: usize) -> &mut [T] {
+        panic!("Buffer mapping is not supported by the mock backend")
+    }
+}
+
+/// Fake factory that hands out unique resource names without
+/// touching any GPU.
+pub struct Factory {
+    capabilities: d::Capabilities,
+    handles: handle::Manager<Resources>,
+    next_name: Name,
+    /// Program information assigned to newly created programs. Batch
+    /// linking is checked against it, so tests should describe here
+    /// the attributes and parameters their shaders would have.
+    pub program_info: d::shade::ProgramInfo,
+}
+
+impl Factory {
+    /// Create a new fake factory.
+    pub fn new() -> Factory {
+        Factory {
+            capabilities: d::Capabilities {
+                shader_model: d::shade::ShaderModel::Version40,
+                max_vertex_count: 0x10000,
+                max_index_count: 0x10000,
+                max_draw_buffers: 8,
+                max_texture_size: 0x1000,
+                max_vertex_attributes: 16,
+                array_buffer_supported: true,
+                fragment_output_supported: true,
+                immutable_storage_supported: true,
+                instance_base_supported: true,
+                instance_call_supported: true,
+                instance_rate_supported: true,
+                render_targets_supported: true,
+                sampler_objects_supported: true,
+                srgb_color_supported: true,
+                uniform_block_supported: true,
+                vertex_base_supported: true,
+            },
+            handles: handle::Manager::new(),
+            next_name: 1,
+            program_info: d::shade::ProgramInfo {
+                attributes: Vec::new(),
+                uniforms: Vec::new(),
+                blocks: Vec::new(),
+                textures: Vec::new(),
+            },
+        }
+    }
+
+    fn gen_name(&mut self) -> Name {
+        let name = self.next_name;
+        self.next_name += 1;
+        name
+    }
+}
+
+impl d::Factory<Resources> for Factory {
+    type Mapper = Mapper;
+
+    fn get_capabilities(&self) -> &d::Capabilities {
+        &self.capabilities
+    }
+
+    fn create_buffer_raw(&mut self, size: usize, usage: d::BufferUsage)
+                         -> handle::RawBuffer<Resources> {
+        let name = self.gen_name();
+        self.handles.make_buffer(name, d::BufferInfo {
+            role: d::BufferRole::Vertex,
+            usage: usage,
+            size: size,
+        })
+    }
+
+    fn create_buffer_static_raw(&mut self, data: &[u8], role: d::BufferRole)
+                                -> handle::RawBuffer<Resources> {
+        let name = self.gen_name();
+        self.handles.make_buffer(name, d::BufferInfo {
+            role: role,
+            usage: d::BufferUsage::Static,
+            size: data.len(),
+        })
+    }
+
+    fn create_array_buffer(&mut self) -> Result<handle::ArrayBuffer<Resources>, ()> {
+        let name = self.gen_name();
+        Ok(self.handles.make_array_buffer(name))
+    }
+
+    fn create_shader(&mut self, stage: d::shade::Stage, _: &[u8])
+                     -> Result<handle::Shader<Resources>, d::shade::CreateShaderError> {
+        let name = self.gen_name();
+        Ok(self.handles.make_shader(name, stage))
+    }
+
+    fn create_program(&mut self, _: &[handle::Shader<Resources>], _: Option<&[&str]>)
+                      -> Result<handle::Program<Resources>, ()> {
+        let name = self.gen_name();
+        let info = self.program_info.clone();
+        Ok(self.handles.make_program(name, info))
+    }
+
+    fn create_frame_buffer(&mut self) -> Result<handle::FrameBuffer<Resources>, ()> {
+        let name = self.gen_name();
+        Ok(self.handles.make_frame_buffer(name))
+    }
+
+    fn create_surface(&mut self, info: d::tex::SurfaceInfo)
+                      -> Result<handle::Surface<Resources>, d::tex::SurfaceError> {
+        let name = self.gen_name();
+        Ok(self.handles.make_surface(name, info))
+    }
+
+    fn create_texture(&mut self, info: d::tex::TextureInfo)
+                      -> Result<handle::Texture<Resources>, d::tex::TextureError> {
+        let name = self.gen_name();
+        Ok(self.handles.make_texture(name, info))
+    }
+
+    fn create_sampler(&mut self, info: d::tex::SamplerInfo) -> handle::Sampler<Resources> {
+        let name = self.gen_name();
+        self.handles.make_sampler(name, info)
+    }
+
+    fn update_buffer_raw(&mut self, _: &handle::RawBuffer<Resources>, _: &[u8], _: usize) {}
+
+    fn update_texture_raw(&mut self, _: &handle::Texture<Resources>, _: &d::tex::ImageInfo,
+                          _: &[u8], _: Option<d::tex::TextureKind>)
+                          -> Result<(), d::tex::TextureError> {
+        Ok(())
+    }
+
+    fn generate_mipmap(&mut self, _: &handle::Texture<Resources>) {}
+
+    fn map_buffer_raw(&mut self, _: &handle::RawBuffer<Resources>, _: d::MapAccess) -> Mapper {
+        Mapper
+    }
+
+    fn unmap_buffer_raw(&mut self, _: Mapper) {}
+
+    fn map_buffer_readable<T: Copy>(&mut self, _: &handle::Buffer<Resources, T>)
+                           -> d::mapping::Readable<T, Resources, Factory> {
+        panic!("Buffer mapping is not supported by the mock backend")
+    }
+
+    fn map_buffer_writable<T: Copy>(&mut self, _: &handle::Buffer<Resources, T>)
+                           -> d::mapping::Writable<T, Resources, Factory> {
+        panic!("Buffer mapping is not supported by the mock backend")
+    }
+
+    fn map_buffer_rw<T: Copy>(&mut self, _: &handle::Buffer<Resources, T>)
+                     -> d::mapping::RW<T, Resources, Factory> {
+        panic!("Buffer mapping is not supported by the mock backend")
+    }
+}
+
+/// Output of a fixed size, standing for the main frame buffer.
+pub struct Output {
+    /// Width and height of the output.
+    pub size: (u16, u16),
+}
+
+impl gfx::Output<Resources> for Output {
+    fn get_handle(&self) -> Option<&handle::FrameBuffer<Resources>> {
+        None
+    }
+
+    fn get_size(&self) -> (u16, u16) {
+        self.size
+    }
+
+    fn get_mask(&self) -> gfx::Mask {
+        gfx::COLOR | gfx::DEPTH | gfx::STENCIL
+    }
+}
+
+/// A single draw call captured by the `Stream`.
+#[derive(Clone, Debug)]
+pub struct DrawCall {
+    /// Shader program.
+    pub program: handle::Program<Resources>,
+    /// Mesh, including the instancing attributes.
+    pub mesh: gfx::Mesh<Resources>,
+    /// Mesh slice.
+    pub slice: gfx::Slice<Resources>,
+    /// Draw state.
+    pub state: gfx::DrawState,
+    /// Parameter values, in the order of the program uniforms.
+    pub uniforms: Vec<d::shade::UniformValue>,
+    /// Uniform buffers, in the order of the program blocks.
+    pub blocks: Vec<handle::RawBuffer<Resources>>,
+    /// Textures, in the order of the program textures.
+    pub textures: Vec<d::shade::TextureParam<Resources>>,
+}
+
+/// A recorded stream command.
+#[derive(Clone, Debug)]
+pub enum Command {
+    /// Clear of the output.
+    Clear(gfx::ClearData),
+    /// Draw call.
+    Draw(DrawCall),
+}
+
+/// A stream that records the commands instead of executing them.
+pub struct Stream {
+    renderer: gfx::Renderer<Resources, CommandBuffer>,
+    output: Output,
+    /// Recorded commands, in the submission order.
+    pub commands: Vec<Command>,
+}
+
+impl Stream {
+    /// Create a new recording stream with an output of the given size.
+    pub fn new(factory: &mut Factory, width: u16, height: u16) -> Stream {
+        use gfx::traits::*;
+        Stream {
+            renderer: factory.create_renderer(),
+            output: Output { size: (width, height) },
+            commands: Vec::new(),
+        }
+    }
+
+    /// Iterate over the recorded draw calls, skipping other commands.
+    pub fn draws<'a>(&'a self) -> Box<Iterator<Item = &'a DrawCall> + 'a> {
+        Box::new(self.commands.iter().filter_map(|c| match *c {
+            Command::Draw(ref call) => Some(call),
+            Command::Clear(_) => None,
+        }))
+    }
+
+    /// Take the recorded commands, leaving the list empty.
+    pub fn take(&mut self) -> Vec<Command> {
+        std::mem::replace(&mut self.commands, Vec::new())
+    }
+}
+
+impl gfx::Stream<Resources> for Stream {
+    type CommandBuffer = CommandBuffer;
+    type Output = Output;
+
+    fn get_output(&self) -> &Output {
+        &self.output
+    }
+
+    fn access(&mut self) -> (&mut gfx::Renderer<Resources, CommandBuffer>, &Output) {
+        (&mut self.renderer, &self.output)
+    }
+
+    fn clear(&mut self, data: gfx::ClearData) {
+        self.commands.push(Command::Clear(data));
+    }
+
+    fn draw<B: gfx::Batch<Resources = Resources>>(&mut self, batch: &B)
+            -> Result<(), gfx::DrawError<B::Error>> {
+        let (mesh, _, slice, state) = match batch.get_data() {
+            Ok(data) => data,
+            Err(e) => return Err(gfx::DrawError::InvalidBatch(e)),
+        };
+        let mut storage = gfx::ParamStorage::new();
+        let program = match batch.fill_params(&mut storage) {
+            Ok(p) => p.clone(),
+            Err(e) => return Err(gfx::DrawError::InvalidBatch(e)),
+        };
+        self.commands.push(Command::Draw(DrawCall {
+            program: program,
+            mesh: mesh.clone(),
+            slice: slice.clone(),
+            state: *state,
+            uniforms: storage.uniforms,
+            blocks: storage.blocks,
+            textures: storage.textures,
+        }));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::marker::PhantomData;
+    use gfx;
+    use gfx::traits::*;
+    use gfx::device::shade;
+    use gfx_phase;
+    use gfx_phase::AbstractPhase;
+    use super::{Command, Factory, Stream};
+
+    #[allow(missing_docs)]
+    mod params {
+        gfx_parameters!( Params {
+            u_Color@ color: [f32; 4],
+        });
+    }
+    use self::params::Params;
+
+    struct Material(Option<[f32; 4]>);
+    impl gfx_phase::Material for Material {}
+
+    #[derive(Clone, Copy)]
+    struct ViewInfo(f32);
+    impl gfx_phase::ToDepth for ViewInfo {
+        type Depth = f32;
+        fn to_depth(&self) -> f32 { self.0 }
+    }
+
+    struct Technique {
+        program: gfx::handle::Program<super::Resources>,
+        state: gfx::DrawState,
+    }
+
+    impl gfx_phase::Technique<super::Resources, Material, ViewInfo> for Technique {
+        type Kernel = ();
+        type Params = Params<super::Resources>;
+        fn test(&self, _: &gfx::Mesh<super::Resources>, mat: &Material) -> Option<()> {
+            mat.0.map(|_| ())
+        }
+        fn compile<'a>(&'a self, _: (), _: &ViewInfo)
+                       -> gfx_phase::TechResult<'a, super::Resources, Params<super::Resources>> {
+            (&self.program, Params { color: [0.0; 4], _r: PhantomData }, None, &self.state)
+        }
+        fn fix_params(&self, mat: &Material, _: &ViewInfo, params: &mut Params<super::Resources>) {
+            params.color = mat.0.unwrap();
+        }
+    }
+
+    #[test]
+    fn golden_draws() {
+        let mut factory = Factory::new();
+        factory.program_info.uniforms.push(shade::UniformVar {
+            name: "u_Color".to_string(),
+            location: 0,
+            count: 1,
+            base_type: shade::BaseType::F32,
+            container: shade::ContainerType::Vector(4),
+        });
+        let program = factory.link_program(b"", b"").unwrap();
+        let mut phase = gfx_phase::Phase::new("Test", Technique {
+            program: program.clone(),
+            state: gfx::DrawState::new(),
+        }).with_sort(gfx_phase::sort::front_to_back)
+          .with_cache();
+        let mesh = gfx::Mesh::new(8);
+        let slices: Vec<gfx::Slice<super::Resources>> = (0 .. 3).map(|i| gfx::Slice {
+            start: i * 2,
+            end: i * 2 + 2,
+            prim_type: gfx::PrimitiveType::Point,
+            kind: gfx::SliceKind::Vertex,
+        }).collect();
+        let objects = [
+            (&slices[0], Material(Some([1.0, 0.0, 0.0, 1.0])), ViewInfo(3.0)),
+            (&slices[1], Material(Some([0.0, 1.0, 0.0, 1.0])), ViewInfo(1.0)),
+            (&slices[2], Material(None), ViewInfo(0.0)),
+            (&slices[2], Material(Some([0.0, 0.0, 1.0, 1.0])), ViewInfo(2.0)),
+        ];
+        let passed: Vec<bool> = objects.iter().map(|&(slice, ref mat, ref view)|
+            phase.enqueue(&mesh, slice, mat, view).unwrap()).collect();
+        assert_eq!(passed, vec![true, true, false, true]);
+        let mut stream = Stream::new(&mut factory, 100, 100);
+        stream.clear(gfx::ClearData {
+            color: [0.0; 4],
+            depth: 1.0,
+            stencil: 0,
+        });
+        phase.flush(&mut stream).unwrap();
+        // the clear is followed by the draws sorted front to back
+        match stream.commands[0] {
+            Command::Clear(ref data) => assert_eq!(data.depth, 1.0),
+            Command::Draw(_) => panic!("Expected a clear first"),
+        }
+        let golden = [
+            (2, [0.0, 1.0, 0.0, 1.0]),
+            (4, [0.0, 0.0, 1.0, 1.0]),
+            (0, [1.0, 0.0, 0.0, 1.0]),
+        ];
+        let draws: Vec<_> = stream.draws().collect();
+        assert_eq!(draws.len(), golden.len());
+        for (call, &(start, color)) in draws.iter().zip(golden.iter()) {
+            assert_eq!(call.program, program);
+            assert_eq!(call.slice.start, start);
+            assert_eq!(call.uniforms.len(), 1);
+            match call.uniforms[0] {
+                shade::UniformValue::F32Vector4(c) => assert_eq!(c, color),
+                ref other => panic!("Unexpected uniform {:?}", other),
+            }
+        }
+        // the queue is empty after the flush
+        stream.take();
+        phase.flush(&mut stream).unwrap();
+        assert!(stream.commands.is_empty());
+    }
+}
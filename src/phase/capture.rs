@@ -0,0 +1,235 @@
+//! Frame capture and replay of the phase output.
+//!
+//! Replaying is only possible in-process, from the objects kept by the
+//! `Capture`, since they refer to live GPU resources. Saved captures
+//! contain textual records, which can be loaded back and compared
+//! against other captures, but not replayed.
+
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use gfx;
+use phase::{Object, FlushError};
+
+/// Textual record of a single draw, used for saving and comparing captures.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    /// Technique kernel.
+    pub kernel: String,
+    /// Object depth.
+    pub depth: String,
+    /// Shader program.
+    pub program: String,
+    /// Mesh slice.
+    pub slice: String,
+    /// Draw state.
+    pub state: String,
+    /// Parameter values.
+    pub params: String,
+}
+
+const NUM_FIELDS: usize = 6;
+
+impl Record {
+    /// Describe an object.
+    pub fn new<S: Debug, K: Debug, P: gfx::shade::ShaderParam>(o: &Object<S, K, P>) -> Record {
+        use gfx::Batch;
+        let mut storage = gfx::ParamStorage::new();
+        let batch = o.with(&o.state);
+        let params = match batch.fill_params(&mut storage) {
+            Ok(_) => format!("{:?} {:?} {:?}", storage.uniforms, storage.blocks, storage.textures),
+            Err(e) => format!("error: {:?}", e),
+        };
+        Record {
+            kernel: format!("{:?}", o.kernel),
+            depth: format!("{:?}", o.depth),
+            program: format!("{:?}", o.batch.program()),
+            slice: format!("{:?}", o.slice),
+            state: format!("{:?}", o.state),
+            params: params,
+        }
+    }
+
+    /// Convert into a single tab-separated line.
+    pub fn to_line(&self) -> String {
+        [&self.kernel, &self.depth, &self.program, &self.slice, &self.state, &self.params]
+            .iter().map(|s| s.replace("\t", " ").replace("\n", " "))
+            .collect::<Vec<_>>().connect("\t")
+    }
+
+    /// Parse a line produced by `to_line`.
+    pub fn from_line(line: &str) -> Option<Record> {
+        let fields: Vec<_> = line.split('\t').collect();
+        if fields.len() != NUM_FIELDS {
+            return None
+        }
+        Some(Record {
+            kernel: fields[0].to_string(),
+            depth: fields[1].to_string(),
+            program: fields[2].to_string(),
+            slice: fields[3].to_string(),
+            state: fields[4].to_string(),
+            params: fields[5].to_string(),
+        })
+    }
+}
+
+/// Objects flushed by a phase during a single frame, in the submission order.
+pub struct Capture<S, K, P: gfx::shade::ShaderParam> {
+    /// Name of the captured phase.
+    pub phase: String,
+    /// Copies of the flushed objects, used for replaying.
+    pub objects: Vec<Object<S, K, P>>,
+    /// Descriptions of the flushed objects, used for saving.
+    pub records: Vec<Record>,
+}
+
+impl<S: Copy + Debug, K: Copy + Debug, P: gfx::shade::ShaderParam + Clone> Capture<S, K, P> where
+    P::Link: Clone,
+{
+    /// Create an empty capture.
+    pub fn new(phase: &str) -> Capture<S, K, P> {
+        Capture {
+            phase: phase.to_string(),
+            objects: Vec::new(),
+            records: Vec::new(),
+        }
+    }
+
+    /// Add a flushed object.
+    pub fn push(&mut self, o: &Object<S, K, P>) {
+        self.records.push(Record::new(o));
+        self.objects.push(o.clone());
+    }
+
+    /// Issue the captured objects again into a stream. The resources
+    /// they refer to have to be still alive. Records loaded from a file
+    /// can't be replayed, only compared.
+    pub fn replay<T: gfx::Stream<P::Resources>>(&self, stream: &mut T)
+                  -> Result<(), FlushError> {
+        for o in self.objects.iter() {
+            try!(stream.draw(&o.with(&o.state)));
+        }
+        Ok(())
+    }
+
+    /// Write the records into a file, one line per object. The file
+    /// is meant for inspection and comparison, not for replaying.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut file = try!(File::create(path));
+        for r in self.records.iter() {
+            try!(writeln!(file, "{}", r.to_line()));
+        }
+        Ok(())
+    }
+}
+
+/// Read the records of a capture saved into a file, for comparing
+/// with `diff_captures`.
+pub fn load_capture(path: &Path) -> io::Result<Vec<Record>> {
+    let file = try!(File::open(path));
+    let mut records = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = try!(line);
+        match Record::from_line(&line) {
+            Some(r) => records.push(r),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Malformed capture record at line {}", i + 1))),
+        }
+    }
+    Ok(records)
+}
+
+/// Difference between two captures at a given position.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Difference {
+    /// Object only present in the second capture.
+    Added(usize, Record),
+    /// Object only present in the first capture.
+    Removed(usize, Record),
+    /// Objects at the same position that differ.
+    Changed(usize, Record, Record),
+}
+
+/// Compare two captures object by object.
+pub fn diff_captures(a: &[Record], b: &[Record]) -> Vec<Difference> {
+    let mut result = Vec::new();
+    for (i, (ra, rb)) in a.iter().zip(b.iter()).enumerate() {
+        if ra != rb {
+            result.push(Difference::Changed(i, ra.clone(), rb.clone()));
+        }
+    }
+    for (i, r) in a.iter().enumerate().skip(b.len()) {
+        result.push(Difference::Removed(i, r.clone()));
+    }
+    for (i, r) in b.iter().enumerate().skip(a.len()) {
+        result.push(Difference::Added(i, r.clone()));
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+    use super::{Record, Difference, load_capture, diff_captures};
+
+    fn make_record(kernel: &str, depth: &str) -> Record {
+        Record {
+            kernel: kernel.to_string(),
+            depth: depth.to_string(),
+            program: "Program(1)".to_string(),
+            slice: "Slice { start: 0, end: 6 }".to_string(),
+            state: "DrawState { .. }".to_string(),
+            params: "[Some(F32Vector4([1.0, 0.0, 0.0, 1.0]))] [] []".to_string(),
+        }
+    }
+
+    #[test]
+    fn line() {
+        let r = make_record("Flat", "0.5");
+        assert_eq!(Record::from_line(&r.to_line()), Some(r.clone()));
+        // separators inside the fields are replaced by spaces
+        let mut multi = r.clone();
+        multi.state = "DrawState {\n\tblend: None\n}".to_string();
+        let parsed = Record::from_line(&multi.to_line()).unwrap();
+        assert_eq!(parsed.state, "DrawState { blend: None }");
+        assert_eq!(parsed.params, r.params);
+        assert_eq!(Record::from_line("Flat\t0.5"), None);
+    }
+
+    #[test]
+    fn load() {
+        let records = vec![make_record("Flat", "0.5"), make_record("Phong", "1.5")];
+        let path = env::temp_dir().join("gfx_phase_capture.txt");
+        {
+            let mut file = File::create(&path).unwrap();
+            for r in records.iter() {
+                writeln!(file, "{}", r.to_line()).unwrap();
+            }
+        }
+        assert_eq!(load_capture(&path).unwrap(), records);
+        File::create(&path).unwrap().write_all(b"garbage\n").unwrap();
+        assert!(load_capture(&path).is_err());
+    }
+
+    #[test]
+    fn diff() {
+        let a = vec![make_record("Flat", "0.5"), make_record("Flat", "1.0")];
+        let b = vec![make_record("Flat", "0.5"), make_record("Phong", "1.0"),
+                     make_record("Phong", "2.0")];
+        assert!(diff_captures(&a, &a).is_empty());
+        assert_eq!(diff_captures(&a, &b), vec![
+            Difference::Changed(1, a[1].clone(), b[1].clone()),
+            Difference::Added(2, b[2].clone()),
+        ]);
+        assert_eq!(diff_captures(&b[..1], &a), vec![
+            Difference::Added(1, a[1].clone()),
+        ]);
+        assert_eq!(diff_captures(&b, &b[..2]), vec![
+            Difference::Removed(2, b[2].clone()),
+        ]);
+    }
+}
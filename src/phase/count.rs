@@ -0,0 +1,202 @@
+//! Stream adaptor for counting state changes.
+
+use gfx;
+
+/// Number of state changes between consecutive draw calls. The first
+/// draw call counts as a change of everything it binds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StateChanges {
+    /// Number of draw calls.
+    pub draws: u32,
+    /// Number of shader program changes.
+    pub programs: u32,
+    /// Number of vertex buffer bindings that changed.
+    pub vertex_buffers: u32,
+    /// Number of index buffer changes.
+    pub index_buffers: u32,
+    /// Number of texture bindings that changed.
+    pub textures: u32,
+    /// Number of uniform values that changed.
+    pub uniforms: u32,
+    /// Number of draw state changes.
+    pub states: u32,
+}
+
+impl StateChanges {
+    /// Create an empty report.
+    pub fn new() -> StateChanges {
+        StateChanges {
+            draws: 0,
+            programs: 0,
+            vertex_buffers: 0,
+            index_buffers: 0,
+            textures: 0,
+            uniforms: 0,
+            states: 0,
+        }
+    }
+}
+
+struct Bindings<R: gfx::Resources> {
+    program: gfx::handle::Program<R>,
+    vertex_buffers: Vec<gfx::handle::RawBuffer<R>>,
+    index_buffer: Option<gfx::handle::RawBuffer<R>>,
+    textures: Vec<gfx::shade::TextureParam<R>>,
+    uniforms: Vec<gfx::shade::UniformValue>,
+    state: gfx::DrawState,
+}
+
+/// Count the changed elements of two lists, including the extra ones.
+fn count_changes<T: PartialEq>(old: &[T], new: &[T]) -> u32 {
+    let changed = old.iter().zip(new.iter()).filter(|&(a, b)| a != b).count();
+    let extra = if new.len() > old.len() {new.len() - old.len()} else {0};
+    (changed + extra) as u32
+}
+
+/// A stream adaptor that forwards everything into the inner stream,
+/// while counting the state changes between consecutive draws.
+pub struct CountingStream<'a, R: gfx::Resources, S: gfx::Stream<R> + 'a> {
+    inner: &'a mut S,
+    last: Option<Bindings<R>>,
+    /// Accumulated counts.
+    pub changes: StateChanges,
+}
+
+impl<'a, R: gfx::Resources, S: gfx::Stream<R>> CountingStream<'a, R, S> {
+    /// Wrap a stream.
+    pub fn new(inner: &'a mut S) -> CountingStream<'a, R, S> {
+        CountingStream {
+            inner: inner,
+            last: None,
+            changes: StateChanges::new(),
+        }
+    }
+
+    /// Reset the counts and forget the last bindings.
+    pub fn reset(&mut self) -> StateChanges {
+        self.last = None;
+        ::std::mem::replace(&mut self.changes, StateChanges::new())
+    }
+}
+
+impl<'a, R: gfx::Resources, S: gfx::Stream<R>> gfx::Stream<R> for CountingStream<'a, R, S> {
+    type CommandBuffer = S::CommandBuffer;
+    type Output = S::Output;
+
+    fn get_output(&self) -> &S::Output {
+        self.inner.get_output()
+    }
+
+    fn access(&mut self) -> (&mut gfx::Renderer<R, S::CommandBuffer>, &S::Output) {
+        self.inner.access()
+    }
+
+    fn clear(&mut self, data: gfx::ClearData) {
+        self.inner.clear(data)
+    }
+
+    fn draw<B: gfx::Batch<Resources = R>>(&mut self, batch: &B)
+            -> Result<(), gfx::DrawError<B::Error>> {
+        let bindings = {
+            let (mesh, _, slice, state) = match batch.get_data() {
+                Ok(data) => data,
+                Err(e) => return Err(gfx::DrawError::InvalidBatch(e)),
+            };
+            let mut storage = gfx::ParamStorage::new();
+            let program = match batch.fill_params(&mut storage) {
+                Ok(p) => p.clone(),
+                Err(e) => return Err(gfx::DrawError::InvalidBatch(e)),
+            };
+            Bindings {
+                program: program,
+                vertex_buffers: mesh.attributes.iter().map(|a| a.buffer.clone()).collect(),
+                index_buffer: match slice.kind {
+                    gfx::SliceKind::Vertex => None,
+                    gfx::SliceKind::Index8(ref buf, _) => Some(buf.raw().clone()),
+                    gfx::SliceKind::Index16(ref buf, _) => Some(buf.raw().clone()),
+                    gfx::SliceKind::Index32(ref buf, _) => Some(buf.raw().clone()),
+                },
+                textures: storage.textures,
+                uniforms: storage.uniforms,
+                state: *state,
+            }
+        };
+        try!(self.inner.draw(batch));
+        let c = &mut self.changes;
+        c.draws += 1;
+        match self.last {
+            Some(ref last) => {
+                if last.program != bindings.program {
+                    c.programs += 1;
+                }
+                c.vertex_buffers += count_changes(&last.vertex_buffers, &bindings.vertex_buffers);
+                if bindings.index_buffer.is_some() && last.index_buffer != bindings.index_buffer {
+                    c.index_buffers += 1;
+                }
+                c.textures += count_changes(&last.textures, &bindings.textures);
+                c.uniforms += count_changes(&last.uniforms, &bindings.uniforms);
+                if last.state != bindings.state {
+                    c.states += 1;
+                }
+            },
+            None => {
+                c.programs += 1;
+                c.vertex_buffers += bindings.vertex_buffers.len() as u32;
+                if bindings.index_buffer.is_some() {
+                    c.index_buffers += 1;
+                }
+                c.textures += bindings.textures.len() as u32;
+                c.uniforms += bindings.uniforms.len() as u32;
+                c.states += 1;
+            },
+        }
+        self.last = Some(bindings);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gfx;
+    use gfx_mock;
+    use test::{make_factory, make_object, make_resources};
+    use super::{CountingStream, StateChanges};
+
+    #[test]
+    fn changes() {
+        let mut factory = make_factory();
+        let (program_a, texture_a) = make_resources(&mut factory);
+        let (program_b, texture_b) = make_resources(&mut factory);
+        let blend = gfx::DrawState::new().blend(gfx::BlendPreset::Alpha);
+        let (red, green) = ([1.0, 0.0, 0.0, 1.0], [0.0, 1.0, 0.0, 1.0]);
+        let objects = [
+            make_object(&program_a, &texture_a, red, [0, 3], 0.0, gfx::DrawState::new()),
+            // only the color changes
+            make_object(&program_a, &texture_a, green, [3, 6], 0.0, gfx::DrawState::new()),
+            // the texture and the state change
+            make_object(&program_a, &texture_b, green, [0, 3], 0.0, blend),
+            // only the program changes
+            make_object(&program_b, &texture_b, green, [0, 3], 0.0, blend),
+        ];
+        let mut inner = gfx_mock::Stream::new(&mut factory, 100, 100);
+        {
+            let mut stream = CountingStream::new(&mut inner);
+            for o in objects.iter() {
+                gfx::Stream::draw(&mut stream, &o.with(&o.state)).unwrap();
+            }
+            // the first draw counts as a change of everything it binds
+            assert_eq!(stream.reset(), StateChanges {
+                draws: 4,
+                programs: 2,
+                vertex_buffers: 0,
+                index_buffers: 0,
+                textures: 2,
+                uniforms: 2,
+                states: 2,
+            });
+            assert_eq!(stream.changes, StateChanges::new());
+        }
+        // everything is forwarded to the inner stream
+        assert_eq!(inner.draws().count(), 4);
+    }
+}
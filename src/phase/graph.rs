@@ -0,0 +1,381 @@
+//! Frame graph of phases.
+
+use gfx;
+use phase::{AbstractPhase, FlushError, Object, Phase};
+use mem;
+
+/// Index of a render target in the graph.
+pub type TargetId = usize;
+/// Index of a pass in the graph.
+pub type PassId = usize;
+
+/// Something that can be flushed into a particular stream type.
+/// Implemented for all phases, so that passes can store them together.
+pub trait Flush<R: gfx::Resources, S: gfx::Stream<R>> {
+    /// Flush the contents into a stream.
+    fn flush_into(&mut self, &mut S) -> Result<(), FlushError>;
+}
+
+impl<
+    R: gfx::Resources,
+    M: ::Material,
+    V: ::ToDepth + Copy,
+    T: ::Technique<R, M, V>,
+    Y: mem::Memory<(T::Kernel, gfx::Mesh<R>),
+        Object<V::Depth, T::Kernel, T::Params>
+    >,
+    S: gfx::Stream<R>,
+> Flush<R, S> for Phase<R, M, V, T, Y> where
+    T::Params: Clone,
+    <T::Params as gfx::shade::ShaderParam>::Link: Clone,
+{
+    fn flush_into(&mut self, stream: &mut S) -> Result<(), FlushError> {
+        self.flush(stream)
+    }
+}
+
+/// A named render target.
+pub struct Target<O> {
+    /// Target name.
+    pub name: String,
+    /// Output that the passes render into.
+    pub output: O,
+}
+
+/// A pass of the graph: a list of phases that read some targets
+/// and render into others.
+pub struct Pass<'a, R: gfx::Resources, S: gfx::Stream<R>> {
+    /// Pass name.
+    pub name: String,
+    /// Targets that the pass reads from.
+    pub inputs: Vec<TargetId>,
+    /// Targets that the pass renders into.
+    pub outputs: Vec<TargetId>,
+    /// Optional clear of the outputs before the pass.
+    pub clear: Option<gfx::ClearData>,
+    /// Phases to flush, in order.
+    pub phases: Vec<&'a mut Flush<R, S>>,
+}
+
+impl<'a, R: gfx::Resources, S: gfx::Stream<R>> Pass<'a, R, S> {
+    /// Create a new empty pass.
+    pub fn new(name: &str) -> Pass<'a, R, S> {
+        Pass {
+            name: name.to_string(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            clear: None,
+            phases: Vec::new(),
+        }
+    }
+
+    /// Add an input target.
+    pub fn with_input(mut self, target: TargetId) -> Pass<'a, R, S> {
+        self.inputs.push(target);
+        self
+    }
+
+    /// Add an output target.
+    pub fn with_output(mut self, target: TargetId) -> Pass<'a, R, S> {
+        self.outputs.push(target);
+        self
+    }
+
+    /// Clear the outputs before the pass.
+    pub fn with_clear(self, data: gfx::ClearData) -> Pass<'a, R, S> {
+        Pass {
+            clear: Some(data),
+            .. self
+        }
+    }
+
+    /// Add a phase to flush.
+    pub fn with_phase(mut self, phase: &'a mut Flush<R, S>) -> Pass<'a, R, S> {
+        self.phases.push(phase);
+        self
+    }
+}
+
+/// Frame graph error.
+#[derive(Debug)]
+pub enum GraphError {
+    /// Pass refers to a target that doesn't exist.
+    UnknownTarget(String, TargetId),
+    /// Passes depend on each other, given by names.
+    Cycle(Vec<String>),
+    /// Error flushing a pass phase.
+    Flush(String, FlushError),
+}
+
+/// Frame graph, ordering the passes by their target dependencies.
+pub struct FrameGraph<'a, R: gfx::Resources, S: gfx::Stream<R>, O> {
+    /// Render targets.
+    pub targets: Vec<Target<O>>,
+    /// Passes, in the declaration order.
+    pub passes: Vec<Pass<'a, R, S>>,
+    /// Targets that are consumed outside of the graph.
+    pub presented: Vec<TargetId>,
+}
+
+impl<'a, R: gfx::Resources, S: gfx::Stream<R>, O> FrameGraph<'a, R, S, O> {
+    /// Create a new empty graph.
+    pub fn new() -> FrameGraph<'a, R, S, O> {
+        FrameGraph {
+            targets: Vec::new(),
+            passes: Vec::new(),
+            presented: Vec::new(),
+        }
+    }
+
+    /// Add a render target.
+    pub fn add_target(&mut self, name: &str, output: O) -> TargetId {
+        self.targets.push(Target {
+            name: name.to_string(),
+            output: output,
+        });
+        self.targets.len() - 1
+    }
+
+    /// Add a pass.
+    pub fn add_pass(&mut self, pass: Pass<'a, R, S>) -> PassId {
+        self.passes.push(pass);
+        self.passes.len() - 1
+    }
+
+    /// Mark a target as used outside of the graph, e.g. for presenting.
+    /// Passes not contributing to any presented targets are culled.
+    pub fn present(&mut self, target: TargetId) {
+        self.presented.push(target);
+    }
+
+    /// Compute the dependencies of every pass, as pairs of a pass index
+    /// and a flag telling if the contents it produces are used.
+    ///
+    /// Every write produces a new version of the target, in the
+    /// declaration order. A reader depends on the last writer declared
+    /// before it, and the next writer depends on the reader, so that it
+    /// doesn't overwrite the version being read. A target read before
+    /// any pass writes it refers to the imported contents, such as the
+    /// history of the previous frame, so only the first writer waits for it.
+    /// Writers depend on the previous writers of the same target,
+    /// using their contents unless the outputs are cleared.
+    fn get_dependencies(&self) -> Vec<Vec<(PassId, bool)>> {
+        let writers: Vec<Vec<PassId>> = (0 .. self.targets.len()).map(|t|
+            self.passes.iter().enumerate()
+                .filter(|&(_, p)| p.outputs.contains(&t))
+                .map(|(i, _)| i)
+                .collect()
+            ).collect();
+        let mut deps: Vec<Vec<(PassId, bool)>> = self.passes.iter().map(|_| Vec::new()).collect();
+        for (b, pass) in self.passes.iter().enumerate() {
+            for &t in pass.inputs.iter() {
+                let list = &writers[t];
+                let version = match list.iter().position(|&w| w >= b) {
+                    Some(0) => {
+                        // imported contents
+                        if list[0] != b {
+                            deps[list[0]].push((b, false));
+                        }
+                        continue
+                    },
+                    Some(k) => k - 1,
+                    None if list.is_empty() => continue,
+                    None => list.len() - 1,
+                };
+                deps[b].push((list[version], true));
+                if let Some(&next) = list.get(version + 1) {
+                    if next != b {
+                        deps[next].push((b, false));
+                    }
+                }
+            }
+            for &t in pass.outputs.iter() {
+                let list = &writers[t];
+                let k = list.iter().position(|&w| w == b).unwrap();
+                if k > 0 {
+                    deps[b].push((list[k - 1], pass.clear.is_none()));
+                }
+            }
+        }
+        deps
+    }
+
+    /// Find out which passes contribute to the presented targets.
+    fn find_alive(&self, deps: &[Vec<(PassId, bool)>]) -> Vec<bool> {
+        let mut alive: Vec<bool> = self.passes.iter().map(|_| false).collect();
+        let mut stack = Vec::new();
+        for &t in self.presented.iter() {
+            let last = self.passes.iter().enumerate().rev()
+                           .find(|&(_, p)| p.outputs.contains(&t));
+            if let Some((i, _)) = last {
+                stack.push(i);
+            }
+        }
+        while let Some(i) = stack.pop() {
+            if alive[i] {
+                continue
+            }
+            alive[i] = true;
+            stack.extend(deps[i].iter().filter(|&&(_, used)| used).map(|&(d, _)| d));
+        }
+        alive
+    }
+
+    /// Compute the execution order of the passes, skipping the ones
+    /// that don't contribute to the presented targets.
+    pub fn compile(&self) -> Result<Vec<PassId>, GraphError> {
+        let num = self.targets.len();
+        for pass in self.passes.iter() {
+            for &t in pass.inputs.iter().chain(pass.outputs.iter()) {
+                if t >= num {
+                    return Err(GraphError::UnknownTarget(pass.name.clone(), t))
+                }
+            }
+        }
+        for &t in self.presented.iter() {
+            if t >= num {
+                return Err(GraphError::UnknownTarget(String::new(), t))
+            }
+        }
+        let deps = self.get_dependencies();
+        let alive = self.find_alive(&deps);
+        let mut pending: Vec<PassId> = (0 .. self.passes.len())
+            .filter(|&i| alive[i]).collect();
+        let mut order = Vec::with_capacity(pending.len());
+        while !pending.is_empty() {
+            // pick the first pass in the declaration order
+            // that has all the dependencies satisfied
+            let ready = pending.iter().position(|&b|
+                !deps[b].iter().any(|&(a, _)| pending.contains(&a)));
+            match ready {
+                Some(pos) => order.push(pending.remove(pos)),
+                None => return Err(GraphError::Cycle(pending.iter()
+                    .map(|&i| self.passes[i].name.clone()).collect())),
+            }
+        }
+        Ok(order)
+    }
+
+    /// Execute the passes in order. The `bind` function is called before
+    /// each pass in order to direct the stream into the pass outputs.
+    pub fn execute<F>(&mut self, stream: &mut S, mut bind: F)
+                   -> Result<Vec<PassId>, GraphError> where
+        F: FnMut(&mut S, &[&O]),
+    {
+        let order = try!(self.compile());
+        for &id in order.iter() {
+            let pass = &mut self.passes[id];
+            let outputs: Vec<&O> = pass.outputs.iter()
+                .map(|&t| &self.targets[t].output).collect();
+            bind(stream, &outputs);
+            if let Some(data) = pass.clear {
+                stream.clear(data);
+            }
+            for phase in pass.phases.iter_mut() {
+                match phase.flush_into(stream) {
+                    Ok(()) => (),
+                    Err(e) => return Err(GraphError::Flush(pass.name.clone(), e)),
+                }
+            }
+        }
+        Ok(order)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gfx_mock;
+    use super::{FrameGraph, GraphError, Pass};
+
+    type Graph<'a> = FrameGraph<'a, gfx_mock::Resources, gfx_mock::Stream, ()>;
+    type MockPass<'a> = Pass<'a, gfx_mock::Resources, gfx_mock::Stream>;
+
+    #[test]
+    fn ping_pong() {
+        let mut graph = Graph::new();
+        let t = graph.add_target("T", ());
+        let u = graph.add_target("U", ());
+        graph.add_pass(MockPass::new("A").with_output(t));
+        graph.add_pass(MockPass::new("B").with_input(t).with_output(u));
+        graph.add_pass(MockPass::new("C").with_input(u).with_output(t));
+        graph.present(t);
+        assert_eq!(graph.compile().unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn read_before_overwrite() {
+        let mut graph = Graph::new();
+        let x = graph.add_target("X", ());
+        let y = graph.add_target("Y", ());
+        graph.add_pass(MockPass::new("P1").with_output(x));
+        graph.add_pass(MockPass::new("P2").with_input(x).with_output(y));
+        graph.add_pass(MockPass::new("P3").with_output(x));
+        graph.present(x);
+        graph.present(y);
+        // the second writer of X has to wait for the reader of the first
+        let deps = graph.get_dependencies();
+        assert!(deps[2].contains(&(1, false)));
+        assert!(deps[1].contains(&(0, true)));
+        assert_eq!(graph.compile().unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn culling() {
+        let mut graph = Graph::new();
+        let x = graph.add_target("X", ());
+        let y = graph.add_target("Y", ());
+        let z = graph.add_target("Z", ());
+        graph.add_pass(MockPass::new("unused").with_output(z));
+        graph.add_pass(MockPass::new("first").with_output(x));
+        // overwrites X with a clear, so "first" is not needed
+        graph.add_pass(MockPass::new("second").with_output(x).with_clear(::gfx::ClearData {
+            color: [0.0; 4],
+            depth: 1.0,
+            stencil: 0,
+        }));
+        graph.add_pass(MockPass::new("final").with_input(x).with_output(y));
+        graph.present(y);
+        assert_eq!(graph.compile().unwrap(), vec![2, 3]);
+    }
+
+    #[test]
+    fn history() {
+        let mut graph = Graph::new();
+        let color = graph.add_target("Color", ());
+        let history = graph.add_target("History", ());
+        let output = graph.add_target("Output", ());
+        graph.add_pass(MockPass::new("scene").with_output(color));
+        // blends with the previous frame before it's overwritten
+        graph.add_pass(MockPass::new("resolve").with_input(color).with_input(history)
+                                               .with_output(output));
+        graph.add_pass(MockPass::new("store").with_input(output).with_output(history));
+        graph.present(output);
+        graph.present(history);
+        let deps = graph.get_dependencies();
+        assert_eq!(deps[1], vec![(0, true)]);
+        assert!(deps[2].contains(&(1, true)) && deps[2].contains(&(1, false)));
+        assert_eq!(graph.compile().unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn imported_read() {
+        let mut graph = Graph::new();
+        let x = graph.add_target("X", ());
+        let y = graph.add_target("Y", ());
+        // reads the imported X, so it doesn't wait for B
+        graph.add_pass(MockPass::new("A").with_input(x).with_output(y));
+        graph.add_pass(MockPass::new("B").with_input(y).with_output(x));
+        graph.present(x);
+        assert_eq!(graph.compile().unwrap(), vec![0, 1]);
+    }
+
+    #[test]
+    fn unknown_target() {
+        let mut graph = Graph::new();
+        graph.add_pass(MockPass::new("A").with_output(3));
+        match graph.compile() {
+            Err(GraphError::UnknownTarget(ref name, 3)) if name == "A" => (),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+}
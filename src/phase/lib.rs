@@ -5,16 +5,31 @@
 
 #[macro_use]
 extern crate log;
+#[cfg_attr(test, macro_use)]
 extern crate gfx;
 extern crate draw_queue;
+#[cfg(test)]
+extern crate gfx_mock;
 
+mod capture;
+mod count;
+mod graph;
 mod mem;
+mod permutation;
 mod phase;
+mod validate;
+mod watch;
 
 use std::fmt::Debug;
 use std::hash::Hash;
 
-pub use self::phase::{Object, sort, FlushError, OrderFun,
+pub use self::capture::{Record, Capture, Difference, load_capture, diff_captures};
+pub use self::count::{CountingStream, StateChanges};
+pub use self::graph::{TargetId, PassId, Flush, Target, Pass, GraphError, FrameGraph};
+pub use self::permutation::{Bitset, LinkResult, Permutations};
+pub use self::validate::{Rules, Violation, validate};
+pub use self::watch::Watcher;
+pub use self::phase::{Object, ObjectInfo, sort, FlushError, OrderFun,
                       AbstractPhase, CachedPhase, Phase};
 
 /// Abstract material.
@@ -54,3 +69,77 @@ pub trait Technique<R: gfx::Resources, M: Material, V: ToDepth> {
     /// Fix the shader parameters, using an updated material and view info.
     fn fix_params(&self, &M, &V, &mut Self::Params);
 }
+
+#[cfg(test)]
+mod test {
+    use std::marker::PhantomData;
+    use gfx;
+    use gfx::traits::*;
+    use gfx::device::shade;
+    use gfx_mock;
+
+    #[allow(missing_docs)]
+    pub mod params {
+        use gfx::shade::TextureParam;
+        gfx_parameters!( Params {
+            u_Color@ color: [f32; 4],
+            t_Color@ color_map: TextureParam<R>,
+        });
+    }
+    pub use self::params::Params;
+
+    pub type Object = ::Object<f32, (), Params<gfx_mock::Resources>>;
+
+    /// Create a factory of programs with one color uniform and one texture.
+    pub fn make_factory() -> gfx_mock::Factory {
+        let mut factory = gfx_mock::Factory::new();
+        factory.program_info.uniforms.push(shade::UniformVar {
+            name: "u_Color".to_string(),
+            location: 0,
+            count: 1,
+            base_type: shade::BaseType::F32,
+            container: shade::ContainerType::Vector(4),
+        });
+        factory.program_info.textures.push(shade::SamplerVar {
+            name: "t_Color".to_string(),
+            location: 0,
+            base_type: shade::BaseType::F32,
+            sampler_type: shade::SamplerType::Sampler2D(shade::IsArray::NoArray,
+                shade::IsShadow::NoShadow, shade::IsMultiSample::NoMultiSample,
+                shade::IsRect::NoRect),
+        });
+        factory
+    }
+
+    /// Create an object drawing a range of vertices of an 8-vertex mesh.
+    pub fn make_object(program: &gfx::handle::Program<gfx_mock::Resources>,
+                       texture: &gfx::handle::Texture<gfx_mock::Resources>,
+                       color: [f32; 4], range: [u32; 2], depth: f32,
+                       state: gfx::DrawState) -> Object {
+        ::Object {
+            batch: gfx::batch::Core::new(gfx::Mesh::new(8), program.clone()).unwrap(),
+            params: Params {
+                color: color,
+                color_map: (texture.clone(), None),
+                _r: PhantomData,
+            },
+            slice: gfx::Slice {
+                start: range[0],
+                end: range[1],
+                prim_type: gfx::PrimitiveType::TriangleList,
+                kind: gfx::SliceKind::Vertex,
+            },
+            depth: depth,
+            kernel: (),
+            state: state,
+        }
+    }
+
+    /// Create a program and a texture to draw objects with.
+    pub fn make_resources(factory: &mut gfx_mock::Factory)
+                          -> (gfx::handle::Program<gfx_mock::Resources>,
+                              gfx::handle::Texture<gfx_mock::Resources>) {
+        (factory.link_program(b"", b"").unwrap(),
+         factory.create_texture_rgba8(1, 1).unwrap())
+    }
+}
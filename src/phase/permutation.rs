@@ -0,0 +1,183 @@
+//! Shader permutations driven by technique kernels.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use gfx;
+use gfx::traits::*;
+
+/// A kernel that is a set of bits, each enabling a shader feature.
+pub trait Bitset: Copy + Eq + Hash {
+    /// Get the raw bits.
+    fn get_bits(&self) -> u64;
+}
+
+macro_rules! impl_bitset {
+    ($($t:ty)*) => ($(
+        impl Bitset for $t {
+            fn get_bits(&self) -> u64 { *self as u64 }
+        }
+    )*)
+}
+
+impl_bitset!(u8 u16 u32 u64 usize);
+
+/// Result of linking a permutation. Link errors are memorized as well,
+/// in order to avoid repeating them next time.
+pub type LinkResult<R> = Result<gfx::handle::Program<R>, gfx::ProgramError>;
+
+/// A set of program permutations, produced from the same sources by
+/// defining a preprocessor symbol for each bit of the kernel.
+/// Programs are linked on demand and cached per kernel.
+pub struct Permutations<R: gfx::Resources, K> {
+    vertex: String,
+    fragment: String,
+    defines: Vec<(u64, String)>,
+    cache: HashMap<K, LinkResult<R>>,
+}
+
+impl<R: gfx::Resources, K: Bitset> Permutations<R, K> {
+    /// Create a new permutation set from the shader sources.
+    pub fn new(vertex: &str, fragment: &str) -> Permutations<R, K> {
+        Permutations {
+            vertex: vertex.to_string(),
+            fragment: fragment.to_string(),
+            defines: Vec::new(),
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Define a symbol for the kernels that have all the given bits set.
+    pub fn with_define(mut self, bits: K, name: &str) -> Permutations<R, K> {
+        self.defines.push((bits.get_bits(), name.to_string()));
+        self
+    }
+
+    /// Get the symbols defined for a kernel.
+    pub fn get_defines(&self, kernel: K) -> Vec<&str> {
+        let bits = kernel.get_bits();
+        self.defines.iter()
+            .filter(|&&(mask, _)| bits & mask == mask)
+            .map(|&(_, ref name)| &name[..])
+            .collect()
+    }
+
+    /// Produce the source of a shader for a kernel. The defines are
+    /// inserted after the `#version` line, if there is one.
+    pub fn make_source(&self, source: &str, kernel: K) -> Vec<u8> {
+        let (version, body) = if source.trim_left().starts_with("#version") {
+            let source = source.trim_left();
+            match source.find('\n') {
+                Some(pos) => (&source[.. pos+1], &source[pos+1 ..]),
+                None => (source, ""),
+            }
+        }else {
+            ("", source)
+        };
+        let mut out = version.to_string();
+        if !out.is_empty() && !out.ends_with("\n") {
+            out.push('\n');
+        }
+        for name in self.get_defines(kernel).iter() {
+            out.push_str(&format!("#define {}\n", name));
+        }
+        out.push_str(body);
+        out.into_bytes()
+    }
+
+    /// Get the program for a kernel, linking it if needed.
+    pub fn prepare<F: gfx::Factory<R>>(&mut self, factory: &mut F, kernel: K)
+                   -> Result<&gfx::handle::Program<R>, &gfx::ProgramError> {
+        if !self.cache.contains_key(&kernel) {
+            let vs = self.make_source(&self.vertex, kernel);
+            let fs = self.make_source(&self.fragment, kernel);
+            let result = factory.link_program(&vs, &fs);
+            if let Err(ref e) = result {
+                warn!("Permutation {:?} failed to link: {:?}",
+                      self.get_defines(kernel), e);
+            }
+            self.cache.insert(kernel, result);
+        }
+        self.cache[&kernel].as_ref()
+    }
+
+    /// Get an already prepared program, or the memorized link error.
+    /// Returns `None` if the kernel has not been prepared yet.
+    pub fn get(&self, kernel: K) -> Option<Result<&gfx::handle::Program<R>, &gfx::ProgramError>> {
+        self.cache.get(&kernel).map(|r| r.as_ref())
+    }
+
+    /// Forget all the linked programs and errors, e.g. after the
+    /// sources have changed.
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+
+    /// Replace the sources and relink all the cached kernels. If a kernel
+    /// fails to link, its previous program is kept. Returns the kernels
+    /// that got new programs.
+    pub fn reload<F: gfx::Factory<R>>(&mut self, factory: &mut F,
+                  vertex: &str, fragment: &str) -> Vec<K> {
+        self.vertex = vertex.to_string();
+        self.fragment = fragment.to_string();
+        let kernels: Vec<K> = self.cache.keys().map(|k| *k).collect();
+        let mut changed = Vec::new();
+        for kernel in kernels.into_iter() {
+            let vs = self.make_source(&self.vertex, kernel);
+            let fs = self.make_source(&self.fragment, kernel);
+            match factory.link_program(&vs, &fs) {
+                Ok(program) => {
+                    self.cache.insert(kernel, Ok(program));
+                    changed.push(kernel);
+                },
+                Err(e) => {
+                    warn!("Permutation {:?} failed to relink: {:?}",
+                          self.get_defines(kernel), e);
+                    if self.cache[&kernel].is_err() {
+                        self.cache.insert(kernel, Err(e));
+                    }
+                },
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gfx_mock;
+    use super::Permutations;
+
+    fn make() -> Permutations<gfx_mock::Resources, u8> {
+        Permutations::new("", "")
+            .with_define(1, "HAS_A")
+            .with_define(2, "HAS_B")
+            .with_define(3, "HAS_AB")
+    }
+
+    #[test]
+    fn defines() {
+        let p = make();
+        assert!(p.get_defines(0).is_empty());
+        assert_eq!(p.get_defines(1), vec!["HAS_A"]);
+        assert_eq!(p.get_defines(2), vec!["HAS_B"]);
+        assert_eq!(p.get_defines(3), vec!["HAS_A", "HAS_B", "HAS_AB"]);
+        assert_eq!(p.get_defines(4), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn source_with_version() {
+        let p = make();
+        let out = p.make_source("  #version 150 core\nvoid main() {}\n", 3);
+        assert_eq!(String::from_utf8(out).unwrap(),
+            "#version 150 core\n#define HAS_A\n#define HAS_B\n#define HAS_AB\nvoid main() {}\n");
+    }
+
+    #[test]
+    fn source_without_version() {
+        let p = make();
+        let out = p.make_source("void main() {}", 2);
+        assert_eq!(String::from_utf8(out).unwrap(), "#define HAS_B\nvoid main() {}");
+        let out = p.make_source("#version 150", 1);
+        assert_eq!(String::from_utf8(out).unwrap(), "#version 150\n#define HAS_A\n");
+    }
+}
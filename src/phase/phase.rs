@@ -2,9 +2,12 @@
 
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::fmt;
 use draw_queue;
 use gfx;
+use capture::Capture;
 use mem;
+use validate::{self, Rules, Violation};
 
 /// Potential error occuring during rendering.
 pub type FlushError = gfx::DrawError<gfx::batch::Error>;
@@ -63,6 +66,45 @@ impl<S: PartialOrd, K, P: gfx::shade::ShaderParam> Object<S, K, P> {
     }
 }
 
+/// Debug description of a queued object.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectInfo {
+    /// Technique kernel.
+    pub kernel: String,
+    /// Object depth.
+    pub depth: String,
+    /// Shader program.
+    pub program: String,
+    /// Vertex buffers of the mesh.
+    pub mesh: String,
+    /// Short summary of the draw state.
+    pub state: String,
+}
+
+impl ObjectInfo {
+    /// Describe an object.
+    pub fn new<S: fmt::Debug, K: fmt::Debug, P: gfx::shade::ShaderParam>(
+               o: &Object<S, K, P>) -> ObjectInfo {
+        let buffers: Vec<_> = o.batch.mesh().attributes.iter()
+                               .map(|a| format!("{:?}", a.buffer)).collect();
+        ObjectInfo {
+            kernel: format!("{:?}", o.kernel),
+            depth: format!("{:?}", o.depth),
+            program: format!("{:?}", o.batch.program()),
+            mesh: buffers.connect(","),
+            state: format!("depth={:?} blend={}", o.state.depth,
+                           if o.state.blend.is_some() {"on"} else {"off"}),
+        }
+    }
+}
+
+impl fmt::Display for ObjectInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "kernel={} depth={} program={} mesh=[{}] {}",
+               self.kernel, self.depth, self.program, self.mesh, self.state)
+    }
+}
+
 /// A container for the standard sorting methods.
 pub mod sort {
     use std::cmp::Ordering;
@@ -90,6 +132,16 @@ pub mod sort {
     {
         a.batch.program().cmp_ref(&b.batch.program())
     }
+    /// Sort by shader program, then front-to-back. Useful for opaque objects
+    /// when the depth buffer is already filled by a pre-pass.
+    pub fn program_front_to_back<S: PartialOrd, K, P: ShaderParam>(
+                                 a: &Object<S, K, P>, b: &Object<S, K, P>) -> Ordering
+    {
+        match program(a, b) {
+            Ordering::Equal => a.cmp_depth(b),
+            x => x,
+        }
+    }
     /// Sort by mesh. Allows minimizing the vertex format changes.
     pub fn mesh<S, K, P: ShaderParam>(a: &Object<S, K, P>, b: &Object<S, K, P>)
                 -> Ordering
@@ -133,6 +185,14 @@ pub struct Phase<
     memory: Y,
     /// Sorted draw queue.
     queue: draw_queue::Queue<Object<V::Depth, T::Kernel, T::Params>>,
+    /// Capture waiting for the next flush.
+    capture: Option<Capture<V::Depth, T::Kernel, T::Params>>,
+    /// Capture closed by the last flush.
+    captured: Option<Capture<V::Depth, T::Kernel, T::Params>>,
+    /// Validation rules checked at flush time.
+    pub validation: Option<Rules>,
+    /// Rule violations found since the last retrieval.
+    violations: Vec<Violation>,
 }
 
 /// Memory typedef using a `HashMap`.
@@ -166,7 +226,11 @@ impl<
             technique: tech,
             sort: None,
             memory: (),
-            queue: draw_queue::Queue::new()
+            queue: draw_queue::Queue::new(),
+            capture: None,
+            captured: None,
+            validation: None,
+            violations: Vec::new(),
         }
     }
 
@@ -187,10 +251,114 @@ impl<
             sort: self.sort,
             memory: HashMap::new(),
             queue: self.queue,
+            capture: self.capture,
+            captured: self.captured,
+            validation: self.validation,
+            violations: self.violations,
+        }
+    }
+}
+
+impl<
+    R: gfx::Resources,
+    M: ::Material,
+    V: ::ToDepth,
+    T: ::Technique<R, M, V>,
+> Phase<R, M, V, T, CacheMap<R, M, V, T>> {
+    /// Remove the cached objects of the kernels matching a predicate,
+    /// so that they get compiled again by the technique, e.g. after the
+    /// programs have been reloaded. Returns the number of removed objects.
+    pub fn invalidate<F: Fn(&T::Kernel) -> bool>(&mut self, fun: F) -> usize {
+        let keys: Vec<_> = self.memory.keys()
+                                      .filter(|&&(ref k, _)| fun(k))
+                                      .map(|k| k.clone())
+                                      .collect();
+        for key in keys.iter() {
+            self.memory.remove(key);
+        }
+        keys.len()
+    }
+
+    /// Let a function relink the programs of the technique, and remove
+    /// the cached objects of the kernels it returns, e.g. the result of
+    /// a technique `reload`. Returns the number of removed objects.
+    pub fn reload<F>(&mut self, fun: F) -> usize where
+        F: FnOnce(&mut T) -> Vec<T::Kernel>,
+    {
+        let changed = fun(&mut self.technique);
+        self.invalidate(|k| changed.contains(k))
+    }
+}
+
+impl<
+    R: gfx::Resources,
+    M: ::Material,
+    V: ::ToDepth,
+    T: ::Technique<R, M, V>,
+    Y,
+> Phase<R, M, V, T, Y> where
+    T::Params: Clone,
+    <T::Params as gfx::shade::ShaderParam>::Link: Clone,
+{
+    /// Start capturing the objects of the next flush. The capture is
+    /// closed at the end of that flush, and retrieved with `take_capture`.
+    pub fn start_capture(&mut self) {
+        self.capture = Some(Capture::new(&self.name));
+    }
+
+    /// Take the capture closed by the last flush. Returns `None` if
+    /// there was no capture started, or it hasn't been flushed yet.
+    pub fn take_capture(&mut self) -> Option<Capture<V::Depth, T::Kernel, T::Params>> {
+        self.captured.take()
+    }
+
+    /// Enable validation of the flushed objects.
+    pub fn with_validation(self, rules: Rules) -> Phase<R, M, V, T, Y> {
+        Phase {
+            validation: Some(rules),
+            .. self
+        }
+    }
+
+    /// Take the rule violations found since the last call.
+    pub fn take_violations(&mut self) -> Vec<Violation> {
+        ::std::mem::replace(&mut self.violations, Vec::new())
+    }
+
+    /// List the queued objects in the order they are going to be flushed.
+    pub fn list(&mut self) -> Vec<ObjectInfo> {
+        if let Some(fun) = self.sort {
+            self.queue.sort(fun);
+        }
+        self.queue.list().into_iter().map(|o| ObjectInfo::new(o)).collect()
+    }
+
+    /// Log the queued objects in the order they are going to be flushed.
+    pub fn log_queue(&mut self) {
+        for (i, info) in self.list().iter().enumerate() {
+            debug!("Phase {}: {}: {}", self.name, i, info);
         }
     }
 }
 
+impl<
+    R: gfx::Resources,
+    M: ::Material,
+    V: ::ToDepth,
+    T: ::Technique<R, M, V>,
+    Y,
+> fmt::Display for Phase<R, M, V, T, Y> {
+    /// List the queued objects, in the sorted order if the queue
+    /// has not changed since the last sorting.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "Phase {} ({} objects):", self.name, self.queue.objects.len()));
+        for (i, o) in self.queue.list().into_iter().enumerate() {
+            try!(writeln!(f, "\t{}: {}", i, ObjectInfo::new(o)));
+        }
+        Ok(())
+    }
+}
+
 impl<
     R: gfx::Resources,
     M: ::Material,
@@ -264,24 +432,52 @@ impl<
 
     fn flush<S: gfx::Stream<R>>(&mut self, stream: &mut S)
              -> Result<(), FlushError> {
-        match self.sort {
-            Some(fun) => {
-                // sort the queue
-                self.queue.sort(fun);
-                // accumulate the sorted draws into the renderer
-                for o in self.queue.iter() {
-                    try!(stream.draw(&o.with(&o.state)));
-                }
-            },
-            None => {
-                // accumulate the raw draws into the renderer
-                for o in self.queue.objects.iter() {
-                    try!(stream.draw(&o.with(&o.state)));
-                }
+        if let Some(fun) = self.sort {
+            // sort the queue
+            self.queue.sort(fun);
+        }
+        if let Some(ref rules) = self.validation {
+            let violations = match self.sort {
+                Some(_) => validate::validate(self.queue.iter(), rules),
+                None => validate::validate(self.queue.objects.iter(), rules),
+            };
+            for v in violations.iter() {
+                warn!("Phase {}: {}", self.name, v);
             }
+            self.violations.extend(violations.into_iter());
         }
+        let mut capture = self.capture.take();
+        let result = match self.sort {
+            // accumulate the sorted draws into the renderer
+            Some(_) => draw_objects(self.queue.iter(), &mut capture, stream),
+            // accumulate the raw draws into the renderer
+            None => draw_objects(self.queue.objects.iter(), &mut capture, stream),
+        };
+        // close the capture, even if the flush failed half-way
+        if capture.is_some() {
+            self.captured = capture;
+        }
+        try!(result);
         // done
-        self.queue.objects.clear();
+        self.queue.clear();
         Ok(())
     }
 }
+
+fn draw_objects<'a, S, K, P, I, T>(objects: I, capture: &mut Option<Capture<S, K, P>>,
+                stream: &mut T) -> Result<(), FlushError> where
+    S: Copy + fmt::Debug + 'a,
+    K: Copy + fmt::Debug + 'a,
+    P: gfx::shade::ShaderParam + Clone + 'a,
+    P::Link: Clone,
+    I: Iterator<Item = &'a Object<S, K, P>>,
+    T: gfx::Stream<P::Resources>,
+{
+    for o in objects {
+        if let Some(ref mut c) = *capture {
+            c.push(o);
+        }
+        try!(stream.draw(&o.with(&o.state)));
+    }
+    Ok(())
+}
@@ -0,0 +1,231 @@
+//! Validation of the phase output.
+
+use std::fmt;
+use gfx;
+use phase::Object;
+
+/// Set of validation rules to check.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rules {
+    /// Blended objects must be flushed back-to-front.
+    pub blend_order: bool,
+    /// Blended objects must not write depth.
+    pub blend_depth_write: bool,
+    /// Slices must stay within the vertex and index buffers.
+    pub slice_range: bool,
+    /// All shader parameters must be provided.
+    pub missing_params: bool,
+}
+
+impl Rules {
+    /// Enable all the rules.
+    pub fn all() -> Rules {
+        Rules {
+            blend_order: true,
+            blend_depth_write: true,
+            slice_range: true,
+            missing_params: true,
+        }
+    }
+
+    /// Enable all the rules except the blend order, for phases with
+    /// order-independent transparency.
+    pub fn order_independent() -> Rules {
+        Rules {
+            blend_order: false,
+            .. Rules::all()
+        }
+    }
+}
+
+/// A rule violation, with the index of the object in the flush order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Violation {
+    /// Blended object flushed after a farther blended object.
+    BlendOrder(usize),
+    /// Blended object writing depth.
+    BlendDepthWrite(usize),
+    /// Slice going outside of the buffer, with its end
+    /// and the number of available elements.
+    SliceRange(usize, u32, u32),
+    /// Shader parameters that were not filled, with their counts
+    /// of uniforms, blocks, and textures.
+    MissingParams(usize, usize, usize, usize),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Violation::BlendOrder(i) =>
+                write!(f, "object {} is blended but not drawn back-to-front", i),
+            Violation::BlendDepthWrite(i) =>
+                write!(f, "object {} is blended but writes depth", i),
+            Violation::SliceRange(i, end, size) =>
+                write!(f, "object {} slice ends at {} out of {} elements", i, end, size),
+            Violation::MissingParams(i, u, b, t) =>
+                write!(f, "object {} misses {} uniforms, {} blocks, and {} textures", i, u, b, t),
+        }
+    }
+}
+
+fn get_index_count<R: gfx::Resources>(slice: &gfx::Slice<R>) -> Option<u32> {
+    let (raw, size) = match slice.kind {
+        gfx::SliceKind::Vertex => return None,
+        gfx::SliceKind::Index8(ref buf, _) => (buf.raw(), 1),
+        gfx::SliceKind::Index16(ref buf, _) => (buf.raw(), 2),
+        gfx::SliceKind::Index32(ref buf, _) => (buf.raw(), 4),
+    };
+    Some((raw.get_info().size / size) as u32)
+}
+
+/// Check the objects, given in the flush order, against the rules.
+/// Consecutive blended objects must have non-increasing depth, whichever
+/// way they got sorted.
+pub fn validate<'a, S: 'a, K: 'a, P, I>(objects: I, rules: &Rules)
+                -> Vec<Violation> where
+    S: Copy + PartialOrd,
+    P: gfx::shade::ShaderParam + 'a,
+    I: Iterator<Item = &'a Object<S, K, P>>,
+{
+    use gfx::Batch;
+    let mut last_blend_depth: Option<S> = None;
+    let mut violations = Vec::new();
+    for (i, o) in objects.enumerate() {
+        let blend = o.state.blend.is_some();
+        if rules.blend_order && blend {
+            if let Some(d) = last_blend_depth {
+                if o.depth > d {
+                    violations.push(Violation::BlendOrder(i));
+                }
+            }
+            last_blend_depth = Some(o.depth);
+        }
+        if rules.blend_depth_write && blend && o.state.depth.map_or(false, |d| d.write) {
+            violations.push(Violation::BlendDepthWrite(i));
+        }
+        if rules.slice_range {
+            let size = match get_index_count(&o.slice) {
+                Some(count) => count,
+                None => o.batch.mesh().num_vertices,
+            };
+            if o.slice.end > size {
+                violations.push(Violation::SliceRange(i, o.slice.end, size));
+            }
+        }
+        if rules.missing_params {
+            let mut storage = gfx::ParamStorage::new();
+            if let Ok(program) = o.with(&o.state).fill_params(&mut storage) {
+                let info = program.get_info();
+                let u = info.uniforms.len().saturating_sub(storage.uniforms.len());
+                let b = info.blocks.len().saturating_sub(storage.blocks.len());
+                let t = info.textures.len().saturating_sub(storage.textures.len());
+                if u + b + t > 0 {
+                    violations.push(Violation::MissingParams(i, u, b, t));
+                }
+            }
+        }
+    }
+    violations
+}
+
+#[cfg(test)]
+mod test {
+    use gfx;
+    use gfx::device::shade::{ProgramInfo, ParameterError};
+    use gfx_mock;
+    use phase::Object;
+    use test::{make_factory, make_object, make_resources};
+    use super::{Rules, Violation, validate};
+
+    /// Parameters that provide nothing to the program.
+    struct Empty;
+
+    impl gfx::shade::ShaderParam for Empty {
+        type Resources = gfx_mock::Resources;
+        type Link = ();
+        fn create_link(_: Option<&Empty>, _: &ProgramInfo) -> Result<(), ParameterError> {
+            Ok(())
+        }
+        fn fill_params(&self, _: &(), _: &mut gfx::ParamStorage<gfx_mock::Resources>) {}
+    }
+
+    fn blended() -> gfx::DrawState {
+        gfx::DrawState::new().blend(gfx::BlendPreset::Alpha)
+    }
+
+    #[test]
+    fn clean() {
+        let mut factory = make_factory();
+        let (program, texture) = make_resources(&mut factory);
+        let objects = [
+            make_object(&program, &texture, [1.0; 4], [0, 3], 1.0,
+                        gfx::DrawState::new().depth(gfx::state::Comparison::LessEqual, true)),
+            make_object(&program, &texture, [1.0; 4], [3, 6], 2.0, blended()),
+            make_object(&program, &texture, [1.0; 4], [0, 8], 1.0, blended()),
+        ];
+        assert!(validate(objects.iter(), &Rules::all()).is_empty());
+    }
+
+    #[test]
+    fn blend_order() {
+        let mut factory = make_factory();
+        let (program, texture) = make_resources(&mut factory);
+        // opaque objects don't count
+        let objects = [
+            make_object(&program, &texture, [1.0; 4], [0, 3], 1.0, blended()),
+            make_object(&program, &texture, [1.0; 4], [0, 3], 5.0, gfx::DrawState::new()),
+            make_object(&program, &texture, [1.0; 4], [0, 3], 2.0, blended()),
+        ];
+        assert_eq!(validate(objects.iter(), &Rules::all()), vec![Violation::BlendOrder(2)]);
+        assert!(validate(objects.iter(), &Rules::order_independent()).is_empty());
+    }
+
+    #[test]
+    fn blend_depth_write() {
+        let mut factory = make_factory();
+        let (program, texture) = make_resources(&mut factory);
+        let state = blended().depth(gfx::state::Comparison::LessEqual, true);
+        let objects = [
+            make_object(&program, &texture, [1.0; 4], [0, 3], 1.0, state),
+        ];
+        assert_eq!(validate(objects.iter(), &Rules::all()), vec![Violation::BlendDepthWrite(0)]);
+    }
+
+    #[test]
+    fn slice_range() {
+        let mut factory = make_factory();
+        let (program, texture) = make_resources(&mut factory);
+        let objects = [
+            make_object(&program, &texture, [1.0; 4], [0, 3], 1.0, gfx::DrawState::new()),
+            make_object(&program, &texture, [1.0; 4], [6, 9], 1.0, gfx::DrawState::new()),
+        ];
+        assert_eq!(validate(objects.iter(), &Rules::all()), vec![Violation::SliceRange(1, 9, 8)]);
+    }
+
+    #[test]
+    fn missing_params() {
+        let mut factory = make_factory();
+        let (program, _) = make_resources(&mut factory);
+        let object: Object<f32, (), Empty> = Object {
+            batch: gfx::batch::Core::new(gfx::Mesh::new(8), program).unwrap(),
+            params: Empty,
+            slice: gfx::Slice {
+                start: 0,
+                end: 3,
+                prim_type: gfx::PrimitiveType::TriangleList,
+                kind: gfx::SliceKind::Vertex,
+            },
+            depth: 1.0,
+            kernel: (),
+            state: gfx::DrawState::new(),
+        };
+        // one uniform and one texture of the program are not provided
+        assert_eq!(validate(Some(&object).into_iter(), &Rules::all()),
+                   vec![Violation::MissingParams(0, 1, 0, 1)]);
+        let rules = Rules {
+            missing_params: false,
+            .. Rules::all()
+        };
+        assert!(validate(Some(&object).into_iter(), &rules).is_empty());
+    }
+}
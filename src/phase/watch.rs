@@ -0,0 +1,125 @@
+//! Watching shader sources for changes.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// A set of source files, polled for changes of their contents.
+/// Each file can be associated with the keys of the permutation sets
+/// built from it, e.g. an enum of the techniques.
+///
+/// A typical hot-reload loop calls `poll_affected`, relinks the programs
+/// of each affected technique, and passes the kernels that got new
+/// programs to `Phase::invalidate`, or does both with `Phase::reload`.
+pub struct Watcher<K> {
+    files: Vec<(PathBuf, String, Vec<K>)>,
+}
+
+fn read_file(path: &Path) -> io::Result<String> {
+    let mut source = String::new();
+    let mut file = try!(File::open(path));
+    try!(file.read_to_string(&mut source));
+    Ok(source)
+}
+
+impl<K: Clone + PartialEq> Watcher<K> {
+    /// Create a new empty watcher.
+    pub fn new() -> Watcher<K> {
+        Watcher {
+            files: Vec::new(),
+        }
+    }
+
+    /// Start watching a file, returning its index.
+    pub fn add<P: AsRef<Path>>(&mut self, path: P) -> io::Result<usize> {
+        let path = path.as_ref().to_path_buf();
+        let source = try!(read_file(&path));
+        self.files.push((path, source, Vec::new()));
+        Ok(self.files.len() - 1)
+    }
+
+    /// Mark a permutation set as affected by the changes of a file.
+    /// Returns `false` if there is no file with this index.
+    pub fn affect(&mut self, index: usize, key: K) -> bool {
+        match self.files.get_mut(index) {
+            Some(&mut (_, _, ref mut keys)) => {
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Get the last read source of a file.
+    pub fn get_source(&self, index: usize) -> Option<&str> {
+        self.files.get(index).map(|&(_, ref source, _)| &source[..])
+    }
+
+    /// Re-read all the files, returning the indices of the changed ones.
+    /// Files that fail to read, for example while being saved by
+    /// an editor, keep their previous source.
+    pub fn poll(&mut self) -> Vec<usize> {
+        let mut changed = Vec::new();
+        for (i, &mut (ref path, ref mut source, _)) in self.files.iter_mut().enumerate() {
+            match read_file(path) {
+                Ok(ref s) if *s == *source => (),
+                Ok(s) => {
+                    debug!("Shader source {:?} changed", path);
+                    *source = s;
+                    changed.push(i);
+                },
+                Err(e) => warn!("Failed to read {:?}: {}", path, e),
+            }
+        }
+        changed
+    }
+
+    /// Re-read all the files, returning the keys affected by the changed
+    /// ones, each key listed once.
+    pub fn poll_affected(&mut self) -> Vec<K> {
+        let mut affected = Vec::new();
+        for i in self.poll().into_iter() {
+            for key in self.files[i].2.iter() {
+                if !affected.contains(key) {
+                    affected.push(key.clone());
+                }
+            }
+        }
+        affected
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+    use super::Watcher;
+
+    fn write(name: &str, text: &str) -> ::std::path::PathBuf {
+        let path = env::temp_dir().join(name);
+        File::create(&path).unwrap().write_all(text.as_bytes()).unwrap();
+        path
+    }
+
+    #[test]
+    fn affected() {
+        let a = write("gfx_phase_watch_a.glsl", "a");
+        let b = write("gfx_phase_watch_b.glsl", "b");
+        let mut watcher = Watcher::new();
+        let ia = watcher.add(&a).unwrap();
+        let ib = watcher.add(&b).unwrap();
+        assert!(watcher.affect(ia, "flat"));
+        assert!(watcher.affect(ia, "phong"));
+        assert!(watcher.affect(ib, "phong"));
+        assert!(!watcher.affect(5, "pbr"));
+        assert_eq!(watcher.poll_affected(), Vec::<&str>::new());
+        write("gfx_phase_watch_a.glsl", "aa");
+        write("gfx_phase_watch_b.glsl", "bb");
+        assert_eq!(watcher.poll_affected(), vec!["flat", "phong"]);
+        assert_eq!(watcher.get_source(ib), Some("bb"));
+        assert_eq!(watcher.get_source(5), None);
+    }
+}
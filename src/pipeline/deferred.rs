@@ -0,0 +1,499 @@
+//! Deferred shading pipeline.
+
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use cgmath;
+use gfx;
+use gfx::traits::*;
+use gfx_phase;
+use gfx_phase::{AbstractPhase, CachedPhase, Phase};
+use gfx_scene;
+use gfx_scene::AbstractScene;
+use ViewInfo;
+
+#[allow(missing_docs)]
+mod params {
+    use gfx::shade::TextureParam;
+
+    gfx_vertex!( ProxyVertex {
+        a_Position@ position: [f32; 3],
+    });
+
+    gfx_parameters!( GeometryParams {
+        u_Transform@ transform: [[f32; 4]; 4],
+        u_Model@ model: [[f32; 4]; 4],
+        u_Albedo@ albedo: [f32; 4],
+        u_Specular@ specular: f32,
+    });
+
+    gfx_parameters!( LightParams {
+        u_Transform@ transform: [[f32; 4]; 4],
+        u_InvViewProj@ inv_view_proj: [[f32; 4]; 4],
+        u_ScreenSize@ screen_size: [f32; 2],
+        u_LightPos@ light_pos: [f32; 4],
+        u_LightDir@ light_dir: [f32; 4],
+        u_LightColor@ light_color: [f32; 4],
+        t_Albedo@ albedo: TextureParam<R>,
+        t_Normal@ normal: TextureParam<R>,
+        t_Depth@ depth: TextureParam<R>,
+    });
+}
+
+pub use self::params::{GeometryParams, LightParams};
+use self::params::ProxyVertex;
+
+static GEOMETRY_VERTEX_SRC: &'static [u8] = b"
+    #version 150 core
+    in vec3 a_Position;
+    in vec3 a_Normal;
+    uniform mat4 u_Transform;
+    uniform mat4 u_Model;
+    out vec3 v_Normal;
+    void main() {
+        v_Normal = mat3(u_Model) * a_Normal;
+        gl_Position = u_Transform * vec4(a_Position, 1.0);
+    }
+";
+
+static GEOMETRY_FRAGMENT_SRC: &'static [u8] = b"
+    #version 150 core
+    in vec3 v_Normal;
+    uniform vec4 u_Albedo;
+    uniform float u_Specular;
+    out vec4 o_Albedo;
+    out vec4 o_Normal;
+    void main() {
+        o_Albedo = vec4(u_Albedo.rgb, u_Specular);
+        o_Normal = vec4(0.5 * normalize(v_Normal) + 0.5, 0.0);
+    }
+";
+
+static LIGHT_VERTEX_SRC: &'static [u8] = b"
+    #version 150 core
+    in vec3 a_Position;
+    uniform mat4 u_Transform;
+    void main() {
+        gl_Position = u_Transform * vec4(a_Position, 1.0);
+    }
+";
+
+static LIGHT_FRAGMENT_SRC: &'static [u8] = b"
+    #version 150 core
+    uniform mat4 u_InvViewProj;
+    uniform vec2 u_ScreenSize;
+    uniform vec4 u_LightPos;
+    uniform vec4 u_LightDir;
+    uniform vec4 u_LightColor;
+    uniform sampler2D t_Albedo;
+    uniform sampler2D t_Normal;
+    uniform sampler2D t_Depth;
+    out vec4 o_Color;
+    void main() {
+        vec2 tc = gl_FragCoord.xy / u_ScreenSize;
+        vec4 ndc = vec4(tc, texture(t_Depth, tc).x, 1.0) * 2.0 - 1.0;
+        vec4 pos = u_InvViewProj * ndc;
+        pos /= pos.w;
+        vec3 normal = texture(t_Normal, tc).xyz * 2.0 - 1.0;
+        vec3 albedo = texture(t_Albedo, tc).rgb;
+        // w = 0 for directional lights, range otherwise
+        vec3 dir = u_LightPos.w > 0.0 ? u_LightPos.xyz - pos.xyz : -u_LightDir.xyz;
+        float dist = length(dir);
+        dir /= dist;
+        float atten = u_LightPos.w > 0.0 ? clamp(1.0 - dist / u_LightPos.w, 0.0, 1.0) : 1.0;
+        // w = cosine of the spot angle, -1 for non-spot lights
+        if (dot(-dir, u_LightDir.xyz) < u_LightDir.w)
+            atten = 0.0;
+        float diffuse = max(0.0, dot(normal, dir));
+        o_Color = vec4(u_LightColor.rgb * u_LightColor.a * albedo * diffuse * atten, 0.0);
+    }
+";
+
+/// Material that provides the surface properties for the G-buffer.
+pub trait GeometryMaterial: ::Material {
+    /// Get the albedo color.
+    fn get_albedo(&self) -> [f32; 4];
+    /// Get the specular intensity.
+    fn get_specular(&self) -> f32;
+}
+
+/// G-buffer render targets.
+pub struct GBuffer<R: gfx::Resources> {
+    /// Frame to render the geometry into.
+    pub frame: gfx::Frame<R>,
+    /// Albedo color, with the specular intensity in alpha.
+    pub albedo: gfx::handle::Texture<R>,
+    /// World-space normal, packed into the unsigned range.
+    pub normal: gfx::handle::Texture<R>,
+    /// Depth buffer.
+    pub depth: gfx::handle::Texture<R>,
+    /// Point sampler for reading the G-buffer.
+    pub sampler: gfx::handle::Sampler<R>,
+}
+
+impl<R: gfx::Resources> GBuffer<R> {
+    /// Create a new G-buffer of the given size.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F, width: u16, height: u16)
+               -> Result<GBuffer<R>, gfx::tex::TextureError> {
+        let albedo = try!(factory.create_texture_rgba8(width, height));
+        let normal = try!(factory.create_texture_rgba8(width, height));
+        let depth = try!(factory.create_texture_depth_stencil(width, height));
+        let sampler = factory.create_sampler(gfx::tex::SamplerInfo::new(
+            gfx::tex::FilterMethod::Scale, gfx::tex::WrapMode::Clamp));
+        let mut frame = gfx::Frame::new(width, height);
+        frame.colors.push(gfx::Plane::Texture(albedo.clone(), 0, None));
+        frame.colors.push(gfx::Plane::Texture(normal.clone(), 0, None));
+        frame.depth = Some(gfx::Plane::Texture(depth.clone(), 0, None));
+        Ok(GBuffer {
+            frame: frame,
+            albedo: albedo,
+            normal: normal,
+            depth: depth,
+            sampler: sampler,
+        })
+    }
+}
+
+/// Standard technique filling the G-buffer with non-blended materials.
+pub struct GeometryTechnique<R: gfx::Resources, M> {
+    program: gfx::handle::Program<R>,
+    state: gfx::DrawState,
+    _material: PhantomData<M>,
+}
+
+impl<R: gfx::Resources, M> GeometryTechnique<R, M> {
+    /// Create a new geometry technique.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F)
+               -> Result<GeometryTechnique<R, M>, gfx::ProgramError> {
+        let program = try!(factory.link_program(GEOMETRY_VERTEX_SRC, GEOMETRY_FRAGMENT_SRC));
+        Ok(GeometryTechnique {
+            program: program,
+            state: gfx::DrawState::new().depth(gfx::state::Comparison::LessEqual, true),
+            _material: PhantomData,
+        })
+    }
+}
+
+impl<R: gfx::Resources, M: GeometryMaterial> gfx_phase::Technique<R, M, ViewInfo>
+for GeometryTechnique<R, M> {
+    type Kernel = ();
+    type Params = GeometryParams<R>;
+
+    fn test(&self, _: &gfx::Mesh<R>, material: &M) -> Option<()> {
+        match material.get_transparency() {
+            ::Transparency::Blend => None,
+            _ => Some(()),
+        }
+    }
+
+    fn compile<'a>(&'a self, _: (), _: &ViewInfo)
+                   -> gfx_phase::TechResult<'a, R, GeometryParams<R>> {
+        (   &self.program,
+            GeometryParams {
+                transform: [[0.0; 4]; 4],
+                model: [[0.0; 4]; 4],
+                albedo: [0.0; 4],
+                specular: 0.0,
+                _r: PhantomData,
+            },
+            None,
+            &self.state,
+        )
+    }
+
+    fn fix_params(&self, material: &M, view: &ViewInfo, params: &mut GeometryParams<R>) {
+        use cgmath::FixedArray;
+        params.transform = *view.mvp.as_fixed();
+        params.model = *view.model.as_fixed();
+        params.albedo = material.get_albedo();
+        params.specular = material.get_specular();
+    }
+}
+
+/// Material of a light proxy, built by the pipeline for each visible light.
+#[derive(Clone, Copy, Debug)]
+pub struct LightMaterial {
+    /// The light to apply.
+    pub light: gfx_scene::LightInfo<f32>,
+    /// Inverse view-projection of the camera.
+    pub inv_view_proj: cgmath::Matrix4<f32>,
+    /// Size of the target, in pixels.
+    pub screen_size: [f32; 2],
+}
+
+impl gfx_phase::Material for LightMaterial {}
+
+/// Shape of the light proxy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LightVolume {
+    /// Fullscreen quad, for directional lights.
+    Fullscreen,
+    /// A box enclosing the light range.
+    Box,
+}
+
+/// Technique accumulating the light contributions from the G-buffer.
+pub struct LightTechnique<R: gfx::Resources> {
+    program: gfx::handle::Program<R>,
+    fullscreen_state: gfx::DrawState,
+    box_state: gfx::DrawState,
+    albedo: gfx::shade::TextureParam<R>,
+    normal: gfx::shade::TextureParam<R>,
+    depth: gfx::shade::TextureParam<R>,
+}
+
+impl<R: gfx::Resources> LightTechnique<R> {
+    /// Create a new light technique reading from a G-buffer.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F, gbuf: &GBuffer<R>)
+               -> Result<LightTechnique<R>, gfx::ProgramError> {
+        let program = try!(factory.link_program(LIGHT_VERTEX_SRC, LIGHT_FRAGMENT_SRC));
+        let state = gfx::DrawState::new().blend(gfx::BlendPreset::Add);
+        let mut box_state = state;
+        // back faces are drawn, so that the camera can be inside the volume
+        box_state.primitive.method = gfx::state::RasterMethod::Fill(gfx::state::CullFace::Front);
+        let sampler = Some(gbuf.sampler.clone());
+        Ok(LightTechnique {
+            program: program,
+            fullscreen_state: state,
+            box_state: box_state,
+            albedo: (gbuf.albedo.clone(), sampler.clone()),
+            normal: (gbuf.normal.clone(), sampler.clone()),
+            depth: (gbuf.depth.clone(), sampler),
+        })
+    }
+}
+
+impl<R: gfx::Resources> gfx_phase::Technique<R, LightMaterial, ViewInfo>
+for LightTechnique<R> {
+    type Kernel = LightVolume;
+    type Params = LightParams<R>;
+
+    fn test(&self, _: &gfx::Mesh<R>, material: &LightMaterial) -> Option<LightVolume> {
+        Some(match material.light.kind {
+            gfx_scene::LightKind::Directional => LightVolume::Fullscreen,
+            _ => LightVolume::Box,
+        })
+    }
+
+    fn compile<'a>(&'a self, kernel: LightVolume, _: &ViewInfo)
+                   -> gfx_phase::TechResult<'a, R, LightParams<R>> {
+        (   &self.program,
+            LightParams {
+                transform: [[0.0; 4]; 4],
+                inv_view_proj: [[0.0; 4]; 4],
+                screen_size: [0.0; 2],
+                light_pos: [0.0; 4],
+                light_dir: [0.0; 4],
+                light_color: [0.0; 4],
+                albedo: self.albedo.clone(),
+                normal: self.normal.clone(),
+                depth: self.depth.clone(),
+                _r: PhantomData,
+            },
+            None,
+            match kernel {
+                LightVolume::Fullscreen => &self.fullscreen_state,
+                LightVolume::Box => &self.box_state,
+            },
+        )
+    }
+
+    fn fix_params(&self, material: &LightMaterial, view: &ViewInfo,
+                  params: &mut LightParams<R>) {
+        use cgmath::FixedArray;
+        let light = &material.light;
+        let (p, d) = (light.position, light.direction);
+        params.transform = *view.mvp.as_fixed();
+        params.inv_view_proj = *material.inv_view_proj.as_fixed();
+        params.screen_size = material.screen_size;
+        params.light_color = light.color;
+        let (range, spot) = match light.kind {
+            gfx_scene::LightKind::Directional => (0.0, -1.0),
+            gfx_scene::LightKind::Point => (light.range, -1.0),
+            gfx_scene::LightKind::Spot(angle) => (light.range, angle.s.cos()),
+        };
+        params.light_pos = [p.x, p.y, p.z, range];
+        params.light_dir = [d.x, d.y, d.z, spot];
+    }
+}
+
+/// Deferred pipeline. Non-blended materials are rendered into the G-buffer,
+/// then every visible light is accumulated into the output by drawing its
+/// proxy volume, and finally the blended materials are drawn on top,
+/// sorted back-to-front. The lighting technique `L` defaults to
+/// `LightTechnique`, but can be replaced to shade the G-buffer differently.
+pub struct Deferred<R: gfx::Resources, M: ::Material, G, T, L = LightTechnique<R>> where
+    G: gfx_phase::Technique<R, M, ViewInfo>,
+    T: gfx_phase::Technique<R, M, ViewInfo>,
+    L: gfx_phase::Technique<R, LightMaterial, ViewInfo>,
+{
+    /// G-buffer targets.
+    pub gbuffer: GBuffer<R>,
+    /// G-buffer phase.
+    pub geometry: CachedPhase<R, M, ViewInfo, G>,
+    /// Light accumulation phase.
+    pub lighting: CachedPhase<R, LightMaterial, ViewInfo, L>,
+    /// Transparent phase.
+    pub transparent: CachedPhase<R, M, ViewInfo, T>,
+    /// Clear data applied to the G-buffer.
+    pub clear: gfx::ClearData,
+    cube: (gfx::Mesh<R>, gfx::Slice<R>),
+    quad: (gfx::Mesh<R>, gfx::Slice<R>),
+}
+
+impl<R: gfx::Resources, M: ::Material, G, T> Deferred<R, M, G, T> where
+    G: gfx_phase::Technique<R, M, ViewInfo>,
+    T: gfx_phase::Technique<R, M, ViewInfo>,
+    G::Params: Clone,
+    T::Params: Clone,
+    <G::Params as gfx::shade::ShaderParam>::Link: Clone,
+    <T::Params as gfx::shade::ShaderParam>::Link: Clone,
+{
+    /// Create a new deferred pipeline with the given G-buffer, as well as
+    /// the geometry and transparent techniques. Lights are shaded by
+    /// the standard `LightTechnique`.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F, gbuffer: GBuffer<R>,
+               geometry: G, transparent: T)
+               -> Result<Deferred<R, M, G, T>, gfx::ProgramError> {
+        let lighting = try!(LightTechnique::new(factory, &gbuffer));
+        Ok(Deferred::with_lighting(factory, gbuffer, geometry, lighting, transparent))
+    }
+}
+
+impl<R: gfx::Resources, M: ::Material, G, T, L> Deferred<R, M, G, T, L> where
+    G: gfx_phase::Technique<R, M, ViewInfo>,
+    T: gfx_phase::Technique<R, M, ViewInfo>,
+    L: gfx_phase::Technique<R, LightMaterial, ViewInfo>,
+    G::Params: Clone,
+    T::Params: Clone,
+    L::Params: Clone,
+    <G::Params as gfx::shade::ShaderParam>::Link: Clone,
+    <T::Params as gfx::shade::ShaderParam>::Link: Clone,
+    <L::Params as gfx::shade::ShaderParam>::Link: Clone,
+{
+    /// Create a new deferred pipeline with a custom lighting technique,
+    /// which is expected to read from the given G-buffer.
+    pub fn with_lighting<F: gfx::Factory<R>>(factory: &mut F, gbuffer: GBuffer<R>,
+                         geometry: G, lighting: L, transparent: T)
+                         -> Deferred<R, M, G, T, L> {
+        let cube_data: Vec<ProxyVertex> = CUBE_INDICES.iter().map(|&i| {
+            let c = [(i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32];
+            ProxyVertex { position: [c[0] * 2.0 - 1.0, c[1] * 2.0 - 1.0, c[2] * 2.0 - 1.0] }
+        }).collect();
+        let cube = factory.create_mesh(&cube_data);
+        let cube_slice = cube.to_slice(gfx::PrimitiveType::TriangleList);
+        let quad_data = [
+            ProxyVertex { position: [-1.0, -1.0, 0.0] },
+            ProxyVertex { position: [1.0, -1.0, 0.0] },
+            ProxyVertex { position: [-1.0, 1.0, 0.0] },
+            ProxyVertex { position: [1.0, 1.0, 0.0] },
+        ];
+        let quad = factory.create_mesh(&quad_data);
+        let quad_slice = quad.to_slice(gfx::PrimitiveType::TriangleStrip);
+        Deferred {
+            gbuffer: gbuffer,
+            geometry: Phase::new("GBuffer", geometry)
+                            .with_sort(gfx_phase::sort::program_front_to_back)
+                            .with_cache(),
+            lighting: Phase::new("Lighting", lighting)
+                            .with_sort(gfx_phase::sort::program)
+                            .with_cache(),
+            transparent: Phase::new("Transparent", transparent)
+                               .with_sort(gfx_phase::sort::back_to_front)
+                               .with_cache(),
+            clear: gfx::ClearData {
+                color: [0.0, 0.0, 0.0, 0.0],
+                depth: 1.0,
+                stencil: 0,
+            },
+            cube: (cube, cube_slice),
+            quad: (quad, quad_slice),
+        }
+    }
+
+    /// Render the scene from a camera. The `gbuf_stream` has to target
+    /// the frame of the G-buffer, and `stream` the final output, sharing
+    /// the depth with the G-buffer for the transparent objects to be
+    /// occluded. The output color is expected to be cleared by the caller,
+    /// since only the G-buffer is cleared here. Returns the report
+    /// aggregated over the scene phases.
+    pub fn render<W, B, P, S, X>(&mut self,
+                  scene: &gfx_scene::Scene<R, M, W, B, P, ViewInfo>,
+                  camera: &gfx_scene::Camera<P, W::NodePtr>,
+                  gbuf_stream: &mut X, stream: &mut S)
+                  -> Result<gfx_scene::Report, gfx_scene::Error> where
+        W: gfx_scene::World<Scalar = f32>,
+        W::Transform: Into<cgmath::Matrix4<f32>>,
+        B: gfx_scene::SkinBound<f32> + Debug,
+        P: cgmath::Projection<f32> + Clone,
+        S: gfx::Stream<R>,
+        X: gfx::Stream<R>,
+    {
+        use cgmath::Matrix;
+        let mut report = gfx_scene::Report::new();
+        // geometry
+        gbuf_stream.clear(self.clear);
+        report.accumulate(&try!(scene.draw(&mut self.geometry, camera, gbuf_stream)));
+        // lighting
+        let view_proj = try!(camera.get_view_projection(&scene.world));
+        let inv_view_proj = match view_proj.invert() {
+            Some(m) => m,
+            None => return Err(gfx_scene::Error::SingularCamera),
+        };
+        let (width, height) = stream.get_output().get_size();
+        let identity = cgmath::Matrix4::identity();
+        for light in scene.lights.iter() {
+            let info = light.resolve(&scene.world);
+            if !info.is_visible(&view_proj) {
+                continue
+            }
+            let material = LightMaterial {
+                light: info,
+                inv_view_proj: inv_view_proj,
+                screen_size: [width as f32, height as f32],
+            };
+            let (model, mvp, &(ref mesh, ref slice)) = match info.kind {
+                gfx_scene::LightKind::Directional => (identity, identity, &self.quad),
+                _ => {
+                    let (p, r) = (info.position, info.range);
+                    let model = cgmath::Matrix4::new(
+                        r, 0.0, 0.0, 0.0,
+                        0.0, r, 0.0, 0.0,
+                        0.0, 0.0, r, 0.0,
+                        p.x, p.y, p.z, 1.0);
+                    (model, view_proj.mul_m(&model), &self.cube)
+                },
+            };
+            let mut lights = [None; ::MAX_LIGHTS];
+            lights[0] = Some(info);
+            let view_info = ViewInfo {
+                mvp: mvp,
+                view: identity,
+                model: model,
+                bones: Vec::new(),
+                lights: lights,
+            };
+            match self.lighting.enqueue(mesh, slice, &material, &view_info) {
+                Ok(_) => (),
+                Err(e) => return Err(gfx_scene::Error::Batch(e)),
+            }
+        }
+        match self.lighting.flush(stream) {
+            Ok(()) => (),
+            Err(e) => return Err(gfx_scene::Error::Flush(e)),
+        }
+        // transparent
+        report.accumulate(&try!(scene.draw(&mut self.transparent, camera, stream)));
+        Ok(report)
+    }
+}
+
+/// Vertex corners of the unit cube triangles, with the bits
+/// encoding X, Y, and Z.
+static CUBE_INDICES: [u8; 36] = [
+    0, 2, 1, 1, 2, 3, // -Z
+    4, 5, 6, 5, 7, 6, // +Z
+    0, 1, 4, 1, 5, 4, // -Y
+    2, 6, 3, 3, 6, 7, // +Y
+    0, 4, 2, 2, 4, 6, // -X
+    1, 3, 5, 3, 7, 5, // +X
+];
@@ -0,0 +1,333 @@
+//! Forward rendering pipeline.
+
+use std::marker::PhantomData;
+use gfx;
+use gfx::traits::*;
+use gfx_phase;
+use gfx_phase::{CachedPhase, Phase};
+use gfx_scene;
+use gfx_scene::AbstractScene;
+use ViewInfo;
+
+#[allow(missing_docs)]
+mod params {
+    gfx_parameters!( DepthParams {
+        u_Transform@ transform: [[f32; 4]; 4],
+    });
+}
+
+pub use self::params::DepthParams;
+
+static DEPTH_VERTEX_SRC: &'static [u8] = b"
+    #version 150 core
+    in vec3 a_Position;
+    uniform mat4 u_Transform;
+    void main() {
+        gl_Position = u_Transform * vec4(a_Position, 1.0);
+    }
+";
+
+static DEPTH_FRAGMENT_SRC: &'static [u8] = b"
+    #version 150 core
+    void main() {}
+";
+
+/// Depth-only technique, drawing opaque materials without any color output.
+pub struct DepthTechnique<R: gfx::Resources, M> {
+    program: gfx::handle::Program<R>,
+    state: gfx::DrawState,
+    _material: PhantomData<M>,
+}
+
+impl<R: gfx::Resources, M> DepthTechnique<R, M> {
+    /// Create a new depth technique.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F)
+               -> Result<DepthTechnique<R, M>, gfx::ProgramError> {
+        let program = try!(factory.link_program(DEPTH_VERTEX_SRC, DEPTH_FRAGMENT_SRC));
+        let mut state = gfx::DrawState::new()
+            .depth(gfx::state::Comparison::LessEqual, true);
+        state.color_mask = gfx::state::MASK_NONE;
+        Ok(DepthTechnique {
+            program: program,
+            state: state,
+            _material: PhantomData,
+        })
+    }
+
+    /// Apply a slope-scaled depth bias, useful for rendering shadow casters.
+    pub fn with_bias(mut self, slope: f32, units: i32) -> DepthTechnique<R, M> {
+        self.state.primitive.offset = Some(gfx::state::Offset(slope, units));
+        self
+    }
+}
+
+impl<R: gfx::Resources, M: ::Material> gfx_phase::Technique<R, M, ViewInfo>
+for DepthTechnique<R, M> {
+    type Kernel = ();
+    type Params = DepthParams<R>;
+
+    fn test(&self, _: &gfx::Mesh<R>, material: &M) -> Option<()> {
+        match material.get_transparency() {
+            ::Transparency::Opaque => Some(()),
+            _ => None,
+        }
+    }
+
+    fn compile<'a>(&'a self, _: (), _: &ViewInfo)
+                   -> gfx_phase::TechResult<'a, R, DepthParams<R>> {
+        (   &self.program,
+            DepthParams {
+                transform: [[0.0; 4]; 4],
+                _r: PhantomData,
+            },
+            None,
+            &self.state,
+        )
+    }
+
+    fn fix_params(&self, _: &M, view: &ViewInfo, params: &mut DepthParams<R>) {
+        use cgmath::FixedArray;
+        params.transform = *view.mvp.as_fixed();
+    }
+}
+
+/// Forward pipeline. The opaque objects are drawn after an optional depth
+/// pre-pass, sorted by program and then front-to-back, followed by the
+/// transparent objects, sorted back-to-front. The opaque technique is
+/// expected to reject blended materials, and the transparent one to only
+/// accept them.
+pub struct Forward<R: gfx::Resources, M: ::Material, Z, O, T> where
+    Z: gfx_phase::Technique<R, M, ViewInfo>,
+    O: gfx_phase::Technique<R, M, ViewInfo>,
+    T: gfx_phase::Technique<R, M, ViewInfo>,
+{
+    /// Optional depth pre-pass.
+    pub prepass: Option<CachedPhase<R, M, ViewInfo, Z>>,
+    /// Opaque phase.
+    pub opaque: CachedPhase<R, M, ViewInfo, O>,
+    /// Transparent phase.
+    pub transparent: CachedPhase<R, M, ViewInfo, T>,
+    /// Clear data applied before drawing.
+    pub clear: Option<gfx::ClearData>,
+}
+
+impl<R: gfx::Resources, M: ::Material, Z, O, T> Forward<R, M, Z, O, T> where
+    Z: gfx_phase::Technique<R, M, ViewInfo>,
+    O: gfx_phase::Technique<R, M, ViewInfo>,
+    T: gfx_phase::Technique<R, M, ViewInfo>,
+    Z::Params: Clone,
+    O::Params: Clone,
+    T::Params: Clone,
+    <Z::Params as gfx::shade::ShaderParam>::Link: Clone,
+    <O::Params as gfx::shade::ShaderParam>::Link: Clone,
+    <T::Params as gfx::shade::ShaderParam>::Link: Clone,
+{
+    /// Create a new forward pipeline with the given techniques.
+    pub fn new(prepass: Option<Z>, opaque: O, transparent: T)
+               -> Forward<R, M, Z, O, T> {
+        Forward {
+            prepass: prepass.map(|tech|
+                Phase::new("Depth", tech)
+                      .with_sort(gfx_phase::sort::front_to_back)
+                      .with_cache()
+            ),
+            opaque: Phase::new("Opaque", opaque)
+                          .with_sort(gfx_phase::sort::program_front_to_back)
+                          .with_cache(),
+            transparent: Phase::new("Transparent", transparent)
+                               .with_sort(gfx_phase::sort::back_to_front)
+                               .with_cache(),
+            clear: Some(gfx::ClearData {
+                color: [0.0, 0.0, 0.0, 1.0],
+                depth: 1.0,
+                stencil: 0,
+            }),
+        }
+    }
+
+    /// Render the opaque part of the scene from a camera: the depth
+    /// pre-pass and the opaque phase. Useful for replacing the sorted
+    /// transparent phase with `Oit`.
+    pub fn render_opaque<X, S>(&mut self, scene: &X, camera: &X::Camera, stream: &mut S)
+                         -> Result<gfx_scene::Report, gfx_scene::Error> where
+        X: AbstractScene<R, ViewInfo = ViewInfo, Material = M,
+                         Status = gfx_scene::Report>,
+        S: gfx::Stream<R>,
+    {
+        if let Some(data) = self.clear {
+            stream.clear(data);
+        }
+        let mut report = gfx_scene::Report::new();
+        if let Some(ref mut phase) = self.prepass {
+            report.accumulate(&try!(scene.draw(phase, camera, stream)));
+        }
+        report.accumulate(&try!(scene.draw(&mut self.opaque, camera, stream)));
+        Ok(report)
+    }
+
+    /// Render the scene from a camera, returning the report
+    /// aggregated over all the phases.
+    pub fn render<X, S>(&mut self, scene: &X, camera: &X::Camera, stream: &mut S)
+                  -> Result<gfx_scene::Report, gfx_scene::Error> where
+        X: AbstractScene<R, ViewInfo = ViewInfo, Material = M,
+                         Status = gfx_scene::Report>,
+        S: gfx::Stream<R>,
+    {
+        let mut report = try!(self.render_opaque(scene, camera, stream));
+        report.accumulate(&try!(scene.draw(&mut self.transparent, camera, stream)));
+        Ok(report)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::marker::PhantomData;
+    use cgmath;
+    use gfx;
+    use gfx::traits::*;
+    use gfx::device::shade;
+    use gfx_mock;
+    use gfx_phase;
+    use gfx_scene;
+    use {Transparency, ViewInfo};
+    use super::{DepthParams, DepthTechnique, Forward};
+
+    type R = gfx_mock::Resources;
+    type Transform = cgmath::Decomposed<f32, cgmath::Vector3<f32>, cgmath::Quaternion<f32>>;
+
+    struct World(Vec<Transform>);
+
+    impl gfx_scene::World for World {
+        type Scalar = f32;
+        type Transform = Transform;
+        type NodePtr = usize;
+        type SkeletonPtr = ();
+        fn get_transform(&self, node: &usize) -> Transform {
+            self.0[*node].clone()
+        }
+    }
+
+    struct Material(Transparency);
+    impl gfx_phase::Material for Material {}
+    impl ::Material for Material {
+        fn get_transparency(&self) -> Transparency { self.0 }
+    }
+
+    /// Technique accepting the materials of a single transparency.
+    struct Technique {
+        program: gfx::handle::Program<R>,
+        state: gfx::DrawState,
+        transparency: Transparency,
+    }
+
+    impl gfx_phase::Technique<R, Material, ViewInfo> for Technique {
+        type Kernel = ();
+        type Params = DepthParams<R>;
+        fn test(&self, _: &gfx::Mesh<R>, mat: &Material) -> Option<()> {
+            if mat.0 == self.transparency {Some(())} else {None}
+        }
+        fn compile<'a>(&'a self, _: (), _: &ViewInfo)
+                       -> gfx_phase::TechResult<'a, R, DepthParams<R>> {
+            (&self.program, DepthParams { transform: [[0.0; 4]; 4], _r: PhantomData },
+             None, &self.state)
+        }
+        fn fix_params(&self, _: &Material, view: &ViewInfo, params: &mut DepthParams<R>) {
+            use cgmath::FixedArray;
+            params.transform = *view.mvp.as_fixed();
+        }
+    }
+
+    fn translate(z: f32) -> Transform {
+        use cgmath::Transform;
+        let mut t: Transform = Transform::identity();
+        t.disp.z = z;
+        t
+    }
+
+    #[test]
+    fn render() {
+        let mut factory = gfx_mock::Factory::new();
+        factory.program_info.uniforms.push(shade::UniformVar {
+            name: "u_Transform".to_string(),
+            location: 0,
+            count: 1,
+            base_type: shade::BaseType::F32,
+            container: shade::ContainerType::Matrix(shade::MatrixFormat::ColumnMajor, 4, 4),
+        });
+        let opaque = Technique {
+            program: factory.link_program(b"", b"").unwrap(),
+            state: gfx::DrawState::new().depth(gfx::state::Comparison::LessEqual, true),
+            transparency: Transparency::Opaque,
+        };
+        let transparent = Technique {
+            program: factory.link_program(b"", b"").unwrap(),
+            state: gfx::DrawState::new().blend(gfx::BlendPreset::Alpha),
+            transparency: Transparency::Blend,
+        };
+        let (opaque_program, transparent_program) =
+            (opaque.program.clone(), transparent.program.clone());
+        let prepass: DepthTechnique<R, Material> = DepthTechnique::new(&mut factory).unwrap();
+        let mut forward = Forward::new(Some(prepass), opaque, transparent);
+        // entities in front of the camera, which sits at the origin
+        let depths = [(4.0, Transparency::Blend), (2.0, Transparency::Opaque),
+                      (6.0, Transparency::Blend), (3.0, Transparency::Opaque)];
+        let world = World(depths.iter().map(|&(d, _)| translate(-d))
+                                .chain(Some(translate(0.0)).into_iter()).collect());
+        let mut scene: gfx_scene::Scene<R, Material, World, cgmath::Aabb3<f32>,
+                                        cgmath::PerspectiveFov<f32, cgmath::Rad<f32>>,
+                                        ViewInfo> = gfx_scene::Scene::new(world);
+        let slice = gfx::Slice {
+            start: 0,
+            end: 3,
+            prim_type: gfx::PrimitiveType::TriangleList,
+            kind: gfx::SliceKind::Vertex,
+        };
+        for (i, &(_, transparency)) in depths.iter().enumerate() {
+            let bound = cgmath::Aabb3::new(cgmath::Point3::new(-0.5, -0.5, -0.5),
+                                           cgmath::Point3::new(0.5, 0.5, 0.5));
+            let mut ent = gfx_scene::Entity::new(gfx::Mesh::new(3), i, bound);
+            ent.fragments.push(gfx_scene::Fragment::new(Material(transparency), slice.clone()));
+            scene.entities.push(ent);
+        }
+        let camera = gfx_scene::Camera {
+            name: "main".to_string(),
+            projection: cgmath::PerspectiveFov {
+                fovy: cgmath::deg(90.0f32).into(),
+                aspect: 1.0,
+                near: 0.1,
+                far: 100.0,
+            },
+            node: depths.len(),
+            layers: gfx_scene::ALL_LAYERS,
+        };
+        let mut stream = gfx_mock::Stream::new(&mut factory, 100, 100);
+        let report = forward.render(&scene, &camera, &mut stream).unwrap();
+        assert_eq!((report.calls_passed, report.calls_rejected), (6, 6));
+        match stream.commands[0] {
+            gfx_mock::Command::Clear(ref data) => assert_eq!(data.depth, 1.0),
+            gfx_mock::Command::Draw(_) => panic!("Expected a clear first"),
+        }
+        // the depth pre-pass and the opaque objects go front to back,
+        // followed by the transparent objects back to front
+        let golden = [
+            (None, 2.0), (None, 3.0),
+            (Some(&opaque_program), 2.0), (Some(&opaque_program), 3.0),
+            (Some(&transparent_program), 6.0), (Some(&transparent_program), 4.0),
+        ];
+        let draws: Vec<_> = stream.draws().collect();
+        assert_eq!(draws.len(), golden.len());
+        for (call, &(program, depth)) in draws.iter().zip(golden.iter()) {
+            match program {
+                Some(p) => assert_eq!(call.program, *p),
+                None => assert!(call.program != opaque_program &&
+                                call.program != transparent_program),
+            }
+            // the clip W of the entity origin is its distance from the camera
+            match call.uniforms[0] {
+                shade::UniformValue::F32Matrix4(m) => assert_eq!(m[3][3], depth),
+                ref other => panic!("Unexpected uniform {:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,135 @@
+#![deny(missing_docs)]
+
+//! Standard rendering pipelines, built on top of `gfx_phase` and
+//! `gfx_scene`. The pipelines share the view information type and
+//! expect meshes with the vertex attributes of `gfx_scene_load`:
+//! `a_Position`, `a_Normal`, and `a_TexCoord`.
+//!
+//! The material techniques link their programs ahead of time: every
+//! material has to be passed to the `prepare` method of its technique
+//! before it's drawn, or it gets rejected.
+
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate gfx;
+extern crate gfx_phase;
+extern crate gfx_scene;
+extern crate cgmath;
+#[cfg(test)]
+extern crate gfx_mock;
+
+mod deferred;
+mod forward;
+mod material;
+mod oit;
+mod post;
+mod property;
+mod shadow;
+
+pub use self::deferred::{GeometryMaterial, GBuffer, GeometryParams, GeometryTechnique,
+                         LightMaterial, LightVolume, LightParams, LightTechnique, Deferred};
+pub use self::forward::{DepthParams, DepthTechnique, Forward};
+pub use self::material::{Features, COLOR_MAP, NORMAL_MAP, SPECULAR_MAP,
+                         METALLIC_ROUGHNESS_MAP, OCCLUSION_MAP, BLEND,
+                         FlatMaterial, PhongMaterial, PbrMaterial, StandardMaterial, Standard,
+                         FlatParams, PhongParams, PbrParams,
+                         FlatTechnique, PhongTechnique, PbrTechnique, TechniqueError};
+pub use self::oit::{OIT_ACCUMULATE_SRC, OitParams, CompositeParams, OitBuffer, OitTarget,
+                    OitTechnique, Oit};
+pub use self::post::{FULLSCREEN_VERTEX_SRC, Effect, AbstractEffect, draw_effect,
+                      ToneMapParams, ToneMap, ColorGradeParams, ColorGrade,
+                      FxaaParams, Fxaa, BloomParams, Bloom,
+                      PingPong, PostSettings, PostStack};
+pub use self::property::{MAX_FLAGS, Property, PropertyMaterial, PropertyLink, PropertyParams,
+                         PropertyTechnique, PropertyError, DefinitionError,
+                         parse_materials, load_materials};
+pub use self::shadow::{MAX_CASCADES, MAX_SPOT_FOV, ShadowSettings, ShadowError, ShadowInfo,
+                       Shadow, snap_to_texels};
+
+/// Maximum number of lights passed to the view information.
+pub const MAX_LIGHTS: usize = 4;
+
+/// How a material is mixed with the background.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Transparency {
+    /// Fully opaque, writes depth.
+    Opaque,
+    /// Either opaque or discarded, based on the alpha test.
+    Cutout,
+    /// Blended with the background, doesn't write depth.
+    Blend,
+}
+
+/// Material that can be drawn by the standard pipelines.
+pub trait Material: gfx_phase::Material {
+    /// Get the transparency of the material.
+    fn get_transparency(&self) -> Transparency;
+}
+
+/// Standard view information.
+#[derive(Clone, Debug)]
+pub struct ViewInfo {
+    /// Model-view-projection matrix.
+    pub mvp: cgmath::Matrix4<f32>,
+    /// World-to-view matrix.
+    pub view: cgmath::Matrix4<f32>,
+    /// Model-to-world matrix.
+    pub model: cgmath::Matrix4<f32>,
+    /// Bone matrix palette, empty for entities without a skeleton.
+    pub bones: Vec<cgmath::Matrix4<f32>>,
+    /// Lights affecting the entity, most relevant first.
+    pub lights: [Option<gfx_scene::LightInfo<f32>>; MAX_LIGHTS],
+}
+
+impl gfx_phase::ToDepth for ViewInfo {
+    type Depth = f32;
+    fn to_depth(&self) -> f32 {
+        self.mvp.w.z / self.mvp.w.w
+    }
+}
+
+impl<T: cgmath::Transform3<f32> + Into<cgmath::Matrix4<f32>>>
+gfx_scene::ViewInfo<f32, T> for ViewInfo {
+    fn new(mvp: cgmath::Matrix4<f32>, view: T, model: T,
+           bones: &[cgmath::Matrix4<f32>], lights: &[gfx_scene::LightInfo<f32>])
+           -> ViewInfo {
+        let mut info = ViewInfo {
+            mvp: mvp,
+            view: view.into(),
+            model: model.into(),
+            bones: bones.to_vec(),
+            lights: [None; MAX_LIGHTS],
+        };
+        for (slot, light) in info.lights.iter_mut().zip(lights.iter()) {
+            *slot = Some(*light);
+        }
+        info
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath;
+    use gfx_scene;
+
+    #[test]
+    fn view_info() {
+        use cgmath::Transform;
+        let identity: cgmath::Decomposed<f32, cgmath::Vector3<f32>, cgmath::Quaternion<f32>> =
+            Transform::identity();
+        let bones = [cgmath::Matrix4::from_translation(&cgmath::Vector3::new(1.0, 0.0, 0.0)),
+                     cgmath::Matrix4::identity()];
+        let light = gfx_scene::LightInfo {
+            kind: gfx_scene::LightKind::Point,
+            color: [1.0; 4],
+            position: cgmath::Point3::new(0.0, 0.0, 0.0),
+            direction: cgmath::Vector3::new(0.0, 0.0, -1.0),
+            range: 1.0,
+        };
+        let info: ::ViewInfo = gfx_scene::ViewInfo::new(cgmath::Matrix4::identity(),
+            identity.clone(), identity, &bones, &[light]);
+        assert_eq!(info.bones, bones.to_vec());
+        assert!(info.lights[0].is_some() && info.lights[1].is_none());
+    }
+}
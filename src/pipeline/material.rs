@@ -0,0 +1,914 @@
+//! Standard material models and their forward techniques.
+//!
+//! The techniques link a program permutation per set of material
+//! features, and `Technique::test` can't link on its own, since it only
+//! gets a shared reference. So every material has to be passed to the
+//! `prepare` method of its technique once, after the technique is created
+//! or the material is changed, and before the phase draws it:
+//!
+//! ```ignore
+//! let mut tech = PhongTechnique::new(&mut factory).unwrap();
+//! for ent in scene.entities.iter() {
+//!     for frag in ent.fragments.iter() {
+//!         tech.prepare(&mut factory, &frag.material).unwrap();
+//!     }
+//! }
+//! let mut phase = gfx_phase::Phase::new("Main", tech);
+//! ```
+//!
+//! Materials that weren't prepared are rejected by `test` with a warning,
+//! and counted in `Report::calls_rejected` instead of being drawn.
+
+use std::marker::PhantomData;
+use gfx;
+use gfx::traits::*;
+use gfx_phase;
+use gfx_scene;
+use {GeometryMaterial, ShadowInfo, Transparency, ViewInfo, MAX_LIGHTS};
+
+/// Bit set of the material features, used as a technique kernel.
+pub type Features = u8;
+
+/// The material has a color (diffuse, base color) map.
+pub const COLOR_MAP: Features = 1 << 0;
+/// The material has a tangent-space normal map.
+pub const NORMAL_MAP: Features = 1 << 1;
+/// The material has a specular map.
+pub const SPECULAR_MAP: Features = 1 << 2;
+/// The material has a metallic (blue) and roughness (green) map.
+pub const METALLIC_ROUGHNESS_MAP: Features = 1 << 3;
+/// The material has an ambient occlusion map.
+pub const OCCLUSION_MAP: Features = 1 << 4;
+/// The material is blended.
+pub const BLEND: Features = 1 << 7;
+
+/// Unlit material of a single color.
+#[derive(Clone, Debug)]
+pub struct FlatMaterial<R: gfx::Resources> {
+    /// Color.
+    pub color: [f32; 4],
+    /// Optional color map, multiplied by the color.
+    pub color_map: Option<gfx::shade::TextureParam<R>>,
+    /// Transparency mode.
+    pub transparency: Transparency,
+}
+
+/// Blinn-Phong material.
+#[derive(Clone, Debug)]
+pub struct PhongMaterial<R: gfx::Resources> {
+    /// Diffuse color.
+    pub diffuse: [f32; 4],
+    /// Specular color.
+    pub specular: [f32; 3],
+    /// Specular power.
+    pub shininess: f32,
+    /// Optional diffuse map, multiplied by the diffuse color.
+    pub diffuse_map: Option<gfx::shade::TextureParam<R>>,
+    /// Optional normal map.
+    pub normal_map: Option<gfx::shade::TextureParam<R>>,
+    /// Optional specular map, multiplied by the specular color.
+    pub specular_map: Option<gfx::shade::TextureParam<R>>,
+    /// Transparency mode.
+    pub transparency: Transparency,
+}
+
+/// Metallic-roughness PBR material, following the glTF conventions.
+#[derive(Clone, Debug)]
+pub struct PbrMaterial<R: gfx::Resources> {
+    /// Base color.
+    pub base_color: [f32; 4],
+    /// Metalness factor.
+    pub metallic: f32,
+    /// Roughness factor.
+    pub roughness: f32,
+    /// Emitted color.
+    pub emissive: [f32; 3],
+    /// Optional base color map, multiplied by the base color.
+    pub base_color_map: Option<gfx::shade::TextureParam<R>>,
+    /// Optional normal map.
+    pub normal_map: Option<gfx::shade::TextureParam<R>>,
+    /// Optional metallic-roughness map, multiplied by the factors.
+    pub metallic_roughness_map: Option<gfx::shade::TextureParam<R>>,
+    /// Optional ambient occlusion map.
+    pub occlusion_map: Option<gfx::shade::TextureParam<R>>,
+    /// Transparency mode.
+    pub transparency: Transparency,
+}
+
+/// Material that can be drawn by the standard techniques.
+/// Implement this for your own material enum to mix the models.
+pub trait StandardMaterial<R: gfx::Resources> {
+    /// Get the flat material, if any.
+    fn as_flat(&self) -> Option<&FlatMaterial<R>> { None }
+    /// Get the Blinn-Phong material, if any.
+    fn as_phong(&self) -> Option<&PhongMaterial<R>> { None }
+    /// Get the PBR material, if any.
+    fn as_pbr(&self) -> Option<&PbrMaterial<R>> { None }
+}
+
+/// Any of the standard materials.
+#[derive(Clone, Debug)]
+pub enum Standard<R: gfx::Resources> {
+    /// Flat material.
+    Flat(FlatMaterial<R>),
+    /// Blinn-Phong material.
+    Phong(PhongMaterial<R>),
+    /// PBR material.
+    Pbr(PbrMaterial<R>),
+}
+
+impl<R: gfx::Resources> gfx_phase::Material for FlatMaterial<R> {}
+impl<R: gfx::Resources> gfx_phase::Material for PhongMaterial<R> {}
+impl<R: gfx::Resources> gfx_phase::Material for PbrMaterial<R> {}
+impl<R: gfx::Resources> gfx_phase::Material for Standard<R> {}
+
+impl<R: gfx::Resources> ::Material for FlatMaterial<R> {
+    fn get_transparency(&self) -> Transparency { self.transparency }
+}
+impl<R: gfx::Resources> ::Material for PhongMaterial<R> {
+    fn get_transparency(&self) -> Transparency { self.transparency }
+}
+impl<R: gfx::Resources> ::Material for PbrMaterial<R> {
+    fn get_transparency(&self) -> Transparency { self.transparency }
+}
+impl<R: gfx::Resources> ::Material for Standard<R> {
+    fn get_transparency(&self) -> Transparency {
+        match *self {
+            Standard::Flat(ref m) => m.transparency,
+            Standard::Phong(ref m) => m.transparency,
+            Standard::Pbr(ref m) => m.transparency,
+        }
+    }
+}
+
+impl<R: gfx::Resources> StandardMaterial<R> for FlatMaterial<R> {
+    fn as_flat(&self) -> Option<&FlatMaterial<R>> { Some(self) }
+}
+impl<R: gfx::Resources> StandardMaterial<R> for PhongMaterial<R> {
+    fn as_phong(&self) -> Option<&PhongMaterial<R>> { Some(self) }
+}
+impl<R: gfx::Resources> StandardMaterial<R> for PbrMaterial<R> {
+    fn as_pbr(&self) -> Option<&PbrMaterial<R>> { Some(self) }
+}
+impl<R: gfx::Resources> StandardMaterial<R> for Standard<R> {
+    fn as_flat(&self) -> Option<&FlatMaterial<R>> {
+        match *self { Standard::Flat(ref m) => Some(m), _ => None }
+    }
+    fn as_phong(&self) -> Option<&PhongMaterial<R>> {
+        match *self { Standard::Phong(ref m) => Some(m), _ => None }
+    }
+    fn as_pbr(&self) -> Option<&PbrMaterial<R>> {
+        match *self { Standard::Pbr(ref m) => Some(m), _ => None }
+    }
+}
+
+impl<R: gfx::Resources> GeometryMaterial for FlatMaterial<R> {
+    fn get_albedo(&self) -> [f32; 4] { self.color }
+    fn get_specular(&self) -> f32 { 0.0 }
+}
+impl<R: gfx::Resources> GeometryMaterial for PhongMaterial<R> {
+    fn get_albedo(&self) -> [f32; 4] { self.diffuse }
+    fn get_specular(&self) -> f32 {
+        (self.specular[0] + self.specular[1] + self.specular[2]) / 3.0
+    }
+}
+impl<R: gfx::Resources> GeometryMaterial for PbrMaterial<R> {
+    fn get_albedo(&self) -> [f32; 4] { self.base_color }
+    fn get_specular(&self) -> f32 { 1.0 - self.roughness }
+}
+impl<R: gfx::Resources> GeometryMaterial for Standard<R> {
+    fn get_albedo(&self) -> [f32; 4] {
+        match *self {
+            Standard::Flat(ref m) => m.get_albedo(),
+            Standard::Phong(ref m) => m.get_albedo(),
+            Standard::Pbr(ref m) => m.get_albedo(),
+        }
+    }
+    fn get_specular(&self) -> f32 {
+        match *self {
+            Standard::Flat(ref m) => m.get_specular(),
+            Standard::Phong(ref m) => m.get_specular(),
+            Standard::Pbr(ref m) => m.get_specular(),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+mod params {
+    use gfx::shade::TextureParam;
+
+    gfx_parameters!( FlatParams {
+        u_Transform@ transform: [[f32; 4]; 4],
+        u_Color@ color: [f32; 4],
+        t_Color@ color_map: TextureParam<R>,
+    });
+
+    gfx_parameters!( PhongParams {
+        u_Transform@ transform: [[f32; 4]; 4],
+        u_Model@ model: [[f32; 4]; 4],
+        u_CameraPos@ camera_pos: [f32; 4],
+        u_LightPos@ light_pos: [[f32; 4]; 4],
+        u_LightDir@ light_dir: [[f32; 4]; 4],
+        u_LightColor@ light_color: [[f32; 4]; 4],
+        u_View@ view: [[f32; 4]; 4],
+        u_ShadowMatrix0@ shadow_matrix0: [[f32; 4]; 4],
+        u_ShadowMatrix1@ shadow_matrix1: [[f32; 4]; 4],
+        u_ShadowMatrix2@ shadow_matrix2: [[f32; 4]; 4],
+        u_ShadowMatrix3@ shadow_matrix3: [[f32; 4]; 4],
+        u_ShadowSplits@ shadow_splits: [f32; 4],
+        u_ShadowLight@ shadow_light: i32,
+        t_Shadow@ shadow_map: TextureParam<R>,
+        u_Diffuse@ diffuse: [f32; 4],
+        u_Specular@ specular: [f32; 4],
+        t_Diffuse@ diffuse_map: TextureParam<R>,
+        t_Normal@ normal_map: TextureParam<R>,
+        t_Specular@ specular_map: TextureParam<R>,
+    });
+
+    gfx_parameters!( PbrParams {
+        u_Transform@ transform: [[f32; 4]; 4],
+        u_Model@ model: [[f32; 4]; 4],
+        u_CameraPos@ camera_pos: [f32; 4],
+        u_LightPos@ light_pos: [[f32; 4]; 4],
+        u_LightDir@ light_dir: [[f32; 4]; 4],
+        u_LightColor@ light_color: [[f32; 4]; 4],
+        u_View@ view: [[f32; 4]; 4],
+        u_ShadowMatrix0@ shadow_matrix0: [[f32; 4]; 4],
+        u_ShadowMatrix1@ shadow_matrix1: [[f32; 4]; 4],
+        u_ShadowMatrix2@ shadow_matrix2: [[f32; 4]; 4],
+        u_ShadowMatrix3@ shadow_matrix3: [[f32; 4]; 4],
+        u_ShadowSplits@ shadow_splits: [f32; 4],
+        u_ShadowLight@ shadow_light: i32,
+        t_Shadow@ shadow_map: TextureParam<R>,
+        u_BaseColor@ base_color: [f32; 4],
+        u_MetallicRoughness@ metallic_roughness: [f32; 4],
+        u_Emissive@ emissive: [f32; 4],
+        t_BaseColor@ base_color_map: TextureParam<R>,
+        t_Normal@ normal_map: TextureParam<R>,
+        t_MetallicRoughness@ metallic_roughness_map: TextureParam<R>,
+        t_Occlusion@ occlusion_map: TextureParam<R>,
+    });
+}
+
+pub use self::params::{FlatParams, PhongParams, PbrParams};
+
+static VERTEX_SRC: &'static str = "
+    in vec3 a_Position;
+    in vec3 a_Normal;
+    in vec2 a_TexCoord;
+    uniform mat4 u_Transform;
+    uniform mat4 u_Model;
+    out vec3 v_World;
+    out vec3 v_Normal;
+    out vec2 v_TexCoord;
+    void main() {
+        v_World = (u_Model * vec4(a_Position, 1.0)).xyz;
+        v_Normal = mat3(u_Model) * a_Normal;
+        v_TexCoord = a_TexCoord;
+        gl_Position = u_Transform * vec4(a_Position, 1.0);
+    }
+";
+
+static FLAT_FRAGMENT_SRC: &'static str = "
+    in vec2 v_TexCoord;
+    uniform vec4 u_Color;
+    uniform sampler2D t_Color;
+    out vec4 o_Color;
+    void main() {
+        o_Color = u_Color;
+    #ifdef HAS_COLOR_MAP
+        o_Color *= texture(t_Color, v_TexCoord);
+    #endif
+    }
+";
+
+/// Shared lighting helpers: light vectors and the normal perturbation.
+static LIGHTING_SRC: &'static str = "
+    in vec3 v_World;
+    in vec3 v_Normal;
+    in vec2 v_TexCoord;
+    uniform vec4 u_CameraPos;
+    // columns are lights: xyz = position, w = range (0 for directional)
+    uniform mat4 u_LightPos;
+    // xyz = direction, w = cosine of the spot angle (-1 if not a spot)
+    uniform mat4 u_LightDir;
+    // rgb = color, a = intensity
+    uniform mat4 u_LightColor;
+    uniform sampler2D t_Normal;
+
+    vec3 get_normal() {
+        vec3 n = normalize(v_Normal);
+    #ifdef HAS_NORMAL_MAP
+        vec3 dp1 = dFdx(v_World), dp2 = dFdy(v_World);
+        vec2 duv1 = dFdx(v_TexCoord), duv2 = dFdy(v_TexCoord);
+        vec3 t = normalize(dp1 * duv2.y - dp2 * duv1.y);
+        vec3 b = normalize(cross(n, t));
+        vec3 tn = texture(t_Normal, v_TexCoord).xyz * 2.0 - 1.0;
+        n = normalize(mat3(t, b, n) * tn);
+    #endif
+        return n;
+    }
+
+    // returns the direction to the light, and the attenuated radiance in w
+    vec4 get_light(int i) {
+        vec4 pos = u_LightPos[i], dir = u_LightDir[i];
+        if (pos.w == 0.0)
+            return vec4(-dir.xyz, 1.0);
+        vec3 v = pos.xyz - v_World;
+        float dist = length(v);
+        v /= dist;
+        float atten = clamp(1.0 - dist / pos.w, 0.0, 1.0);
+        if (dot(-v, dir.xyz) < dir.w)
+            atten = 0.0;
+        return vec4(v, atten);
+    }
+
+    uniform mat4 u_View;
+    // world-to-shadow-clip matrices of the cascades
+    uniform mat4 u_ShadowMatrix0, u_ShadowMatrix1, u_ShadowMatrix2, u_ShadowMatrix3;
+    // far view distance of each cascade, 0 if unused
+    uniform vec4 u_ShadowSplits;
+    // index of the shadowed light, -1 if there is none
+    uniform int u_ShadowLight;
+    uniform sampler2DArray t_Shadow;
+    const float SHADOW_BIAS = 0.002;
+
+    // returns 0 if the light is occluded, 1 otherwise
+    float get_shadow(int i) {
+        if (i != u_ShadowLight)
+            return 1.0;
+        float depth = -(u_View * vec4(v_World, 1.0)).z;
+        int cascade = 0;
+        mat4 m = u_ShadowMatrix0;
+        if (depth > u_ShadowSplits.x && u_ShadowSplits.y > 0.0) {
+            cascade = 1; m = u_ShadowMatrix1;
+        }
+        if (depth > u_ShadowSplits.y && u_ShadowSplits.z > 0.0) {
+            cascade = 2; m = u_ShadowMatrix2;
+        }
+        if (depth > u_ShadowSplits.z && u_ShadowSplits.w > 0.0) {
+            cascade = 3; m = u_ShadowMatrix3;
+        }
+        vec4 pos = m * vec4(v_World, 1.0);
+        vec3 tc = pos.xyz / pos.w * 0.5 + 0.5;
+        if (any(lessThan(tc, vec3(0.0))) || any(greaterThan(tc, vec3(1.0))))
+            return 1.0;
+        float occluder = texture(t_Shadow, vec3(tc.xy, float(cascade))).r;
+        return tc.z - SHADOW_BIAS > occluder ? 0.0 : 1.0;
+    }
+";
+
+static PHONG_FRAGMENT_SRC: &'static str = "
+    uniform vec4 u_Diffuse;
+    uniform vec4 u_Specular;
+    uniform sampler2D t_Diffuse;
+    uniform sampler2D t_Specular;
+    out vec4 o_Color;
+    void main() {
+        vec4 diffuse = u_Diffuse;
+    #ifdef HAS_COLOR_MAP
+        diffuse *= texture(t_Diffuse, v_TexCoord);
+    #endif
+        vec3 specular = u_Specular.rgb;
+    #ifdef HAS_SPECULAR_MAP
+        specular *= texture(t_Specular, v_TexCoord).rgb;
+    #endif
+        vec3 n = get_normal();
+        vec3 e = normalize(u_CameraPos.xyz - v_World);
+        vec3 color = vec3(0.0);
+        for (int i = 0; i < 4; ++i) {
+            vec4 l = get_light(i);
+            l.w *= get_shadow(i);
+            vec3 radiance = u_LightColor[i].rgb * u_LightColor[i].a * l.w;
+            vec3 h = normalize(l.xyz + e);
+            color += radiance * (diffuse.rgb * max(0.0, dot(n, l.xyz)) +
+                specular * pow(max(0.0, dot(n, h)), u_Specular.a));
+        }
+        o_Color = vec4(color, diffuse.a);
+    }
+";
+
+static PBR_FRAGMENT_SRC: &'static str = "
+    uniform vec4 u_BaseColor;
+    // x = metallic, y = roughness
+    uniform vec4 u_MetallicRoughness;
+    uniform vec4 u_Emissive;
+    uniform sampler2D t_BaseColor;
+    uniform sampler2D t_MetallicRoughness;
+    uniform sampler2D t_Occlusion;
+    out vec4 o_Color;
+    const float PI = 3.14159265;
+    void main() {
+        vec4 base = u_BaseColor;
+    #ifdef HAS_COLOR_MAP
+        base *= texture(t_BaseColor, v_TexCoord);
+    #endif
+        float metallic = u_MetallicRoughness.x;
+        float roughness = u_MetallicRoughness.y;
+    #ifdef HAS_METALLIC_ROUGHNESS_MAP
+        vec4 mr = texture(t_MetallicRoughness, v_TexCoord);
+        metallic *= mr.b;
+        roughness *= mr.g;
+    #endif
+        float alpha = max(roughness * roughness, 0.001);
+        vec3 f0 = mix(vec3(0.04), base.rgb, metallic);
+        vec3 diffuse = base.rgb * (1.0 - metallic) / PI;
+        vec3 n = get_normal();
+        vec3 v = normalize(u_CameraPos.xyz - v_World);
+        float nv = max(dot(n, v), 0.001);
+        vec3 color = vec3(0.0);
+        for (int i = 0; i < 4; ++i) {
+            vec4 l = get_light(i);
+            l.w *= get_shadow(i);
+            vec3 radiance = u_LightColor[i].rgb * u_LightColor[i].a * l.w;
+            vec3 h = normalize(l.xyz + v);
+            float nl = max(dot(n, l.xyz), 0.0);
+            float nh = max(dot(n, h), 0.0);
+            // GGX distribution, Schlick-GGX geometry, Schlick Fresnel
+            float a2 = alpha * alpha;
+            float d = nh * nh * (a2 - 1.0) + 1.0;
+            float ndf = a2 / (PI * d * d);
+            float k = alpha * 0.5;
+            float g = nl / (nl * (1.0 - k) + k) * nv / (nv * (1.0 - k) + k);
+            vec3 f = f0 + (1.0 - f0) * pow(1.0 - max(dot(h, v), 0.0), 5.0);
+            vec3 spec = ndf * g * f / (4.0 * nl * nv + 0.001);
+            color += radiance * nl * ((1.0 - f) * diffuse + spec);
+        }
+    #ifdef HAS_OCCLUSION_MAP
+        color *= texture(t_Occlusion, v_TexCoord).r;
+    #endif
+        o_Color = vec4(color + u_Emissive.rgb, base.a);
+    }
+";
+
+/// Collect the lights of the view into the matrix columns, as expected
+/// by the shaders: positions, directions, and colors.
+fn get_lights(view: &ViewInfo) -> [[[f32; 4]; 4]; 3] {
+    let mut out = [[[0.0; 4]; 4]; 3];
+    for (i, light) in view.lights.iter().take(MAX_LIGHTS).enumerate() {
+        let light = match *light {
+            Some(ref l) => l,
+            None => continue,
+        };
+        let (p, d) = (light.position, light.direction);
+        let (range, spot) = match light.kind {
+            gfx_scene::LightKind::Directional => (0.0, -1.0),
+            gfx_scene::LightKind::Point => (light.range, -1.0),
+            gfx_scene::LightKind::Spot(angle) => (light.range, angle.s.cos()),
+        };
+        out[0][i] = [p.x, p.y, p.z, range];
+        out[1][i] = [d.x, d.y, d.z, spot];
+        out[2][i] = light.color;
+    }
+    out
+}
+
+/// Get the world-space camera position from the view.
+fn get_camera_pos(view: &ViewInfo) -> [f32; 4] {
+    use cgmath::Matrix;
+    match view.view.invert() {
+        Some(m) => [m.w.x, m.w.y, m.w.z, 1.0],
+        None => [0.0, 0.0, 0.0, 1.0],
+    }
+}
+
+fn get_features(transparency: Transparency, maps: &[(Features, bool)]) -> Features {
+    maps.iter().fold(match transparency {
+        Transparency::Blend => BLEND,
+        _ => 0,
+    }, |f, &(bit, present)| if present {f | bit} else {f})
+}
+
+fn get_flat_features<R: gfx::Resources>(m: &FlatMaterial<R>) -> Features {
+    get_features(m.transparency, &[
+        (COLOR_MAP, m.color_map.is_some()),
+    ])
+}
+
+fn get_phong_features<R: gfx::Resources>(m: &PhongMaterial<R>) -> Features {
+    get_features(m.transparency, &[
+        (COLOR_MAP, m.diffuse_map.is_some()),
+        (NORMAL_MAP, m.normal_map.is_some()),
+        (SPECULAR_MAP, m.specular_map.is_some()),
+    ])
+}
+
+fn get_pbr_features<R: gfx::Resources>(m: &PbrMaterial<R>) -> Features {
+    get_features(m.transparency, &[
+        (COLOR_MAP, m.base_color_map.is_some()),
+        (NORMAL_MAP, m.normal_map.is_some()),
+        (METALLIC_ROUGHNESS_MAP, m.metallic_roughness_map.is_some()),
+        (OCCLUSION_MAP, m.occlusion_map.is_some()),
+    ])
+}
+
+/// Error in creating a standard technique.
+#[derive(Debug)]
+pub enum TechniqueError {
+    /// Failed to link a program.
+    Program(gfx::ProgramError),
+    /// Failed to create the default texture.
+    Texture(gfx::tex::TextureError),
+}
+
+impl From<gfx::ProgramError> for TechniqueError {
+    fn from(e: gfx::ProgramError) -> TechniqueError {
+        TechniqueError::Program(e)
+    }
+}
+
+impl From<gfx::tex::TextureError> for TechniqueError {
+    fn from(e: gfx::tex::TextureError) -> TechniqueError {
+        TechniqueError::Texture(e)
+    }
+}
+
+/// Shadow parameters of a view: the index of the shadowed light,
+/// the cascade matrices, and the cascade splits.
+type ShadowParams = (i32, [[[f32; 4]; 4]; 4], [f32; 4]);
+
+/// Program variants and states shared by the standard techniques.
+struct Programs<R: gfx::Resources> {
+    programs: gfx_phase::Permutations<R, Features>,
+    opaque: gfx::DrawState,
+    blend: gfx::DrawState,
+    white: gfx::shade::TextureParam<R>,
+    shadow: Option<(ShadowInfo, gfx::shade::TextureParam<R>)>,
+}
+
+impl<R: gfx::Resources> Programs<R> {
+    fn new<F: gfx::Factory<R>>(factory: &mut F, fragment: &[&str])
+           -> Result<Programs<R>, TechniqueError> {
+        let mut programs = gfx_phase::Permutations::new(
+            &format!("#version 150 core\n{}", VERTEX_SRC),
+            &format!("#version 150 core\n{}", fragment.concat()))
+            .with_define(COLOR_MAP, "HAS_COLOR_MAP")
+            .with_define(NORMAL_MAP, "HAS_NORMAL_MAP")
+            .with_define(SPECULAR_MAP, "HAS_SPECULAR_MAP")
+            .with_define(METALLIC_ROUGHNESS_MAP, "HAS_METALLIC_ROUGHNESS_MAP")
+            .with_define(OCCLUSION_MAP, "HAS_OCCLUSION_MAP")
+            .with_define(BLEND, "BLEND");
+        // only the base permutation is linked up front, to catch
+        // errors in the sources early, the rest are linked by `prepare`
+        if let Err(e) = programs.prepare(factory, 0) {
+            return Err(TechniqueError::Program(e.clone()))
+        }
+        let texture = try!(factory.create_texture_rgba8_static(1, 1, &[0xFFFFFFFF]));
+        Ok(Programs {
+            programs: programs,
+            opaque: gfx::DrawState::new().depth(gfx::state::Comparison::LessEqual, true),
+            blend: gfx::DrawState::new().depth(gfx::state::Comparison::LessEqual, false)
+                                        .blend(gfx::BlendPreset::Alpha),
+            white: (texture, None),
+            shadow: None,
+        })
+    }
+
+    fn prepare<F: gfx::Factory<R>>(&mut self, factory: &mut F, features: Features)
+               -> Result<(), gfx::ProgramError> {
+        match self.programs.prepare(factory, features) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.clone()),
+        }
+    }
+
+    /// Accept the features only if their program is linked.
+    fn test(&self, features: Features) -> Option<Features> {
+        match self.programs.get(features) {
+            Some(Ok(_)) => Some(features),
+            Some(Err(_)) => None, // already logged when linking
+            None => {
+                warn!("Features {:?} are not prepared, rejecting the material", features);
+                None
+            },
+        }
+    }
+
+    fn get(&self, features: Features) -> (&gfx::handle::Program<R>, &gfx::DrawState) {
+        let program = match self.programs.get(features) {
+            Some(Ok(p)) => p,
+            _ => panic!("Features {:?} are not linked, `test` should have rejected them",
+                        features),
+        };
+        (program, if features & BLEND != 0 {&self.blend} else {&self.opaque})
+    }
+
+    fn reload<F: gfx::Factory<R>>(&mut self, factory: &mut F, vertex: &str, fragment: &str)
+              -> Vec<Features> {
+        self.programs.reload(factory, vertex, fragment)
+    }
+
+    fn map(&self, map: &Option<gfx::shade::TextureParam<R>>) -> gfx::shade::TextureParam<R> {
+        match *map {
+            Some(ref t) => t.clone(),
+            None => self.white.clone(),
+        }
+    }
+
+    /// Get the shadow parameters and the shadow map for a view. The light
+    /// index is -1 if the shadowed light doesn't affect the view, in which
+    /// case the map is never sampled, and the white texture stands in.
+    fn get_shadow(&self, view: &ViewInfo) -> (ShadowParams, gfx::shade::TextureParam<R>) {
+        let (info, map) = match self.shadow {
+            Some((ref info, ref map)) => (info, map),
+            None => return ((-1, [[[0.0; 4]; 4]; 4], [0.0; 4]), self.white.clone()),
+        };
+        let index = view.lights.iter().position(|l| match *l {
+            Some(ref l) => l.position == info.light.position &&
+                           l.direction == info.light.direction,
+            None => false,
+        });
+        match index {
+            Some(i) => ((i as i32, info.matrices, info.splits), map.clone()),
+            None => ((-1, info.matrices, info.splits), self.white.clone()),
+        }
+    }
+}
+
+/// Technique for the flat materials. Materials have to go through
+/// `prepare` before drawing, see the module documentation.
+pub struct FlatTechnique<R: gfx::Resources> {
+    programs: Programs<R>,
+}
+
+impl<R: gfx::Resources> FlatTechnique<R> {
+    /// Create a new flat technique.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F)
+               -> Result<FlatTechnique<R>, TechniqueError> {
+        Ok(FlatTechnique {
+            programs: try!(Programs::new(factory, &[FLAT_FRAGMENT_SRC])),
+        })
+    }
+
+    /// Link the program for a material, if it's a flat one. Has to be
+    /// called before drawing with it, since the technique can't link
+    /// on its own.
+    pub fn prepare<F: gfx::Factory<R>, M: StandardMaterial<R>>(&mut self, factory: &mut F,
+                   material: &M) -> Result<(), gfx::ProgramError> {
+        match material.as_flat() {
+            Some(m) => self.programs.prepare(factory, get_flat_features(m)),
+            None => Ok(()),
+        }
+    }
+
+    /// Replace the shader sources, given in full, and relink the prepared
+    /// permutations. Returns the features that got new programs, to be
+    /// invalidated in the phase.
+    pub fn reload<F: gfx::Factory<R>>(&mut self, factory: &mut F,
+                  vertex: &str, fragment: &str) -> Vec<Features> {
+        self.programs.reload(factory, vertex, fragment)
+    }
+}
+
+impl<R: gfx::Resources, M: ::Material + StandardMaterial<R>>
+gfx_phase::Technique<R, M, ViewInfo> for FlatTechnique<R> {
+    type Kernel = Features;
+    type Params = FlatParams<R>;
+
+    fn test(&self, _: &gfx::Mesh<R>, material: &M) -> Option<Features> {
+        material.as_flat().and_then(|m| self.programs.test(get_flat_features(m)))
+    }
+
+    fn compile<'a>(&'a self, kernel: Features, _: &ViewInfo)
+                   -> gfx_phase::TechResult<'a, R, FlatParams<R>> {
+        let (program, state) = self.programs.get(kernel);
+        (   program,
+            FlatParams {
+                transform: [[0.0; 4]; 4],
+                color: [0.0; 4],
+                color_map: self.programs.white.clone(),
+                _r: PhantomData,
+            },
+            None,
+            state,
+        )
+    }
+
+    fn fix_params(&self, material: &M, view: &ViewInfo, params: &mut FlatParams<R>) {
+        use cgmath::FixedArray;
+        let m = material.as_flat().unwrap();
+        params.transform = *view.mvp.as_fixed();
+        params.color = m.color;
+        params.color_map = self.programs.map(&m.color_map);
+    }
+}
+
+/// Technique for the Blinn-Phong materials. Materials have to go through
+/// `prepare` before drawing, see the module documentation.
+pub struct PhongTechnique<R: gfx::Resources> {
+    programs: Programs<R>,
+}
+
+impl<R: gfx::Resources> PhongTechnique<R> {
+    /// Create a new Blinn-Phong technique.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F)
+               -> Result<PhongTechnique<R>, TechniqueError> {
+        Ok(PhongTechnique {
+            programs: try!(Programs::new(factory, &[LIGHTING_SRC, PHONG_FRAGMENT_SRC])),
+        })
+    }
+
+    /// Link the program for a material, if it's a Blinn-Phong one.
+    /// Has to be called before drawing with it.
+    pub fn prepare<F: gfx::Factory<R>, M: StandardMaterial<R>>(&mut self, factory: &mut F,
+                   material: &M) -> Result<(), gfx::ProgramError> {
+        match material.as_phong() {
+            Some(m) => self.programs.prepare(factory, get_phong_features(m)),
+            None => Ok(()),
+        }
+    }
+
+    /// Replace the shader sources, given in full, and relink the prepared
+    /// permutations. Returns the features that got new programs, to be
+    /// invalidated in the phase.
+    pub fn reload<F: gfx::Factory<R>>(&mut self, factory: &mut F,
+                  vertex: &str, fragment: &str) -> Vec<Features> {
+        self.programs.reload(factory, vertex, fragment)
+    }
+
+    /// Set the shadow of a light, with the depth texture array of its
+    /// cascades, or remove it.
+    pub fn set_shadow(&mut self, shadow: Option<(ShadowInfo, gfx::shade::TextureParam<R>)>) {
+        self.programs.shadow = shadow;
+    }
+}
+
+impl<R: gfx::Resources, M: ::Material + StandardMaterial<R>>
+gfx_phase::Technique<R, M, ViewInfo> for PhongTechnique<R> {
+    type Kernel = Features;
+    type Params = PhongParams<R>;
+
+    fn test(&self, _: &gfx::Mesh<R>, material: &M) -> Option<Features> {
+        material.as_phong().and_then(|m| self.programs.test(get_phong_features(m)))
+    }
+
+    fn compile<'a>(&'a self, kernel: Features, _: &ViewInfo)
+                   -> gfx_phase::TechResult<'a, R, PhongParams<R>> {
+        let (program, state) = self.programs.get(kernel);
+        let white = &self.programs.white;
+        (   program,
+            PhongParams {
+                transform: [[0.0; 4]; 4],
+                model: [[0.0; 4]; 4],
+                camera_pos: [0.0; 4],
+                light_pos: [[0.0; 4]; 4],
+                light_dir: [[0.0; 4]; 4],
+                light_color: [[0.0; 4]; 4],
+                view: [[0.0; 4]; 4],
+                shadow_matrix0: [[0.0; 4]; 4],
+                shadow_matrix1: [[0.0; 4]; 4],
+                shadow_matrix2: [[0.0; 4]; 4],
+                shadow_matrix3: [[0.0; 4]; 4],
+                shadow_splits: [0.0; 4],
+                shadow_light: -1,
+                shadow_map: white.clone(),
+                diffuse: [0.0; 4],
+                specular: [0.0; 4],
+                diffuse_map: white.clone(),
+                normal_map: white.clone(),
+                specular_map: white.clone(),
+                _r: PhantomData,
+            },
+            None,
+            state,
+        )
+    }
+
+    fn fix_params(&self, material: &M, view: &ViewInfo, params: &mut PhongParams<R>) {
+        use cgmath::FixedArray;
+        let m = material.as_phong().unwrap();
+        let lights = get_lights(view);
+        params.transform = *view.mvp.as_fixed();
+        params.model = *view.model.as_fixed();
+        params.camera_pos = get_camera_pos(view);
+        params.light_pos = lights[0];
+        params.light_dir = lights[1];
+        params.light_color = lights[2];
+        let ((light, matrices, splits), map) = self.programs.get_shadow(view);
+        params.view = *view.view.as_fixed();
+        params.shadow_matrix0 = matrices[0];
+        params.shadow_matrix1 = matrices[1];
+        params.shadow_matrix2 = matrices[2];
+        params.shadow_matrix3 = matrices[3];
+        params.shadow_splits = splits;
+        params.shadow_light = light;
+        params.shadow_map = map;
+        params.diffuse = m.diffuse;
+        params.specular = [m.specular[0], m.specular[1], m.specular[2], m.shininess];
+        params.diffuse_map = self.programs.map(&m.diffuse_map);
+        params.normal_map = self.programs.map(&m.normal_map);
+        params.specular_map = self.programs.map(&m.specular_map);
+    }
+}
+
+/// Technique for the metallic-roughness PBR materials. Materials have to
+/// go through `prepare` before drawing, see the module documentation.
+pub struct PbrTechnique<R: gfx::Resources> {
+    programs: Programs<R>,
+}
+
+impl<R: gfx::Resources> PbrTechnique<R> {
+    /// Create a new PBR technique.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F)
+               -> Result<PbrTechnique<R>, TechniqueError> {
+        Ok(PbrTechnique {
+            programs: try!(Programs::new(factory, &[LIGHTING_SRC, PBR_FRAGMENT_SRC])),
+        })
+    }
+
+    /// Link the program for a material, if it's a PBR one.
+    /// Has to be called before drawing with it.
+    pub fn prepare<F: gfx::Factory<R>, M: StandardMaterial<R>>(&mut self, factory: &mut F,
+                   material: &M) -> Result<(), gfx::ProgramError> {
+        match material.as_pbr() {
+            Some(m) => self.programs.prepare(factory, get_pbr_features(m)),
+            None => Ok(()),
+        }
+    }
+
+    /// Replace the shader sources, given in full, and relink the prepared
+    /// permutations. Returns the features that got new programs, to be
+    /// invalidated in the phase.
+    pub fn reload<F: gfx::Factory<R>>(&mut self, factory: &mut F,
+                  vertex: &str, fragment: &str) -> Vec<Features> {
+        self.programs.reload(factory, vertex, fragment)
+    }
+
+    /// Set the shadow of a light, with the depth texture array of its
+    /// cascades, or remove it.
+    pub fn set_shadow(&mut self, shadow: Option<(ShadowInfo, gfx::shade::TextureParam<R>)>) {
+        self.programs.shadow = shadow;
+    }
+}
+
+impl<R: gfx::Resources, M: ::Material + StandardMaterial<R>>
+gfx_phase::Technique<R, M, ViewInfo> for PbrTechnique<R> {
+    type Kernel = Features;
+    type Params = PbrParams<R>;
+
+    fn test(&self, _: &gfx::Mesh<R>, material: &M) -> Option<Features> {
+        material.as_pbr().and_then(|m| self.programs.test(get_pbr_features(m)))
+    }
+
+    fn compile<'a>(&'a self, kernel: Features, _: &ViewInfo)
+                   -> gfx_phase::TechResult<'a, R, PbrParams<R>> {
+        let (program, state) = self.programs.get(kernel);
+        let white = &self.programs.white;
+        (   program,
+            PbrParams {
+                transform: [[0.0; 4]; 4],
+                model: [[0.0; 4]; 4],
+                camera_pos: [0.0; 4],
+                light_pos: [[0.0; 4]; 4],
+                light_dir: [[0.0; 4]; 4],
+                light_color: [[0.0; 4]; 4],
+                view: [[0.0; 4]; 4],
+                shadow_matrix0: [[0.0; 4]; 4],
+                shadow_matrix1: [[0.0; 4]; 4],
+                shadow_matrix2: [[0.0; 4]; 4],
+                shadow_matrix3: [[0.0; 4]; 4],
+                shadow_splits: [0.0; 4],
+                shadow_light: -1,
+                shadow_map: white.clone(),
+                base_color: [0.0; 4],
+                metallic_roughness: [0.0; 4],
+                emissive: [0.0; 4],
+                base_color_map: white.clone(),
+                normal_map: white.clone(),
+                metallic_roughness_map: white.clone(),
+                occlusion_map: white.clone(),
+                _r: PhantomData,
+            },
+            None,
+            state,
+        )
+    }
+
+    fn fix_params(&self, material: &M, view: &ViewInfo, params: &mut PbrParams<R>) {
+        use cgmath::FixedArray;
+        let m = material.as_pbr().unwrap();
+        let lights = get_lights(view);
+        params.transform = *view.mvp.as_fixed();
+        params.model = *view.model.as_fixed();
+        params.camera_pos = get_camera_pos(view);
+        params.light_pos = lights[0];
+        params.light_dir = lights[1];
+        params.light_color = lights[2];
+        let ((light, matrices, splits), map) = self.programs.get_shadow(view);
+        params.view = *view.view.as_fixed();
+        params.shadow_matrix0 = matrices[0];
+        params.shadow_matrix1 = matrices[1];
+        params.shadow_matrix2 = matrices[2];
+        params.shadow_matrix3 = matrices[3];
+        params.shadow_splits = splits;
+        params.shadow_light = light;
+        params.shadow_map = map;
+        params.base_color = m.base_color;
+        params.metallic_roughness = [m.metallic, m.roughness, 0.0, 0.0];
+        params.emissive = [m.emissive[0], m.emissive[1], m.emissive[2], 0.0];
+        params.base_color_map = self.programs.map(&m.base_color_map);
+        params.normal_map = self.programs.map(&m.normal_map);
+        params.metallic_roughness_map = self.programs.map(&m.metallic_roughness_map);
+        params.occlusion_map = self.programs.map(&m.occlusion_map);
+    }
+}
@@ -0,0 +1,356 @@
+//! Weighted blended order-independent transparency.
+
+use std::marker::PhantomData;
+use gfx;
+use gfx::traits::*;
+use gfx_phase;
+use gfx_phase::{CachedPhase, Phase};
+use gfx_scene;
+use gfx_scene::AbstractScene;
+use {GeometryMaterial, Transparency, ViewInfo};
+
+#[allow(missing_docs)]
+mod params {
+    use gfx::shade::TextureParam;
+
+    gfx_vertex!( QuadVertex {
+        a_Position@ position: [f32; 2],
+    });
+
+    gfx_parameters!( OitParams {
+        u_Transform@ transform: [[f32; 4]; 4],
+        u_ModelView@ model_view: [[f32; 4]; 4],
+        u_Color@ color: [f32; 4],
+    });
+
+    gfx_parameters!( CompositeParams {
+        t_Accum@ accum: TextureParam<R>,
+        t_Reveal@ reveal: TextureParam<R>,
+    });
+}
+
+pub use self::params::{OitParams, CompositeParams};
+use self::params::QuadVertex;
+
+static OIT_VERTEX_SRC: &'static str = "
+    #version 150 core
+    in vec3 a_Position;
+    uniform mat4 u_Transform;
+    uniform mat4 u_ModelView;
+    out float v_Depth;
+    void main() {
+        v_Depth = -(u_ModelView * vec4(a_Position, 1.0)).z;
+        gl_Position = u_Transform * vec4(a_Position, 1.0);
+    }
+";
+
+/// GLSL chunk of the accumulation output, for custom accumulate techniques:
+/// `oit_accumulate(color, depth)` returns the value to write into the
+/// accumulation target, given a non-premultiplied color and the view depth.
+pub static OIT_ACCUMULATE_SRC: &'static str = "
+    vec4 oit_accumulate(vec4 color, float depth) {
+        float a = color.a;
+        // depth weight from McGuire and Bavoil, 2013
+        float w = a * clamp(0.03 / (1e-5 + pow(depth / 200.0, 4.0)), 1e-2, 3e3);
+        return vec4(color.rgb * a, a) * w;
+    }
+";
+
+static OIT_FRAGMENT_SRC: &'static str = "
+    in float v_Depth;
+    uniform vec4 u_Color;
+    out vec4 o_Color;
+    void main() {
+        float a = u_Color.a;
+    #ifdef ACCUMULATE
+        o_Color = oit_accumulate(u_Color, v_Depth);
+    #else
+        o_Color = vec4(1.0 - a);
+    #endif
+    }
+";
+
+static COMPOSITE_VERTEX_SRC: &'static [u8] = b"
+    #version 150 core
+    in vec2 a_Position;
+    out vec2 v_TexCoord;
+    void main() {
+        v_TexCoord = a_Position * 0.5 + 0.5;
+        gl_Position = vec4(a_Position, 0.0, 1.0);
+    }
+";
+
+static COMPOSITE_FRAGMENT_SRC: &'static [u8] = b"
+    #version 150 core
+    in vec2 v_TexCoord;
+    uniform sampler2D t_Accum;
+    uniform sampler2D t_Reveal;
+    out vec4 o_Color;
+    void main() {
+        vec4 accum = texture(t_Accum, v_TexCoord);
+        float reveal = texture(t_Reveal, v_TexCoord).r;
+        o_Color = vec4(accum.rgb / max(accum.a, 1e-5), 1.0 - reveal);
+    }
+";
+
+/// Accumulation and revealage targets.
+pub struct OitBuffer<R: gfx::Resources> {
+    /// Frame of the accumulation target.
+    pub accum_frame: gfx::Frame<R>,
+    /// Frame of the revealage target.
+    pub reveal_frame: gfx::Frame<R>,
+    /// Sum of the weighted premultiplied colors, and of the weights in alpha.
+    pub accum: gfx::handle::Texture<R>,
+    /// Product of the transparencies.
+    pub reveal: gfx::handle::Texture<R>,
+    /// Sampler for reading the targets.
+    pub sampler: gfx::handle::Sampler<R>,
+}
+
+impl<R: gfx::Resources> OitBuffer<R> {
+    /// Create the targets of the given size, sharing the depth buffer
+    /// of the opaque objects, if any.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F, width: u16, height: u16,
+               depth: Option<gfx::Plane<R>>)
+               -> Result<OitBuffer<R>, gfx::tex::TextureError> {
+        let make_info = |components| gfx::tex::TextureInfo {
+            width: width,
+            height: height,
+            depth: 1,
+            levels: 1,
+            kind: gfx::tex::TextureKind::Texture2D,
+            format: gfx::tex::Format::Float(components, gfx::attrib::FloatSize::F16),
+        };
+        let accum = try!(factory.create_texture(make_info(gfx::tex::Components::RGBA)));
+        let reveal = try!(factory.create_texture(make_info(gfx::tex::Components::R)));
+        let sampler = factory.create_sampler(gfx::tex::SamplerInfo::new(
+            gfx::tex::FilterMethod::Scale, gfx::tex::WrapMode::Clamp));
+        let mut accum_frame = gfx::Frame::new(width, height);
+        accum_frame.colors.push(gfx::Plane::Texture(accum.clone(), 0, None));
+        accum_frame.depth = depth.clone();
+        let mut reveal_frame = gfx::Frame::new(width, height);
+        reveal_frame.colors.push(gfx::Plane::Texture(reveal.clone(), 0, None));
+        reveal_frame.depth = depth;
+        Ok(OitBuffer {
+            accum_frame: accum_frame,
+            reveal_frame: reveal_frame,
+            accum: accum,
+            reveal: reveal,
+            sampler: sampler,
+        })
+    }
+}
+
+/// Which target an OIT technique renders into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OitTarget {
+    /// Weighted color accumulation, blended additively.
+    Accumulate,
+    /// Revealage, blended multiplicatively.
+    Reveal,
+}
+
+impl OitTarget {
+    /// Get the draw state a technique has to use for this target:
+    /// depth test without writes, and the target blending.
+    pub fn get_state(&self) -> gfx::DrawState {
+        let preset = match *self {
+            OitTarget::Accumulate => gfx::BlendPreset::Add,
+            OitTarget::Reveal => gfx::BlendPreset::Multiply,
+        };
+        gfx::DrawState::new().depth(gfx::state::Comparison::LessEqual, false)
+                             .blend(preset)
+    }
+}
+
+/// Technique drawing the blended materials into one of the OIT targets.
+/// The objects don't need any sorting.
+pub struct OitTechnique<R: gfx::Resources, M> {
+    programs: gfx_phase::Permutations<R, u8>,
+    kernel: u8,
+    program: gfx::handle::Program<R>,
+    state: gfx::DrawState,
+    _material: PhantomData<M>,
+}
+
+impl<R: gfx::Resources, M> OitTechnique<R, M> {
+    /// Create a new technique for the given target.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F, target: OitTarget)
+               -> Result<OitTechnique<R, M>, gfx::ProgramError> {
+        let fragment = format!("#version 150 core\n{}{}", OIT_ACCUMULATE_SRC, OIT_FRAGMENT_SRC);
+        let mut programs = gfx_phase::Permutations::new(OIT_VERTEX_SRC, &fragment)
+                                                   .with_define(1u8, "ACCUMULATE");
+        let kernel = match target {
+            OitTarget::Accumulate => 1,
+            OitTarget::Reveal => 0,
+        };
+        let program = match programs.prepare(factory, kernel) {
+            Ok(p) => p.clone(),
+            Err(e) => return Err(e.clone()),
+        };
+        Ok(OitTechnique {
+            programs: programs,
+            kernel: kernel,
+            program: program,
+            state: target.get_state(),
+            _material: PhantomData,
+        })
+    }
+
+    /// Replace the shader sources and relink the program. On failure,
+    /// the previous program is kept. Returns the kernel to invalidate
+    /// in the phase if the program has changed.
+    pub fn reload<F: gfx::Factory<R>>(&mut self, factory: &mut F,
+                  vertex: &str, fragment: &str) -> Vec<()> {
+        if self.programs.reload(factory, vertex, fragment).contains(&self.kernel) {
+            if let Some(Ok(p)) = self.programs.get(self.kernel) {
+                self.program = p.clone();
+            }
+            vec![()]
+        }else {
+            Vec::new()
+        }
+    }
+}
+
+impl<R: gfx::Resources, M: GeometryMaterial> gfx_phase::Technique<R, M, ViewInfo>
+for OitTechnique<R, M> {
+    type Kernel = ();
+    type Params = OitParams<R>;
+
+    fn test(&self, _: &gfx::Mesh<R>, material: &M) -> Option<()> {
+        match material.get_transparency() {
+            Transparency::Blend => Some(()),
+            _ => None,
+        }
+    }
+
+    fn compile<'a>(&'a self, _: (), _: &ViewInfo)
+                   -> gfx_phase::TechResult<'a, R, OitParams<R>> {
+        (   &self.program,
+            OitParams {
+                transform: [[0.0; 4]; 4],
+                model_view: [[0.0; 4]; 4],
+                color: [0.0; 4],
+                _r: PhantomData,
+            },
+            None,
+            &self.state,
+        )
+    }
+
+    fn fix_params(&self, material: &M, view: &ViewInfo, params: &mut OitParams<R>) {
+        use cgmath::{FixedArray, Matrix};
+        params.transform = *view.mvp.as_fixed();
+        params.model_view = *view.view.mul_m(&view.model).as_fixed();
+        params.color = material.get_albedo();
+    }
+}
+
+/// Weighted blended order-independent transparency, a drop-in replacement
+/// of the sorted transparent phase. Draw it after `Forward::render_opaque`.
+/// The accumulate technique `A` defaults to the unlit `OitTechnique`,
+/// and can be replaced by a lit one, which outputs `oit_accumulate` of
+/// `OIT_ACCUMULATE_SRC` using the state of `OitTarget::Accumulate`.
+pub struct Oit<R: gfx::Resources, M: GeometryMaterial, A = OitTechnique<R, M>> where
+    A: gfx_phase::Technique<R, M, ViewInfo>,
+{
+    /// Accumulation and revealage targets.
+    pub buffer: OitBuffer<R>,
+    /// Accumulation phase.
+    pub accumulate: CachedPhase<R, M, ViewInfo, A>,
+    /// Revealage phase.
+    pub reveal: CachedPhase<R, M, ViewInfo, OitTechnique<R, M>>,
+    program: gfx::handle::Program<R>,
+    quad: (gfx::Mesh<R>, gfx::Slice<R>),
+    state: gfx::DrawState,
+}
+
+impl<R: gfx::Resources, M: GeometryMaterial> Oit<R, M> {
+    /// Create a new OIT renderer with the given targets.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F, buffer: OitBuffer<R>)
+               -> Result<Oit<R, M>, gfx::ProgramError> {
+        let accumulate = try!(OitTechnique::new(factory, OitTarget::Accumulate));
+        Oit::with_accumulate(factory, buffer, accumulate)
+    }
+}
+
+impl<R: gfx::Resources, M: GeometryMaterial, A> Oit<R, M, A> where
+    A: gfx_phase::Technique<R, M, ViewInfo>,
+    A::Params: Clone,
+    <A::Params as gfx::shade::ShaderParam>::Link: Clone,
+{
+    /// Create a new OIT renderer with the given targets and
+    /// a custom accumulate technique.
+    pub fn with_accumulate<F: gfx::Factory<R>>(factory: &mut F, buffer: OitBuffer<R>,
+                           accumulate: A) -> Result<Oit<R, M, A>, gfx::ProgramError> {
+        let program = try!(factory.link_program(COMPOSITE_VERTEX_SRC, COMPOSITE_FRAGMENT_SRC));
+        let quad_data = [
+            QuadVertex { position: [-1.0, -1.0] },
+            QuadVertex { position: [1.0, -1.0] },
+            QuadVertex { position: [-1.0, 1.0] },
+            QuadVertex { position: [1.0, 1.0] },
+        ];
+        let quad = factory.create_mesh(&quad_data);
+        let slice = quad.to_slice(gfx::PrimitiveType::TriangleStrip);
+        Ok(Oit {
+            buffer: buffer,
+            accumulate: Phase::new("OIT accumulate", accumulate).with_cache(),
+            reveal: Phase::new("OIT reveal",
+                try!(OitTechnique::new(factory, OitTarget::Reveal))).with_cache(),
+            program: program,
+            quad: (quad, slice),
+            state: gfx::DrawState::new().blend(gfx::BlendPreset::Alpha),
+        })
+    }
+
+    /// Render the transparent objects of the scene into the OIT targets,
+    /// and composite them over the output. The `accum_stream` and
+    /// `reveal_stream` have to target the corresponding frames of the
+    /// buffer. Returns the report aggregated over both phases.
+    pub fn render<X, SA, SR, S>(&mut self, scene: &X, camera: &X::Camera,
+                  accum_stream: &mut SA, reveal_stream: &mut SR, stream: &mut S)
+                  -> Result<gfx_scene::Report, gfx_scene::Error> where
+        X: AbstractScene<R, ViewInfo = ViewInfo, Material = M,
+                         Status = gfx_scene::Report>,
+        SA: gfx::Stream<R>,
+        SR: gfx::Stream<R>,
+        S: gfx::Stream<R>,
+    {
+        let mut report = gfx_scene::Report::new();
+        // only the color is cleared, keeping the depth of opaque objects
+        {
+            let (renderer, output) = accum_stream.access();
+            renderer.clear(gfx::ClearData {
+                color: [0.0; 4],
+                depth: 1.0,
+                stencil: 0,
+            }, gfx::COLOR, output);
+        }
+        report.accumulate(&try!(scene.draw(&mut self.accumulate, camera, accum_stream)));
+        {
+            let (renderer, output) = reveal_stream.access();
+            renderer.clear(gfx::ClearData {
+                color: [1.0; 4],
+                depth: 1.0,
+                stencil: 0,
+            }, gfx::COLOR, output);
+        }
+        report.accumulate(&try!(scene.draw(&mut self.reveal, camera, reveal_stream)));
+        // composite
+        let sampler = Some(self.buffer.sampler.clone());
+        let params = CompositeParams {
+            accum: (self.buffer.accum.clone(), sampler.clone()),
+            reveal: (self.buffer.reveal.clone(), sampler),
+            _r: PhantomData,
+        };
+        let core = match gfx::batch::Core::new(self.quad.0.clone(), self.program.clone()) {
+            Ok(c) => c,
+            Err(e) => return Err(gfx_scene::Error::Batch(e)),
+        };
+        match stream.draw(&core.with(&self.quad.1, &params, &self.state)) {
+            Ok(()) => Ok(report),
+            Err(e) => Err(gfx_scene::Error::Flush(e)),
+        }
+    }
+}
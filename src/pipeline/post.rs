@@ -0,0 +1,624 @@
+//! Post-processing stack of fullscreen effects.
+
+use std::marker::PhantomData;
+use gfx;
+use gfx::traits::*;
+use gfx::shade::TextureParam;
+use gfx_scene;
+
+#[allow(missing_docs)]
+mod params {
+    use gfx::shade::TextureParam;
+
+    gfx_vertex!( QuadVertex {
+        a_Position@ position: [f32; 2],
+    });
+
+    gfx_parameters!( ToneMapParams {
+        t_Source@ source: TextureParam<R>,
+        u_Exposure@ exposure: f32,
+    });
+
+    gfx_parameters!( ColorGradeParams {
+        t_Source@ source: TextureParam<R>,
+        u_ColorMatrix@ color_matrix: [[f32; 4]; 4],
+    });
+
+    gfx_parameters!( FxaaParams {
+        t_Source@ source: TextureParam<R>,
+        u_InvSize@ inv_size: [f32; 2],
+    });
+
+    gfx_parameters!( BrightParams {
+        t_Source@ source: TextureParam<R>,
+        u_Threshold@ threshold: f32,
+    });
+
+    gfx_parameters!( BlurParams {
+        t_Source@ source: TextureParam<R>,
+        u_Step@ step: [f32; 2],
+    });
+
+    gfx_parameters!( BloomParams {
+        t_Source@ source: TextureParam<R>,
+        t_Bloom@ bloom: TextureParam<R>,
+        u_Intensity@ intensity: f32,
+    });
+
+    gfx_parameters!( BlitParams {
+        t_Source@ source: TextureParam<R>,
+    });
+}
+
+pub use self::params::{ToneMapParams, ColorGradeParams, FxaaParams, BloomParams};
+use self::params::{QuadVertex, BrightParams, BlurParams, BlitParams};
+
+/// Vertex shader shared by the fullscreen effects. It provides
+/// the `v_TexCoord` output for sampling the source.
+pub static FULLSCREEN_VERTEX_SRC: &'static [u8] = b"
+    #version 150 core
+    in vec2 a_Position;
+    out vec2 v_TexCoord;
+    void main() {
+        v_TexCoord = a_Position * 0.5 + 0.5;
+        gl_Position = vec4(a_Position, 0.0, 1.0);
+    }
+";
+
+static TONE_MAP_FRAGMENT_SRC: &'static [u8] = b"
+    #version 150 core
+    in vec2 v_TexCoord;
+    uniform sampler2D t_Source;
+    uniform float u_Exposure;
+    out vec4 o_Color;
+    void main() {
+        vec3 color = texture(t_Source, v_TexCoord).rgb * u_Exposure;
+        o_Color = vec4(color / (1.0 + color), 1.0);
+    }
+";
+
+static COLOR_GRADE_FRAGMENT_SRC: &'static [u8] = b"
+    #version 150 core
+    in vec2 v_TexCoord;
+    uniform sampler2D t_Source;
+    uniform mat4 u_ColorMatrix;
+    out vec4 o_Color;
+    void main() {
+        vec4 color = texture(t_Source, v_TexCoord);
+        o_Color = vec4((u_ColorMatrix * vec4(color.rgb, 1.0)).rgb, color.a);
+    }
+";
+
+static FXAA_FRAGMENT_SRC: &'static [u8] = b"
+    #version 150 core
+    in vec2 v_TexCoord;
+    uniform sampler2D t_Source;
+    uniform vec2 u_InvSize;
+    out vec4 o_Color;
+    const vec3 LUMA = vec3(0.299, 0.587, 0.114);
+    const float REDUCE_MIN = 1.0 / 128.0;
+    const float REDUCE_MUL = 1.0 / 8.0;
+    const float SPAN_MAX = 8.0;
+    vec3 fetch(vec2 offset) {
+        return texture(t_Source, v_TexCoord + offset).rgb;
+    }
+    void main() {
+        vec4 center = texture(t_Source, v_TexCoord);
+        float lm = dot(center.rgb, LUMA);
+        float lnw = dot(fetch(vec2(-1.0, -1.0) * u_InvSize), LUMA);
+        float lne = dot(fetch(vec2(1.0, -1.0) * u_InvSize), LUMA);
+        float lsw = dot(fetch(vec2(-1.0, 1.0) * u_InvSize), LUMA);
+        float lse = dot(fetch(vec2(1.0, 1.0) * u_InvSize), LUMA);
+        float lmin = min(lm, min(min(lnw, lne), min(lsw, lse)));
+        float lmax = max(lm, max(max(lnw, lne), max(lsw, lse)));
+        // blur along the edge, which is orthogonal to the luma gradient
+        vec2 dir = vec2(lsw + lse - lnw - lne, lnw + lsw - lne - lse);
+        float reduce = max((lnw + lne + lsw + lse) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+        float scale = 1.0 / (min(abs(dir.x), abs(dir.y)) + reduce);
+        dir = clamp(dir * scale, -SPAN_MAX, SPAN_MAX) * u_InvSize;
+        vec3 a = 0.5 * (fetch(dir * (1.0 / 3.0 - 0.5)) + fetch(dir * (2.0 / 3.0 - 0.5)));
+        vec3 b = 0.5 * a + 0.25 * (fetch(dir * -0.5) + fetch(dir * 0.5));
+        float lb = dot(b, LUMA);
+        o_Color = vec4(lb < lmin || lb > lmax ? a : b, center.a);
+    }
+";
+
+static BRIGHT_FRAGMENT_SRC: &'static [u8] = b"
+    #version 150 core
+    in vec2 v_TexCoord;
+    uniform sampler2D t_Source;
+    uniform float u_Threshold;
+    out vec4 o_Color;
+    void main() {
+        vec3 color = texture(t_Source, v_TexCoord).rgb;
+        o_Color = vec4(max(color - vec3(u_Threshold), vec3(0.0)), 1.0);
+    }
+";
+
+static BLUR_FRAGMENT_SRC: &'static [u8] = b"
+    #version 150 core
+    in vec2 v_TexCoord;
+    uniform sampler2D t_Source;
+    // distance between the taps, in texture coordinates
+    uniform vec2 u_Step;
+    out vec4 o_Color;
+    const float WEIGHTS[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+    void main() {
+        vec3 color = texture(t_Source, v_TexCoord).rgb * WEIGHTS[0];
+        for (int i = 1; i < 5; ++i) {
+            color += texture(t_Source, v_TexCoord + u_Step * float(i)).rgb * WEIGHTS[i];
+            color += texture(t_Source, v_TexCoord - u_Step * float(i)).rgb * WEIGHTS[i];
+        }
+        o_Color = vec4(color, 1.0);
+    }
+";
+
+static BLOOM_FRAGMENT_SRC: &'static [u8] = b"
+    #version 150 core
+    in vec2 v_TexCoord;
+    uniform sampler2D t_Source;
+    uniform sampler2D t_Bloom;
+    uniform float u_Intensity;
+    out vec4 o_Color;
+    void main() {
+        vec4 color = texture(t_Source, v_TexCoord);
+        vec3 bloom = texture(t_Bloom, v_TexCoord).rgb * u_Intensity;
+        o_Color = vec4(color.rgb + bloom, color.a);
+    }
+";
+
+static BLIT_FRAGMENT_SRC: &'static [u8] = b"
+    #version 150 core
+    in vec2 v_TexCoord;
+    uniform sampler2D t_Source;
+    out vec4 o_Color;
+    void main() {
+        o_Color = texture(t_Source, v_TexCoord);
+    }
+";
+
+/// A fullscreen effect, reading the result of the previous one.
+/// Like a technique, it provides the program, the draw state, and
+/// the shader parameters. Putting it into a `PostStack` also needs
+/// an `AbstractEffect` implementation, forwarding to `draw_effect`.
+pub trait Effect<R: gfx::Resources> {
+    /// Shader parameters of the effect.
+    type Params: gfx::shade::ShaderParam<Resources = R>;
+    /// Get the program.
+    fn get_program(&self) -> &gfx::handle::Program<R>;
+    /// Get the draw state.
+    fn get_state(&self) -> &gfx::DrawState;
+    /// Produce the parameters, given the source and the size
+    /// of the target in pixels.
+    fn make_params(&self, source: TextureParam<R>, size: [f32; 2]) -> Self::Params;
+}
+
+/// Abstract effect, hiding the parameter type, so that different
+/// effects can be put into the same stack.
+pub trait AbstractEffect<R: gfx::Resources, S> {
+    /// Draw into the given frame, using the renderer of the stream.
+    fn draw_frame(&self, &TextureParam<R>, &(gfx::Mesh<R>, gfx::Slice<R>),
+                  &mut S, &gfx::Frame<R>) -> Result<(), gfx_scene::Error>;
+    /// Draw into the output of the stream.
+    fn draw_output(&self, &TextureParam<R>, &(gfx::Mesh<R>, gfx::Slice<R>),
+                   &mut S) -> Result<(), gfx_scene::Error>;
+}
+
+/// Draw the fullscreen quad into a frame, or into the output of
+/// the stream if there is no frame.
+fn draw_quad<R, S, P>(stream: &mut S, program: &gfx::handle::Program<R>,
+             quad: &(gfx::Mesh<R>, gfx::Slice<R>), params: &P, state: &gfx::DrawState,
+             frame: Option<&gfx::Frame<R>>) -> Result<(), gfx_scene::Error> where
+    R: gfx::Resources,
+    S: gfx::Stream<R>,
+    P: gfx::shade::ShaderParam<Resources = R>,
+{
+    let core: gfx::batch::Core<P> = match gfx::batch::Core::new(quad.0.clone(), program.clone()) {
+        Ok(c) => c,
+        Err(e) => return Err(gfx_scene::Error::Batch(e)),
+    };
+    let batch = core.with(&quad.1, params, state);
+    match frame {
+        Some(frame) => {
+            let (renderer, _) = stream.access();
+            renderer.draw(&batch, frame)
+        },
+        None => stream.draw(&batch),
+    }.map_err(|e| gfx_scene::Error::Flush(e))
+}
+
+fn get_output_size<R: gfx::Resources, S: gfx::Stream<R>>(stream: &mut S) -> [f32; 2] {
+    let (width, height) = {
+        let (_, output) = stream.access();
+        output.get_size()
+    };
+    [width as f32, height as f32]
+}
+
+/// Draw an effect into a frame, or into the output of the stream if there
+/// is no frame. Custom effects can forward their `AbstractEffect`
+/// implementation to it.
+pub fn draw_effect<R, S, E>(effect: &E, source: &TextureParam<R>,
+                   quad: &(gfx::Mesh<R>, gfx::Slice<R>), stream: &mut S,
+                   frame: Option<&gfx::Frame<R>>) -> Result<(), gfx_scene::Error> where
+    R: gfx::Resources,
+    S: gfx::Stream<R>,
+    E: Effect<R>,
+{
+    let size = match frame {
+        Some(frame) => [frame.width as f32, frame.height as f32],
+        None => get_output_size(stream),
+    };
+    let params = effect.make_params(source.clone(), size);
+    draw_quad(stream, effect.get_program(), quad, &params, effect.get_state(), frame)
+}
+
+macro_rules! impl_abstract_effect {
+    ($($name:ident)*) => {$(
+        impl<R: gfx::Resources, S: gfx::Stream<R>> AbstractEffect<R, S> for $name<R> {
+            fn draw_frame(&self, source: &TextureParam<R>, quad: &(gfx::Mesh<R>, gfx::Slice<R>),
+                          stream: &mut S, frame: &gfx::Frame<R>) -> Result<(), gfx_scene::Error> {
+                draw_effect(self, source, quad, stream, Some(frame))
+            }
+
+            fn draw_output(&self, source: &TextureParam<R>, quad: &(gfx::Mesh<R>, gfx::Slice<R>),
+                           stream: &mut S) -> Result<(), gfx_scene::Error> {
+                draw_effect(self, source, quad, stream, None)
+            }
+        }
+    )*}
+}
+
+/// Tone mapping of HDR colors, using the Reinhard operator.
+pub struct ToneMap<R: gfx::Resources> {
+    program: gfx::handle::Program<R>,
+    state: gfx::DrawState,
+    /// Exposure, applied before the mapping.
+    pub exposure: f32,
+}
+
+impl<R: gfx::Resources> ToneMap<R> {
+    /// Create a new tone mapping effect.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F)
+               -> Result<ToneMap<R>, gfx::ProgramError> {
+        let program = try!(factory.link_program(FULLSCREEN_VERTEX_SRC, TONE_MAP_FRAGMENT_SRC));
+        Ok(ToneMap {
+            program: program,
+            state: gfx::DrawState::new(),
+            exposure: 1.0,
+        })
+    }
+}
+
+impl<R: gfx::Resources> Effect<R> for ToneMap<R> {
+    type Params = ToneMapParams<R>;
+    fn get_program(&self) -> &gfx::handle::Program<R> { &self.program }
+    fn get_state(&self) -> &gfx::DrawState { &self.state }
+    fn make_params(&self, source: TextureParam<R>, _: [f32; 2]) -> ToneMapParams<R> {
+        ToneMapParams {
+            source: source,
+            exposure: self.exposure,
+            _r: PhantomData,
+        }
+    }
+}
+
+/// Color grading by a 4x4 matrix, applied to the RGB color.
+pub struct ColorGrade<R: gfx::Resources> {
+    program: gfx::handle::Program<R>,
+    state: gfx::DrawState,
+    /// Color matrix, column-major.
+    pub matrix: [[f32; 4]; 4],
+}
+
+impl<R: gfx::Resources> ColorGrade<R> {
+    /// Create a new color grading effect with the identity matrix.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F)
+               -> Result<ColorGrade<R>, gfx::ProgramError> {
+        let program = try!(factory.link_program(FULLSCREEN_VERTEX_SRC, COLOR_GRADE_FRAGMENT_SRC));
+        Ok(ColorGrade {
+            program: program,
+            state: gfx::DrawState::new(),
+            matrix: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        })
+    }
+}
+
+impl<R: gfx::Resources> Effect<R> for ColorGrade<R> {
+    type Params = ColorGradeParams<R>;
+    fn get_program(&self) -> &gfx::handle::Program<R> { &self.program }
+    fn get_state(&self) -> &gfx::DrawState { &self.state }
+    fn make_params(&self, source: TextureParam<R>, _: [f32; 2]) -> ColorGradeParams<R> {
+        ColorGradeParams {
+            source: source,
+            color_matrix: self.matrix,
+            _r: PhantomData,
+        }
+    }
+}
+
+/// Fast approximate anti-aliasing, blurring along the detected edges.
+/// The source is expected to be of the target size, with gamma-corrected
+/// colors, so it's best applied after the tone mapping.
+pub struct Fxaa<R: gfx::Resources> {
+    program: gfx::handle::Program<R>,
+    state: gfx::DrawState,
+}
+
+impl<R: gfx::Resources> Fxaa<R> {
+    /// Create a new FXAA effect.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F)
+               -> Result<Fxaa<R>, gfx::ProgramError> {
+        let program = try!(factory.link_program(FULLSCREEN_VERTEX_SRC, FXAA_FRAGMENT_SRC));
+        Ok(Fxaa {
+            program: program,
+            state: gfx::DrawState::new(),
+        })
+    }
+}
+
+impl<R: gfx::Resources> Effect<R> for Fxaa<R> {
+    type Params = FxaaParams<R>;
+    fn get_program(&self) -> &gfx::handle::Program<R> { &self.program }
+    fn get_state(&self) -> &gfx::DrawState { &self.state }
+    fn make_params(&self, source: TextureParam<R>, size: [f32; 2]) -> FxaaParams<R> {
+        FxaaParams {
+            source: source,
+            inv_size: [1.0 / size[0], 1.0 / size[1]],
+            _r: PhantomData,
+        }
+    }
+}
+
+/// Bloom of the bright HDR colors. The parts above the threshold are
+/// extracted into its own targets, usually of a lower resolution, blurred
+/// there, and added back to the source. Put it before the tone mapping.
+pub struct Bloom<R: gfx::Resources> {
+    /// Targets of the extracted and blurred colors.
+    pub targets: PingPong<R>,
+    /// Brightness above which the colors bloom.
+    pub threshold: f32,
+    /// Factor of the blurred colors added to the source.
+    pub intensity: f32,
+    /// Number of the blur passes, each horizontal and vertical.
+    pub passes: usize,
+    bright: gfx::handle::Program<R>,
+    blur: gfx::handle::Program<R>,
+    combine: gfx::handle::Program<R>,
+    state: gfx::DrawState,
+}
+
+impl<R: gfx::Resources> Bloom<R> {
+    /// Create a new bloom effect with the given targets.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F, targets: PingPong<R>)
+               -> Result<Bloom<R>, gfx::ProgramError> {
+        Ok(Bloom {
+            targets: targets,
+            threshold: 1.0,
+            intensity: 0.5,
+            passes: 2,
+            bright: try!(factory.link_program(FULLSCREEN_VERTEX_SRC, BRIGHT_FRAGMENT_SRC)),
+            blur: try!(factory.link_program(FULLSCREEN_VERTEX_SRC, BLUR_FRAGMENT_SRC)),
+            combine: try!(factory.link_program(FULLSCREEN_VERTEX_SRC, BLOOM_FRAGMENT_SRC)),
+            state: gfx::DrawState::new(),
+        })
+    }
+
+    /// Extract and blur the bright colors, leaving them in the first target.
+    fn extract<S: gfx::Stream<R>>(&self, source: &TextureParam<R>,
+               quad: &(gfx::Mesh<R>, gfx::Slice<R>), stream: &mut S)
+               -> Result<TextureParam<R>, gfx_scene::Error> {
+        let frames = &self.targets.frames;
+        let get = |i: usize| (self.targets.textures[i].clone(),
+                              Some(self.targets.sampler.clone()));
+        let bright = BrightParams {
+            source: source.clone(),
+            threshold: self.threshold,
+            _r: PhantomData,
+        };
+        try!(draw_quad(stream, &self.bright, quad, &bright, &self.state, Some(&frames[0])));
+        let (width, height) = (frames[0].width as f32, frames[0].height as f32);
+        for _ in 0 .. self.passes {
+            for &(from, to, step) in [(0, 1, [1.0 / width, 0.0]),
+                                      (1, 0, [0.0, 1.0 / height])].iter() {
+                let params = BlurParams {
+                    source: get(from),
+                    step: step,
+                    _r: PhantomData,
+                };
+                try!(draw_quad(stream, &self.blur, quad, &params, &self.state,
+                               Some(&frames[to])));
+            }
+        }
+        Ok(get(0))
+    }
+
+    fn make_params(&self, source: &TextureParam<R>, bloom: TextureParam<R>) -> BloomParams<R> {
+        BloomParams {
+            source: source.clone(),
+            bloom: bloom,
+            intensity: self.intensity,
+            _r: PhantomData,
+        }
+    }
+}
+
+impl<R: gfx::Resources, S: gfx::Stream<R>> AbstractEffect<R, S> for Bloom<R> {
+    fn draw_frame(&self, source: &TextureParam<R>, quad: &(gfx::Mesh<R>, gfx::Slice<R>),
+                  stream: &mut S, frame: &gfx::Frame<R>) -> Result<(), gfx_scene::Error> {
+        let bloom = try!(self.extract(source, quad, stream));
+        let params = self.make_params(source, bloom);
+        draw_quad(stream, &self.combine, quad, &params, &self.state, Some(frame))
+    }
+
+    fn draw_output(&self, source: &TextureParam<R>, quad: &(gfx::Mesh<R>, gfx::Slice<R>),
+                   stream: &mut S) -> Result<(), gfx_scene::Error> {
+        let bloom = try!(self.extract(source, quad, stream));
+        let params = self.make_params(source, bloom);
+        draw_quad(stream, &self.combine, quad, &params, &self.state, None)
+    }
+}
+
+/// Plain copy of the source, used when all the effects are disabled.
+struct Blit<R: gfx::Resources> {
+    program: gfx::handle::Program<R>,
+    state: gfx::DrawState,
+}
+
+impl<R: gfx::Resources> Effect<R> for Blit<R> {
+    type Params = BlitParams<R>;
+    fn get_program(&self) -> &gfx::handle::Program<R> { &self.program }
+    fn get_state(&self) -> &gfx::DrawState { &self.state }
+    fn make_params(&self, source: TextureParam<R>, _: [f32; 2]) -> BlitParams<R> {
+        BlitParams {
+            source: source,
+            _r: PhantomData,
+        }
+    }
+}
+
+impl_abstract_effect!(ToneMap ColorGrade Fxaa Blit);
+
+/// Pair of render targets the effects alternate between.
+pub struct PingPong<R: gfx::Resources> {
+    /// Frames of the targets.
+    pub frames: [gfx::Frame<R>; 2],
+    /// Color textures of the targets.
+    pub textures: [gfx::handle::Texture<R>; 2],
+    /// Sampler for reading the targets.
+    pub sampler: gfx::handle::Sampler<R>,
+}
+
+impl<R: gfx::Resources> PingPong<R> {
+    /// Create the targets of the given size and format.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F, width: u16, height: u16,
+               format: gfx::tex::Format)
+               -> Result<PingPong<R>, gfx::tex::TextureError> {
+        let info = gfx::tex::TextureInfo {
+            width: width,
+            height: height,
+            depth: 1,
+            levels: 1,
+            kind: gfx::tex::TextureKind::Texture2D,
+            format: format,
+        };
+        let ping = try!(factory.create_texture(info));
+        let pong = try!(factory.create_texture(info));
+        let sampler = factory.create_sampler(gfx::tex::SamplerInfo::new(
+            gfx::tex::FilterMethod::Bilinear, gfx::tex::WrapMode::Clamp));
+        let make_frame = |tex: &gfx::handle::Texture<R>| {
+            let mut frame = gfx::Frame::new(width, height);
+            frame.colors.push(gfx::Plane::Texture(tex.clone(), 0, None));
+            frame
+        };
+        Ok(PingPong {
+            frames: [make_frame(&ping), make_frame(&pong)],
+            textures: [ping, pong],
+            sampler: sampler,
+        })
+    }
+}
+
+/// Per-camera settings of the post-processing stack.
+#[derive(Clone, Debug)]
+pub struct PostSettings {
+    /// Names of the disabled effects.
+    pub disabled: Vec<String>,
+}
+
+impl PostSettings {
+    /// Create new settings with all the effects enabled.
+    pub fn new() -> PostSettings {
+        PostSettings {
+            disabled: Vec::new(),
+        }
+    }
+
+    /// Enable or disable an effect by name.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) {
+        self.disabled.retain(|n| *n != name);
+        if !enabled {
+            self.disabled.push(name.to_string());
+        }
+    }
+
+    /// Check if an effect is enabled.
+    pub fn is_enabled(&self, name: &str) -> bool {
+        !self.disabled.iter().any(|n| *n == name)
+    }
+}
+
+/// Ordered stack of named fullscreen effects.
+pub struct PostStack<R: gfx::Resources, S> {
+    /// Targets for the intermediate results.
+    pub targets: PingPong<R>,
+    /// Named effects, in the order of application.
+    pub effects: Vec<(String, Box<AbstractEffect<R, S>>)>,
+    quad: (gfx::Mesh<R>, gfx::Slice<R>),
+    blit: Blit<R>,
+}
+
+impl<R: gfx::Resources, S: gfx::Stream<R>> PostStack<R, S> {
+    /// Create a new empty stack with the given targets.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F, targets: PingPong<R>)
+               -> Result<PostStack<R, S>, gfx::ProgramError> {
+        let blit = Blit {
+            program: try!(factory.link_program(FULLSCREEN_VERTEX_SRC, BLIT_FRAGMENT_SRC)),
+            state: gfx::DrawState::new(),
+        };
+        let quad_data = [
+            QuadVertex { position: [-1.0, -1.0] },
+            QuadVertex { position: [1.0, -1.0] },
+            QuadVertex { position: [-1.0, 1.0] },
+            QuadVertex { position: [1.0, 1.0] },
+        ];
+        let quad = factory.create_mesh(&quad_data);
+        let slice = quad.to_slice(gfx::PrimitiveType::TriangleStrip);
+        Ok(PostStack {
+            targets: targets,
+            effects: Vec::new(),
+            quad: (quad, slice),
+            blit: blit,
+        })
+    }
+
+    /// Append an effect to the end of the stack.
+    pub fn add<E: AbstractEffect<R, S> + 'static>(&mut self, name: &str, effect: E) {
+        self.effects.push((name.to_string(), Box::new(effect)));
+    }
+
+    /// Apply the enabled effects to the source, writing the result of
+    /// the last one into the output of the stream. The intermediate
+    /// results alternate between the ping-pong targets. Returns the
+    /// number of effects applied. If none is enabled, the source is
+    /// copied to the output as is.
+    pub fn render(&self, source: TextureParam<R>, settings: &PostSettings,
+                  stream: &mut S) -> Result<usize, gfx_scene::Error> {
+        let enabled: Vec<&Box<AbstractEffect<R, S>>> = self.effects.iter()
+            .filter(|&&(ref name, _)| settings.is_enabled(name))
+            .map(|&(_, ref effect)| effect)
+            .collect();
+        if enabled.is_empty() {
+            try!(self.blit.draw_output(&source, &self.quad, stream));
+            return Ok(0)
+        }
+        let mut current = source;
+        for (i, effect) in enabled.iter().enumerate() {
+            if i + 1 == enabled.len() {
+                try!(effect.draw_output(&current, &self.quad, stream));
+            }else {
+                let target = i % 2;
+                try!(effect.draw_frame(&current, &self.quad, stream,
+                                       &self.targets.frames[target]));
+                current = (self.targets.textures[target].clone(),
+                           Some(self.targets.sampler.clone()));
+            }
+        }
+        Ok(enabled.len())
+    }
+}
@@ -0,0 +1,575 @@
+//! Data-driven materials, defined by named properties.
+//!
+//! Like the standard techniques, `PropertyTechnique` links a program
+//! permutation per set of material flags, and has no way to link while
+//! testing a material. Every material has to be passed to
+//! `PropertyTechnique::prepare` before the phase draws it, which also
+//! checks the property types against the shader. Unprepared materials
+//! are rejected with a warning.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::rc::Rc;
+use std::str::FromStr;
+use gfx;
+use gfx::shade::{BaseType, ContainerType, ParameterError, ProgramInfo, TextureParam,
+                 UniformValue, UniformVar};
+use gfx_phase;
+use {Transparency, ViewInfo};
+
+/// A named material property.
+#[derive(Clone, Debug)]
+pub enum Property<R: gfx::Resources> {
+    /// Single float.
+    Float(f32),
+    /// 2-component vector.
+    Vec2([f32; 2]),
+    /// 3-component vector.
+    Vec3([f32; 3]),
+    /// 4-component vector.
+    Vec4([f32; 4]),
+    /// 4x4 matrix.
+    Matrix([[f32; 4]; 4]),
+    /// Texture with an optional sampler.
+    Texture(TextureParam<R>),
+}
+
+impl<R: gfx::Resources> Property<R> {
+    /// Get the uniform value, if it's not a texture.
+    pub fn to_uniform(&self) -> Option<UniformValue> {
+        match *self {
+            Property::Float(v) => Some(UniformValue::F32(v)),
+            Property::Vec2(v) => Some(UniformValue::F32Vector2(v)),
+            Property::Vec3(v) => Some(UniformValue::F32Vector3(v)),
+            Property::Vec4(v) => Some(UniformValue::F32Vector4(v)),
+            Property::Matrix(v) => Some(UniformValue::F32Matrix4(v)),
+            Property::Texture(_) => None,
+        }
+    }
+}
+
+/// Get the zero value of a uniform type, if it's supported.
+fn get_default(var: &UniformVar) -> Option<UniformValue> {
+    Some(match (&var.base_type, &var.container) {
+        (&BaseType::F32, &ContainerType::Single) => UniformValue::F32(0.0),
+        (&BaseType::F32, &ContainerType::Vector(2)) => UniformValue::F32Vector2([0.0; 2]),
+        (&BaseType::F32, &ContainerType::Vector(3)) => UniformValue::F32Vector3([0.0; 3]),
+        (&BaseType::F32, &ContainerType::Vector(4)) => UniformValue::F32Vector4([0.0; 4]),
+        (&BaseType::F32, &ContainerType::Matrix(_, 2, 2)) =>
+            UniformValue::F32Matrix2([[0.0; 2]; 2]),
+        (&BaseType::F32, &ContainerType::Matrix(_, 3, 3)) =>
+            UniformValue::F32Matrix3([[0.0; 3]; 3]),
+        (&BaseType::F32, &ContainerType::Matrix(_, 4, 4)) =>
+            UniformValue::F32Matrix4([[0.0; 4]; 4]),
+        (&BaseType::I32, &ContainerType::Single) => UniformValue::I32(0),
+        (&BaseType::I32, &ContainerType::Vector(2)) => UniformValue::I32Vector2([0; 2]),
+        (&BaseType::I32, &ContainerType::Vector(3)) => UniformValue::I32Vector3([0; 3]),
+        (&BaseType::I32, &ContainerType::Vector(4)) => UniformValue::I32Vector4([0; 4]),
+        _ => return None,
+    })
+}
+
+/// Check if two uniform values are of the same type.
+fn is_same_type(a: &UniformValue, b: &UniformValue) -> bool {
+    match (a, b) {
+        (&UniformValue::I32(_), &UniformValue::I32(_)) |
+        (&UniformValue::I32Vector2(_), &UniformValue::I32Vector2(_)) |
+        (&UniformValue::I32Vector3(_), &UniformValue::I32Vector3(_)) |
+        (&UniformValue::I32Vector4(_), &UniformValue::I32Vector4(_)) |
+        (&UniformValue::F32(_), &UniformValue::F32(_)) |
+        (&UniformValue::F32Vector2(_), &UniformValue::F32Vector2(_)) |
+        (&UniformValue::F32Vector3(_), &UniformValue::F32Vector3(_)) |
+        (&UniformValue::F32Vector4(_), &UniformValue::F32Vector4(_)) |
+        (&UniformValue::F32Matrix2(_), &UniformValue::F32Matrix2(_)) |
+        (&UniformValue::F32Matrix3(_), &UniformValue::F32Matrix3(_)) |
+        (&UniformValue::F32Matrix4(_), &UniformValue::F32Matrix4(_)) => true,
+        _ => false,
+    }
+}
+
+/// Material described by a set of named properties and flags.
+/// Property names match the shader variables they are bound to.
+/// The properties are shared with the shader parameters of the drawn
+/// objects, so changing them makes a copy if they are still in use.
+#[derive(Clone, Debug)]
+pub struct PropertyMaterial<R: gfx::Resources> {
+    /// Material name.
+    pub name: String,
+    /// Transparency mode.
+    pub transparency: Transparency,
+    /// Flags, passed to the shaders as preprocessor symbols.
+    pub flags: Vec<String>,
+    /// Named properties.
+    pub properties: Rc<Vec<(String, Property<R>)>>,
+}
+
+impl<R: gfx::Resources> PropertyMaterial<R> {
+    /// Create a new opaque material without properties.
+    pub fn new(name: &str) -> PropertyMaterial<R> {
+        PropertyMaterial {
+            name: name.to_string(),
+            transparency: Transparency::Opaque,
+            flags: Vec::new(),
+            properties: Rc::new(Vec::new()),
+        }
+    }
+
+    /// Find a property by name.
+    pub fn get(&self, name: &str) -> Option<&Property<R>> {
+        self.properties.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref p)| p)
+    }
+
+    /// Set a property, replacing the old value with the same name.
+    pub fn set(&mut self, name: &str, value: Property<R>) {
+        let mut properties = (*self.properties).clone();
+        match properties.iter().position(|&(ref n, _)| n == name) {
+            Some(i) => properties[i].1 = value,
+            None => properties.push((name.to_string(), value)),
+        }
+        self.properties = Rc::new(properties);
+    }
+}
+
+impl<R: gfx::Resources> gfx_phase::Material for PropertyMaterial<R> {}
+
+impl<R: gfx::Resources> ::Material for PropertyMaterial<R> {
+    fn get_transparency(&self) -> Transparency { self.transparency }
+}
+
+/// Source of a program uniform, resolved at link time.
+#[derive(Clone, Debug)]
+enum Slot {
+    /// One of the view matrices, by index.
+    View(usize),
+    /// Material property by name, with the default value of the uniform type.
+    Property(String, UniformValue),
+}
+
+/// Names of the view matrices, in the order of `PropertyParams::view`.
+const VIEW_NAMES: [&'static str; 3] = ["u_Transform", "u_Model", "u_View"];
+
+/// Link of the property parameters: sources of the program variables.
+#[derive(Clone, Debug)]
+pub struct PropertyLink {
+    uniforms: Vec<Slot>,
+    textures: Vec<String>,
+}
+
+/// Shader parameters binding the material properties by name.
+#[derive(Clone, Debug)]
+pub struct PropertyParams<R: gfx::Resources> {
+    /// View matrices: `u_Transform`, `u_Model`, and `u_View`.
+    pub view: [[[f32; 4]; 4]; 3],
+    /// Material properties.
+    pub properties: Rc<Vec<(String, Property<R>)>>,
+    /// Texture bound to the samplers that have no matching property.
+    pub fallback: TextureParam<R>,
+}
+
+impl<R: gfx::Resources> PropertyParams<R> {
+    fn find(&self, name: &str) -> Option<&Property<R>> {
+        self.properties.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref p)| p)
+    }
+}
+
+impl<R: gfx::Resources> gfx::shade::ShaderParam for PropertyParams<R> {
+    type Resources = R;
+    type Link = PropertyLink;
+
+    fn create_link(_: Option<&PropertyParams<R>>, info: &ProgramInfo)
+                   -> Result<PropertyLink, ParameterError> {
+        if let Some(b) = info.blocks.first() {
+            return Err(ParameterError::MissingBlock(b.name.clone()))
+        }
+        let mut uniforms = Vec::with_capacity(info.uniforms.len());
+        for var in info.uniforms.iter() {
+            let default = match get_default(var) {
+                Some(v) => v,
+                None => return Err(ParameterError::BadUniform(var.name.clone())),
+            };
+            uniforms.push(match VIEW_NAMES.iter().position(|&n| n == var.name) {
+                Some(i) if is_same_type(&default, &UniformValue::F32Matrix4([[0.0; 4]; 4])) =>
+                    Slot::View(i),
+                Some(_) => return Err(ParameterError::BadUniform(var.name.clone())),
+                None => Slot::Property(var.name.clone(), default),
+            });
+        }
+        Ok(PropertyLink {
+            uniforms: uniforms,
+            textures: info.textures.iter().map(|t| t.name.clone()).collect(),
+        })
+    }
+
+    fn fill_params(&self, link: &PropertyLink, storage: &mut gfx::ParamStorage<R>) {
+        // uniforms without a matching property, or with a property of
+        // a different type, are bound to zero
+        for slot in link.uniforms.iter() {
+            storage.uniforms.push(match *slot {
+                Slot::View(i) => UniformValue::F32Matrix4(self.view[i]),
+                Slot::Property(ref name, ref default) => {
+                    match self.find(name).and_then(|p| p.to_uniform()) {
+                        Some(v) if is_same_type(&v, default) => v,
+                        _ => default.clone(),
+                    }
+                },
+            });
+        }
+        for name in link.textures.iter() {
+            storage.textures.push(match self.find(name) {
+                Some(&Property::Texture(ref t)) => t.clone(),
+                _ => self.fallback.clone(),
+            });
+        }
+    }
+}
+
+/// Technique adaptor for the property materials. Material flags are
+/// mapped onto the kernel bits, selecting the program permutation.
+/// Materials have to go through `prepare` before drawing.
+pub struct PropertyTechnique<R: gfx::Resources> {
+    /// Program permutations.
+    pub programs: gfx_phase::Permutations<R, u32>,
+    flags: Vec<String>,
+    opaque: gfx::DrawState,
+    blend: gfx::DrawState,
+    fallback: TextureParam<R>,
+}
+
+/// Kernel bit set for blended materials.
+const BLEND_BIT: u32 = 1 << 31;
+/// Maximum number of flags known to the technique.
+pub const MAX_FLAGS: usize = 31;
+
+/// Error in creating or preparing the property technique.
+#[derive(Debug)]
+pub enum PropertyError {
+    /// There are more flags than the kernel bits, with the count.
+    TooManyFlags(usize),
+    /// Failed to link a program.
+    Program(gfx::ProgramError),
+    /// A material property doesn't match the type of its shader variable.
+    Mismatch(String),
+}
+
+impl<R: gfx::Resources> PropertyTechnique<R> {
+    /// Create a new technique from the shader sources and the list of
+    /// known flags. Unknown material flags are ignored.
+    pub fn new(vertex: &str, fragment: &str, flags: &[&str],
+               fallback: TextureParam<R>) -> Result<PropertyTechnique<R>, PropertyError> {
+        if flags.len() > MAX_FLAGS {
+            return Err(PropertyError::TooManyFlags(flags.len()))
+        }
+        let programs = flags.iter().enumerate().fold(
+            gfx_phase::Permutations::new(vertex, fragment),
+            |p, (i, name)| p.with_define(1 << i, name))
+            .with_define(BLEND_BIT, "BLEND");
+        Ok(PropertyTechnique {
+            programs: programs,
+            flags: flags.iter().map(|f| f.to_string()).collect(),
+            opaque: gfx::DrawState::new().depth(gfx::state::Comparison::LessEqual, true),
+            blend: gfx::DrawState::new().depth(gfx::state::Comparison::LessEqual, false)
+                                        .blend(gfx::BlendPreset::Alpha),
+            fallback: fallback,
+        })
+    }
+
+    /// Get the kernel of a material.
+    pub fn get_kernel(&self, material: &PropertyMaterial<R>) -> u32 {
+        let bits = material.flags.iter().fold(0, |bits, flag|
+            match self.flags.iter().position(|f| f == flag) {
+                Some(i) => bits | (1 << i),
+                None => bits,
+            });
+        match material.transparency {
+            Transparency::Blend => bits | BLEND_BIT,
+            _ => bits,
+        }
+    }
+
+    /// Link the program for a material, and check that its properties
+    /// match the types of the shader variables. Has to be called before
+    /// drawing with it, since the technique can't link on its own.
+    pub fn prepare<F: gfx::Factory<R>>(&mut self, factory: &mut F,
+                   material: &PropertyMaterial<R>) -> Result<(), PropertyError> {
+        let kernel = self.get_kernel(material);
+        let info = match self.programs.prepare(factory, kernel) {
+            Ok(p) => p.get_info(),
+            Err(e) => return Err(PropertyError::Program(e.clone())),
+        };
+        for &(ref name, ref property) in material.properties.iter() {
+            let var = info.uniforms.iter().find(|u| u.name == *name);
+            let matches = match (property.to_uniform(), var) {
+                (Some(v), Some(var)) => get_default(var).map_or(false, |d| is_same_type(&v, &d)),
+                // textures can only be bound to samplers
+                (None, Some(_)) => false,
+                (Some(_), None) => !info.textures.iter().any(|t| t.name == *name),
+                (None, None) => true,
+            };
+            if !matches {
+                return Err(PropertyError::Mismatch(name.clone()))
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the shader sources and relink the prepared permutations.
+    /// Returns the kernels that got new programs, to be invalidated
+    /// in the phase.
+    pub fn reload<F: gfx::Factory<R>>(&mut self, factory: &mut F,
+                  vertex: &str, fragment: &str) -> Vec<u32> {
+        self.programs.reload(factory, vertex, fragment)
+    }
+}
+
+impl<R: gfx::Resources> gfx_phase::Technique<R, PropertyMaterial<R>, ViewInfo>
+for PropertyTechnique<R> {
+    type Kernel = u32;
+    type Params = PropertyParams<R>;
+
+    fn test(&self, _: &gfx::Mesh<R>, material: &PropertyMaterial<R>) -> Option<u32> {
+        let kernel = self.get_kernel(material);
+        match self.programs.get(kernel) {
+            Some(Ok(_)) => Some(kernel),
+            Some(Err(_)) => None, // already logged when linking
+            None => {
+                warn!("Material with flags {:?} is not prepared, rejecting it",
+                      material.flags);
+                None
+            },
+        }
+    }
+
+    fn compile<'a>(&'a self, kernel: u32, _: &ViewInfo)
+                   -> gfx_phase::TechResult<'a, R, PropertyParams<R>> {
+        let program = match self.programs.get(kernel) {
+            Some(Ok(p)) => p,
+            _ => panic!("Kernel {:?} is not linked, `test` should have rejected it", kernel),
+        };
+        (   program,
+            PropertyParams {
+                view: [[[0.0; 4]; 4]; 3],
+                properties: Rc::new(Vec::new()),
+                fallback: self.fallback.clone(),
+            },
+            None,
+            if kernel & BLEND_BIT != 0 {&self.blend} else {&self.opaque},
+        )
+    }
+
+    fn fix_params(&self, material: &PropertyMaterial<R>, view: &ViewInfo,
+                  params: &mut PropertyParams<R>) {
+        use cgmath::FixedArray;
+        params.view = [*view.mvp.as_fixed(), *view.model.as_fixed(), *view.view.as_fixed()];
+        params.properties = material.properties.clone();
+    }
+}
+
+/// Material definition loading error.
+#[derive(Debug)]
+pub enum DefinitionError {
+    /// Error in reading the file.
+    Io(io::Error),
+    /// Malformed line, with the line number.
+    Parse(usize, String),
+}
+
+impl From<io::Error> for DefinitionError {
+    fn from(e: io::Error) -> DefinitionError {
+        DefinitionError::Io(e)
+    }
+}
+
+/// Parse material definitions. Each material starts with a `material <name>`
+/// line, followed by the property lines:
+///
+/// ```text
+/// transparency opaque|cutout|blend
+/// flag <name>
+/// float|vec2|vec3|vec4 <name> <values>
+/// texture <name> <path>
+/// ```
+///
+/// Textures are resolved by the `load_texture` function.
+pub fn parse_materials<R, F>(text: &str, mut load_texture: F)
+                       -> Result<Vec<PropertyMaterial<R>>, DefinitionError> where
+    R: gfx::Resources,
+    F: FnMut(&str) -> Option<TextureParam<R>>,
+{
+    let mut materials: Vec<PropertyMaterial<R>> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let words: Vec<&str> = line.split(|c: char| c.is_whitespace())
+                                   .filter(|w| !w.is_empty())
+                                   .collect();
+        if words.is_empty() || words[0].starts_with("#") {
+            continue
+        }
+        let error = |msg: &str| DefinitionError::Parse(i+1, msg.to_string());
+        if words.len() < 2 {
+            return Err(error("missing arguments"))
+        }
+        if words[0] == "material" {
+            materials.push(PropertyMaterial::new(&words[1..].connect(" ")));
+            continue
+        }
+        let mat = match materials.last_mut() {
+            Some(m) => m,
+            None => return Err(error("property outside of a material")),
+        };
+        match words[0] {
+            "transparency" => {
+                mat.transparency = match words[1] {
+                    "opaque" => Transparency::Opaque,
+                    "cutout" => Transparency::Cutout,
+                    "blend" => Transparency::Blend,
+                    other => return Err(error(&format!("unknown transparency `{}`", other))),
+                };
+                continue
+            },
+            "flag" => {
+                mat.flags.push(words[1].to_string());
+                continue
+            },
+            "texture" => {
+                if words.len() != 3 {
+                    return Err(error("invalid `texture` line"))
+                }
+                match load_texture(words[2]) {
+                    Some(t) => mat.set(words[1], Property::Texture(t)),
+                    None => return Err(error(&format!("unable to load texture `{}`", words[2]))),
+                }
+                continue
+            },
+            _ => (),
+        }
+        let count = match words[0] {
+            "float" => 1,
+            "vec2" => 2,
+            "vec3" => 3,
+            "vec4" => 4,
+            other => return Err(error(&format!("unknown property type `{}`", other))),
+        };
+        if words.len() != count + 2 {
+            return Err(error(&format!("invalid `{}` line", words[0])))
+        }
+        let mut v = [0.0f32; 4];
+        for (w, o) in words[2..].iter().zip(v.iter_mut()) {
+            *o = match f32::from_str(w) {
+                Ok(x) => x,
+                Err(_) => return Err(error(&format!("invalid number `{}`", w))),
+            };
+        }
+        let property = match count {
+            1 => Property::Float(v[0]),
+            2 => Property::Vec2([v[0], v[1]]),
+            3 => Property::Vec3([v[0], v[1], v[2]]),
+            _ => Property::Vec4(v),
+        };
+        mat.set(words[1], property);
+    }
+    Ok(materials)
+}
+
+/// Load material definitions from a file.
+pub fn load_materials<R, F>(path: &Path, load_texture: F)
+                      -> Result<Vec<PropertyMaterial<R>>, DefinitionError> where
+    R: gfx::Resources,
+    F: FnMut(&str) -> Option<TextureParam<R>>,
+{
+    let mut text = String::new();
+    let mut file = try!(File::open(path));
+    try!(file.read_to_string(&mut text));
+    parse_materials(&text, load_texture)
+}
+
+#[cfg(test)]
+mod test {
+    use gfx;
+    use gfx::traits::*;
+    use gfx_mock;
+    use Transparency;
+    use super::{DefinitionError, Property, parse_materials};
+
+    static MATERIALS: &'static str = "
+        # two materials
+        material polished wood
+        flag HAS_COLOR_MAP
+        texture t_Color wood.png
+        float u_Shininess 20
+        vec3 u_Specular 0.5 0.5 0.5
+        material glass
+        transparency blend
+        vec4 u_Color 0.8 0.9 1.0 0.25
+        vec2 u_Scale 1 2
+        float u_Shininess 5
+        float u_Shininess 50
+    ";
+
+    fn parse(text: &str) -> Result<Vec<::PropertyMaterial<gfx_mock::Resources>>, DefinitionError> {
+        let mut factory = gfx_mock::Factory::new();
+        let texture = factory.create_texture_rgba8(1, 1).unwrap();
+        parse_materials(text, |path: &str| if path == "wood.png" {
+            Some((texture.clone(), None))
+        }else {
+            None
+        })
+    }
+
+    #[test]
+    fn materials() {
+        let materials = parse(MATERIALS).unwrap();
+        assert_eq!(materials.len(), 2);
+        let wood = &materials[0];
+        assert_eq!(wood.name, "polished wood");
+        assert_eq!(wood.transparency, Transparency::Opaque);
+        assert_eq!(wood.flags, vec!["HAS_COLOR_MAP".to_string()]);
+        assert_eq!(wood.properties.len(), 3);
+        match wood.get("t_Color") {
+            Some(&Property::Texture(_)) => (),
+            other => panic!("Unexpected property {:?}", other),
+        }
+        match wood.get("u_Specular") {
+            Some(&Property::Vec3(v)) => assert_eq!(v, [0.5, 0.5, 0.5]),
+            other => panic!("Unexpected property {:?}", other),
+        }
+        let glass = &materials[1];
+        assert_eq!(glass.transparency, Transparency::Blend);
+        assert!(glass.flags.is_empty());
+        match glass.get("u_Color") {
+            Some(&Property::Vec4(v)) => assert_eq!(v, [0.8, 0.9, 1.0, 0.25]),
+            other => panic!("Unexpected property {:?}", other),
+        }
+        match glass.get("u_Scale") {
+            Some(&Property::Vec2(v)) => assert_eq!(v, [1.0, 2.0]),
+            other => panic!("Unexpected property {:?}", other),
+        }
+        // the last value wins
+        assert_eq!(glass.properties.len(), 3);
+        match glass.get("u_Shininess") {
+            Some(&Property::Float(v)) => assert_eq!(v, 50.0),
+            other => panic!("Unexpected property {:?}", other),
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let cases = [
+            ("material", 1, "missing arguments"),
+            ("float u_Value 1", 1, "property outside of a material"),
+            ("material a\ntransparency half", 2, "unknown transparency `half`"),
+            ("material a\ntexture t_Color stone.png", 2, "unable to load texture `stone.png`"),
+            ("material a\ntexture a b c", 2, "invalid `texture` line"),
+            ("material a\nmat4 u_Value 1", 2, "unknown property type `mat4`"),
+            ("material a\nvec3 u_Value 1 2", 2, "invalid `vec3` line"),
+            ("material a\nfloat u_Value 1 2", 2, "invalid `float` line"),
+            ("material a\nvec2 u_Value 1 x", 2, "invalid number `x`"),
+        ];
+        for &(text, line, message) in cases.iter() {
+            match parse(text) {
+                Err(DefinitionError::Parse(l, ref m)) if l == line && m == message => (),
+                Err(e) => panic!("Unexpected error {:?} for {:?}", e, text),
+                Ok(m) => panic!("Unexpected success with {} materials for {:?}", m.len(), text),
+            }
+        }
+    }
+}
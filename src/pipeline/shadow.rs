@@ -0,0 +1,212 @@
+//! Shadow mapping support.
+
+use std::fmt::Debug;
+use cgmath;
+use gfx;
+use gfx_phase;
+use gfx_scene;
+use ViewInfo;
+
+/// Maximum number of cascades exposed to the techniques.
+pub const MAX_CASCADES: usize = 4;
+
+/// Maximum field of view of a spot light shadow, in radians (170 degrees).
+pub const MAX_SPOT_FOV: f32 = 2.967_06;
+
+/// Shadow view building settings.
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowSettings {
+    /// Number of cascades for directional lights.
+    pub cascades: usize,
+    /// Blend between the logarithmic (1) and uniform (0) cascade splits.
+    pub lambda: f32,
+    /// Distance to pull the near plane of the cascades towards the light,
+    /// so that casters outside of the camera frustum are kept.
+    pub backoff: f32,
+    /// Resolution of the shadow map, used for the texel snapping.
+    pub resolution: u16,
+    /// Render layers of the shadow casters.
+    pub layers: gfx_scene::Layers,
+}
+
+impl ShadowSettings {
+    /// Create the default settings.
+    pub fn new() -> ShadowSettings {
+        ShadowSettings {
+            cascades: MAX_CASCADES,
+            lambda: 0.75,
+            backoff: 100.0,
+            resolution: 1024,
+            layers: gfx_scene::ALL_LAYERS,
+        }
+    }
+}
+
+/// Shadow building or drawing error.
+#[derive(Debug)]
+pub enum ShadowError {
+    /// Error in the scene, such as a singular camera.
+    Scene(gfx_scene::Error),
+    /// The settings ask for no cascades.
+    NoCascades,
+    /// There is no view or visible set with the given index.
+    ViewIndex(usize),
+}
+
+impl From<gfx_scene::Error> for ShadowError {
+    fn from(e: gfx_scene::Error) -> ShadowError {
+        ShadowError::Scene(e)
+    }
+}
+
+/// Shadow matrices, as exposed to the material techniques. Pass it to
+/// `set_shadow` of the Phong or PBR technique, together with the shadow
+/// map: a depth texture array with a layer per cascade.
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowInfo {
+    /// The shadowed light.
+    pub light: gfx_scene::LightInfo<f32>,
+    /// World-to-shadow-clip matrix of each cascade.
+    pub matrices: [[[f32; 4]; 4]; MAX_CASCADES],
+    /// Far view distance of each cascade.
+    pub splits: [f32; MAX_CASCADES],
+    /// Number of used cascades.
+    pub count: usize,
+}
+
+/// Set of light views to render the shadow casters from.
+#[derive(Clone, Debug)]
+pub struct Shadow<T> {
+    /// The shadowed light.
+    pub light: gfx_scene::LightInfo<f32>,
+    /// Light views, one per cascade.
+    pub views: Vec<gfx_scene::View<f32, T>>,
+    /// Far view distance of each cascade.
+    pub splits: Vec<f32>,
+}
+
+/// Shift the projection of an orthographic view by less than a texel, so
+/// that the world origin falls onto the texel grid. This keeps the shadow
+/// edges from shimmering when the camera moves.
+pub fn snap_to_texels<T>(view: &mut gfx_scene::View<f32, T>, resolution: u16) where
+    T: cgmath::Transform3<f32> + Clone + Into<cgmath::Matrix4<f32>>,
+{
+    use cgmath::Matrix;
+    let origin = view.get_view_projection()
+                     .mul_v(&cgmath::Vector4::new(0.0, 0.0, 0.0, 1.0));
+    let half = resolution as f32 * 0.5;
+    let (x, y) = (origin.x * half, origin.y * half);
+    let (dx, dy) = ((x.round() - x) / half, (y.round() - y) / half);
+    let shift = cgmath::Matrix4::new(
+        1.0, 0.0, 0.0, 0.0,
+        0.0, 1.0, 0.0, 0.0,
+        0.0, 0.0, 1.0, 0.0,
+        dx, dy, 0.0, 1.0);
+    view.projection = shift.mul_m(&view.projection);
+}
+
+impl<T: cgmath::Transform3<f32> + Clone + Into<cgmath::Matrix4<f32>>> Shadow<T> {
+    /// Build the cascades of a directional light, covering the frustum of
+    /// a perspective camera, given by its projection and world-to-view
+    /// transformation. Fails if the camera view can not be inverted,
+    /// or if there are no cascades requested.
+    pub fn directional(light: &gfx_scene::LightInfo<f32>,
+                       fov: &cgmath::PerspectiveFov<f32, cgmath::Rad<f32>>,
+                       camera: &T, settings: &ShadowSettings)
+                       -> Result<Shadow<T>, ShadowError> {
+        if settings.cascades == 0 {
+            return Err(ShadowError::NoCascades)
+        }
+        let count = ::std::cmp::min(settings.cascades, MAX_CASCADES);
+        let splits = gfx_scene::cascade_splits(fov.near, fov.far, count, settings.lambda);
+        let mut views = Vec::with_capacity(count);
+        for i in 0 .. count {
+            let mut view = try!(gfx_scene::View::cascade(fov, camera,
+                splits[i], splits[i+1], light.direction,
+                settings.backoff, settings.layers));
+            snap_to_texels(&mut view, settings.resolution);
+            views.push(view);
+        }
+        Ok(Shadow {
+            light: *light,
+            views: views,
+            splits: splits[1..].to_vec(),
+        })
+    }
+
+    /// Build the view of a spot light, covering its cone. The field of view
+    /// is clamped to `MAX_SPOT_FOV`, since wider cones can't be covered by
+    /// a single perspective. Returns `None` for other light kinds.
+    pub fn spot(light: &gfx_scene::LightInfo<f32>, settings: &ShadowSettings)
+                -> Option<Shadow<T>> {
+        use cgmath::Point;
+        let angle = match light.kind {
+            gfx_scene::LightKind::Spot(angle) => angle,
+            _ => return None,
+        };
+        let (p, d) = (light.position, light.direction);
+        let up = if d.z.abs() < d.x.abs() + d.y.abs() {
+            cgmath::Vector3::unit_z()
+        }else {
+            cgmath::Vector3::unit_x()
+        };
+        let near = light.range / 1000.0;
+        let fov = cgmath::rad((angle.s + angle.s).min(MAX_SPOT_FOV));
+        Some(Shadow {
+            light: *light,
+            views: vec![gfx_scene::View {
+                transform: T::look_at(&p, &p.add_v(&d), &up),
+                projection: cgmath::perspective(fov, 1.0, near, light.range),
+                layers: settings.layers,
+            }],
+            splits: vec![light.range],
+        })
+    }
+
+    /// Get the matrices to pass to the material techniques.
+    pub fn get_info(&self) -> ShadowInfo {
+        use cgmath::FixedArray;
+        let mut info = ShadowInfo {
+            light: self.light,
+            matrices: [[[0.0; 4]; 4]; MAX_CASCADES],
+            splits: [0.0; MAX_CASCADES],
+            count: ::std::cmp::min(self.views.len(), MAX_CASCADES),
+        };
+        for (i, (view, &split)) in self.views.iter().zip(self.splits.iter())
+                                       .take(MAX_CASCADES).enumerate() {
+            info.matrices[i] = *view.get_view_projection().as_fixed();
+            info.splits[i] = split;
+        }
+        info
+    }
+
+    /// Cull the shadow casters against all the views at once,
+    /// producing a visible set per view.
+    pub fn cull<R, M, W, B>(&self, world: &W, entities: &[gfx_scene::Entity<R, M, W, B>])
+                -> Vec<gfx_scene::VisibleSet<ViewInfo>> where
+        R: gfx::Resources,
+        W: gfx_scene::World<Scalar = f32, Transform = T>,
+        B: gfx_scene::SkinBound<f32> + Debug,
+    {
+        let mut culler = gfx_scene::Frustum::new();
+        let mut context = gfx_scene::MultiContext::new(world, &mut culler, &self.views);
+        context.cull(entities)
+    }
+
+    /// Draw the casters of a single view into a stream, using a depth-only
+    /// phase and the visible sets produced by `cull`.
+    pub fn draw<R, M, W, B, H, S>(&self, visible: &[gfx_scene::VisibleSet<ViewInfo>],
+                index: usize, entities: &[gfx_scene::Entity<R, M, W, B>],
+                phase: &mut H, stream: &mut S)
+                -> Result<gfx_scene::Report, ShadowError> where
+        R: gfx::Resources,
+        W: gfx_scene::World<Scalar = f32, Transform = T>,
+        H: gfx_phase::AbstractPhase<R, M, ViewInfo>,
+        S: gfx::Stream<R>,
+    {
+        if index >= self.views.len() || index >= visible.len() {
+            return Err(ShadowError::ViewIndex(index))
+        }
+        Ok(try!(visible[index].draw(entities, phase, stream)))
+    }
+}
@@ -3,6 +3,8 @@
 //! Generic draw queue that keeps item ordering, supposedly minimizing
 //! the sorting time per frame by exploiting temporal coherency.
 
+use std::fmt;
+
 type IdType = u32;
 struct Id<T>(IdType, std::marker::PhantomData<T>);
 
@@ -27,6 +29,7 @@ pub struct Queue<T> {
     /// Exposed objects list that can be modified directly with no harm.
     pub objects: Vec<T>,
     indices: Vec<Id<T>>,
+    sorted: bool,
 }
 
 impl<T> Queue<T> {
@@ -35,9 +38,18 @@ impl<T> Queue<T> {
         Queue {
             objects: Vec::new(),
             indices: Vec::new(),
+            sorted: false,
         }
     }
 
+    /// Remove all the objects. The indices are kept in order to exploit
+    /// the coherency with the next frame, but the queue is no longer
+    /// considered sorted.
+    pub fn clear(&mut self) {
+        self.objects.clear();
+        self.sorted = false;
+    }
+
     fn is_ready(&self) -> bool {
         self.objects.len() == self.indices.len()
     }
@@ -64,6 +76,7 @@ impl<T> Queue<T> {
         self.indices.sort_by(|&Id(a, _), &Id(b, _)|
             fun(&objects[a as usize], &objects[b as usize])
         );
+        self.sorted = true;
     }
 
     /// Iterate over sorted objects.
@@ -74,4 +87,52 @@ impl<T> Queue<T> {
             id_iter: self.indices.iter(),
         }
     }
+
+    /// List the objects for debugging: in the sorted order if the queue
+    /// has been sorted since the last `clear`, or in the storage order
+    /// otherwise.
+    pub fn list<'a>(&'a self) -> Vec<&'a T> {
+        if self.sorted && self.is_ready() {
+            self.iter().collect()
+        }else {
+            self.objects.iter().collect()
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Queue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, o) in self.list().into_iter().enumerate() {
+            try!(writeln!(f, "{}: {:?}", i, o));
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::Queue;
+
+    #[test]
+    fn sort() {
+        let mut queue = Queue::new();
+        queue.objects.extend([3, 1, 2].iter().map(|&x| x));
+        queue.sort(|a, b| a.cmp(b));
+        assert_eq!(queue.iter().map(|&x| x).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(queue.list(), vec![&1, &2, &3]);
+    }
+
+    #[test]
+    fn stale_list() {
+        let mut queue = Queue::new();
+        queue.objects.extend([3, 1, 2].iter().map(|&x| x));
+        queue.sort(|a, b| a.cmp(b));
+        queue.clear();
+        // same number of objects as the last frame, but not sorted yet
+        queue.objects.extend([5, 6, 4].iter().map(|&x| x));
+        assert_eq!(queue.list(), vec![&5, &6, &4]);
+        queue.sort(|a, b| a.cmp(b));
+        assert_eq!(queue.list(), vec![&4, &5, &6]);
+    }
 }
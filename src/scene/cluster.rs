@@ -0,0 +1,253 @@
+//! Clustered light assignment on the CPU, for forward+ rendering.
+
+use std::cmp;
+use cgmath;
+
+/// A grid of view-space clusters. The screen is split uniformly into
+/// tiles, and the depth range is split exponentially into slices.
+#[derive(Clone, Debug)]
+pub struct ClusterGrid<S> {
+    /// Number of clusters along the X, Y, and depth axis.
+    pub dims: [usize; 3],
+    /// Depth of the first slice start.
+    pub near: S,
+    /// Depth of the last slice end.
+    pub far: S,
+}
+
+/// Light lists of every cluster, laid out for uploading into buffers.
+#[derive(Clone, Debug)]
+pub struct Clusters {
+    /// Offset and count of light indices per cluster, indexed by
+    /// `x + dims[0] * (y + dims[1] * z)`.
+    pub ranges: Vec<[u32; 2]>,
+    /// Light indices of all clusters, packed together.
+    pub indices: Vec<u32>,
+}
+
+impl<S: cgmath::BaseFloat + 'static> ClusterGrid<S> {
+    /// Create a new cluster grid.
+    ///
+    /// Panics if any of the dimensions is zero, or unless
+    /// `0 < near < far`, since the slices are logarithmic.
+    pub fn new(dims: [usize; 3], near: S, far: S) -> ClusterGrid<S> {
+        assert!(dims.iter().all(|&d| d > 0), "Empty cluster grid: {:?}", dims);
+        assert!(near > cgmath::zero() && near < far, "Invalid depth range");
+        ClusterGrid {
+            dims: dims,
+            near: near,
+            far: far,
+        }
+    }
+
+    /// Get the number of clusters.
+    pub fn get_count(&self) -> usize {
+        self.dims[0] * self.dims[1] * self.dims[2]
+    }
+
+    /// Get the depth slice index of a view-space depth.
+    pub fn get_slice(&self, depth: S) -> usize {
+        if depth <= self.near {
+            return 0
+        }
+        let num = S::from(self.dims[2]).unwrap();
+        let k = (depth / self.near).ln() / (self.far / self.near).ln() * num;
+        cmp::min(k.to_usize().unwrap_or(0), self.dims[2] - 1)
+    }
+
+    /// Get the tile index of a normalized device coordinate along an axis.
+    fn get_tile(&self, ndc: S, axis: usize) -> usize {
+        let (zero, one): (S, S) = (cgmath::zero(), cgmath::one());
+        let num = S::from(self.dims[axis]).unwrap();
+        let k = (ndc.max(-one).min(one) + one) / (one + one) * num;
+        cmp::min(k.max(zero).to_usize().unwrap_or(0), self.dims[axis] - 1)
+    }
+
+    /// Find the inclusive cluster box affected by a light,
+    /// given the view transform and the projection.
+    fn get_light_box<T>(&self, view: &::View<S, T>, light: &::LightInfo<S>)
+                     -> Option<([usize; 3], [usize; 3])> where
+        T: cgmath::Transform3<S> + Clone,
+    {
+        use cgmath::{Matrix, Point, Transform};
+        let zero: S = cgmath::zero();
+        let last = [self.dims[0] - 1, self.dims[1] - 1, self.dims[2] - 1];
+        if let ::LightKind::Directional = light.kind {
+            return Some(([0, 0, 0], last))
+        }
+        let center = view.transform.transform_point(&light.position);
+        let r = light.range;
+        let depth = -center.z;
+        if depth + r < self.near || depth - r > self.far {
+            return None
+        }
+        let (z0, z1) = (self.get_slice(depth - r), self.get_slice(depth + r));
+        if depth - r <= zero {
+            // the sphere contains the eye plane, so it can cover any tile
+            return Some(([0, 0, z0], [last[0], last[1], z1]))
+        }
+        // find the screen box in normalized device coordinates
+        let one: S = cgmath::one();
+        let (mut min, mut max) = ([one, one], [-one, -one]);
+        let mut first = true;
+        for &dx in [-r, r].iter() {
+            for &dy in [-r, r].iter() {
+                for &dz in [-r, r].iter() {
+                    let p = cgmath::Point3::new(center.x + dx, center.y + dy, center.z + dz);
+                    let clip = view.projection.mul_v(&p.to_homogeneous());
+                    let (x, y) = (clip.x / clip.w, clip.y / clip.w);
+                    if first {
+                        min = [x, y];
+                        max = [x, y];
+                        first = false;
+                    }else {
+                        min = [min[0].min(x), min[1].min(y)];
+                        max = [max[0].max(x), max[1].max(y)];
+                    }
+                }
+            }
+        }
+        // the box is empty after clamping to the screen
+        if max[0] < -one || max[1] < -one || min[0] > one || min[1] > one {
+            return None
+        }
+        Some(([self.get_tile(min[0], 0), self.get_tile(min[1], 1), z0],
+              [self.get_tile(max[0], 0), self.get_tile(max[1], 1), z1]))
+    }
+
+    /// Assign the lights to clusters of a view. Light indices refer to
+    /// the given slice. Point and spot lights are treated as spheres
+    /// of their range, while directional lights go into every cluster.
+    pub fn assign<T>(&self, view: &::View<S, T>, lights: &[::LightInfo<S>])
+                  -> Clusters where
+        T: cgmath::Transform3<S> + Clone,
+    {
+        let boxes: Vec<_> = lights.iter().map(|l| self.get_light_box(view, l))
+                                         .collect();
+        let (dx, dy) = (self.dims[0], self.dims[1]);
+        // count the lights per cluster
+        let mut ranges = vec![[0u32, 0u32]; self.get_count()];
+        for b in boxes.iter() {
+            if let Some((min, max)) = *b {
+                for z in min[2] .. max[2] + 1 {
+                    for y in min[1] .. max[1] + 1 {
+                        for x in min[0] .. max[0] + 1 {
+                            ranges[x + dx * (y + dy * z)][1] += 1;
+                        }
+                    }
+                }
+            }
+        }
+        // compute the offsets
+        let mut total = 0;
+        for r in ranges.iter_mut() {
+            r[0] = total;
+            total += r[1];
+            r[1] = 0;
+        }
+        // fill the indices
+        let mut indices = vec![0u32; total as usize];
+        for (i, b) in boxes.iter().enumerate() {
+            if let Some((min, max)) = *b {
+                for z in min[2] .. max[2] + 1 {
+                    for y in min[1] .. max[1] + 1 {
+                        for x in min[0] .. max[0] + 1 {
+                            let r = &mut ranges[x + dx * (y + dy * z)];
+                            indices[(r[0] + r[1]) as usize] = i as u32;
+                            r[1] += 1;
+                        }
+                    }
+                }
+            }
+        }
+        Clusters {
+            ranges: ranges,
+            indices: indices,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use cgmath;
+    use cgmath::Transform;
+    use super::ClusterGrid;
+
+    type Transform3 = cgmath::Decomposed<f32, cgmath::Vector3<f32>, cgmath::Quaternion<f32>>;
+
+    fn make_view() -> ::View<f32, Transform3> {
+        ::View {
+            transform: Transform::identity(),
+            projection: cgmath::ortho(-1.0, 1.0, -1.0, 1.0, 1.0, 16.0),
+            layers: ::ALL_LAYERS,
+        }
+    }
+
+    fn make_light(kind: ::LightKind<f32>, position: [f32; 3], range: f32)
+                  -> ::LightInfo<f32> {
+        ::LightInfo {
+            kind: kind,
+            color: [1.0; 4],
+            position: cgmath::Point3::new(position[0], position[1], position[2]),
+            direction: cgmath::Vector3::new(0.0, 0.0, -1.0),
+            range: range,
+        }
+    }
+
+    #[test]
+    fn point_light() {
+        // slices start at depths 1, 2, 4, and 8
+        let grid = ClusterGrid::new([4, 4, 4], 1.0, 16.0);
+        let light = make_light(::LightKind::Point, [0.25, 0.25, -3.0], 0.5);
+        let clusters = grid.assign(&make_view(), &[light]);
+        assert_eq!(clusters.ranges.len(), 64);
+        // the sphere spans X and Y from -0.25 to 0.75, and depth from 2.5 to 3.5
+        for z in 0 .. 4 {
+            for y in 0 .. 4 {
+                for x in 0 .. 4 {
+                    let r = clusters.ranges[x + 4 * (y + 4 * z)];
+                    let inside = x >= 1 && y >= 1 && z == 1;
+                    assert_eq!(r[1], if inside {1} else {0});
+                    if inside {
+                        assert_eq!(clusters.indices[r[0] as usize], 0);
+                    }
+                }
+            }
+        }
+        assert_eq!(clusters.indices.len(), 9);
+    }
+
+    #[test]
+    fn off_screen_light() {
+        let grid = ClusterGrid::new([4, 4, 4], 1.0, 16.0);
+        let light = make_light(::LightKind::Point, [3.0, 0.0, -3.0], 0.5);
+        let clusters = grid.assign(&make_view(), &[light]);
+        assert!(clusters.indices.is_empty());
+        assert!(clusters.ranges.iter().all(|r| r[1] == 0));
+    }
+
+    #[test]
+    fn directional_light() {
+        let grid = ClusterGrid::new([3, 2, 5], 0.5, 100.0);
+        let light = make_light(::LightKind::Directional, [0.0, 0.0, 0.0], 0.0);
+        let clusters = grid.assign(&make_view(), &[light]);
+        assert_eq!(clusters.ranges.len(), 30);
+        for (i, r) in clusters.ranges.iter().enumerate() {
+            assert_eq!(*r, [i as u32, 1]);
+        }
+        assert!(clusters.indices.iter().all(|&i| i == 0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_grid() {
+        ClusterGrid::new([4, 0, 4], 1.0, 16.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_near() {
+        ClusterGrid::new([4, 4, 4], 0.0, 16.0);
+    }
+}
@@ -1,3 +1,4 @@
+use std::cmp::Ordering;
 use std::marker::PhantomData;
 use cgmath;
 use gfx;
@@ -19,6 +20,114 @@ impl<S, B: cgmath::Bound<S>> Culler<S, B> for () {
     }
 }
 
+/// A bound that can be adjusted to enclose a skinned mesh.
+pub trait SkinBound<S>: cgmath::Bound<S> {
+    /// Produce a bound that encloses this one transformed by every bone
+    /// of the palette. An empty palette leaves the bound intact.
+    fn skin(&self, bones: &[cgmath::Matrix4<S>]) -> Self;
+}
+
+impl<S: cgmath::BaseFloat + 'static> SkinBound<S> for cgmath::Aabb3<S> {
+    fn skin(&self, bones: &[cgmath::Matrix4<S>]) -> cgmath::Aabb3<S> {
+        use cgmath::{Aabb, Matrix, Point};
+        let corners = self.to_corners();
+        let mut result: Option<cgmath::Aabb3<S>> = None;
+        for bone in bones.iter() {
+            for c in corners.iter() {
+                let p = cgmath::Point3::from_homogeneous(
+                    &bone.mul_v(&c.to_homogeneous()));
+                result = Some(match result {
+                    Some(r) => r.grow(&p),
+                    None => cgmath::Aabb3::new(p, p),
+                });
+            }
+        }
+        result.unwrap_or(*self)
+    }
+}
+
+/// Cull a model-space bound, adjusted by the bone palette.
+fn cull_bound<S, B, C>(culler: &mut C, bound: &B, bones: &[cgmath::Matrix4<S>],
+                       mvp: &cgmath::Matrix4<S>) -> cgmath::Relation where
+    B: SkinBound<S>,
+    C: Culler<S, B>,
+{
+    if bones.is_empty() {
+        culler.cull(bound, mvp)
+    }else {
+        culler.cull(&bound.skin(bones), mvp)
+    }
+}
+
+/// Lights of a single view, to be assigned to the entities.
+struct LightSet<S> {
+    lights: Vec<(::LightInfo<S>, Option<cgmath::Matrix4<S>>)>,
+    max: usize,
+    scores: Vec<(S, usize)>,
+    assigned: Vec<::LightInfo<S>>,
+}
+
+impl<S: cgmath::BaseFloat + 'static> LightSet<S> {
+    fn new() -> LightSet<S> {
+        LightSet {
+            lights: Vec::new(),
+            max: 0,
+            scores: Vec::new(),
+            assigned: Vec::new(),
+        }
+    }
+
+    /// Resolve the lights, keeping the ones visible by the view.
+    fn set<W>(&mut self, world: &W, lights: &[::Light<S, W::NodePtr>],
+              view_projection: &cgmath::Matrix4<S>, max: usize) where
+        W: ::World<Scalar = S>,
+    {
+        self.lights.clear();
+        for light in lights.iter() {
+            let info = light.resolve(world);
+            if info.is_visible(view_projection) {
+                self.lights.push((info, info.get_volume()));
+            }
+        }
+        self.max = max;
+    }
+
+    /// Pick the most relevant lights for a model-space bound, ordered by
+    /// the distance to the model origin relative to the light range.
+    /// The bound is expected to be skinned already.
+    fn assign<T, B>(&mut self, model: &T, bound: &B) -> &[::LightInfo<S>] where
+        T: cgmath::Transform3<S> + Clone,
+        B: cgmath::Bound<S>,
+    {
+        use cgmath::{EuclideanVector, Matrix, Point, Transform};
+        self.assigned.clear();
+        if self.max == 0 || self.lights.is_empty() {
+            return &self.assigned
+        }
+        let model_mx: cgmath::Matrix4<S> = model.clone().into();
+        let center = model.transform_point(&cgmath::Point3::origin());
+        self.scores.clear();
+        for (i, &(ref info, ref volume)) in self.lights.iter().enumerate() {
+            let score = match *volume {
+                Some(ref mx) => {
+                    if bound.relate_clip_space(&mx.mul_m(&model_mx)) == cgmath::Relation::Out {
+                        continue
+                    }
+                    info.position.sub_p(&center).length() / info.range
+                },
+                None => cgmath::zero(),
+            };
+            self.scores.push((score, i));
+        }
+        self.scores.sort_by(|a, b| a.0.partial_cmp(&b.0)
+                                      .unwrap_or(Ordering::Equal));
+        for &(_, i) in self.scores.iter().take(self.max) {
+            self.assigned.push(self.lights[i].0);
+        }
+        &self.assigned
+    }
+}
+
 /// Frustum culler.
 pub struct Frustum<S, B>(PhantomData<(S, B)>);
 
@@ -47,6 +156,8 @@ pub struct Context<'a, 'c, W, B, C> where
     culler: &'c mut C,
     cam_inverse: W::Transform,
     projection: cgmath::Matrix4<W::Scalar>,
+    layers: ::Layers,
+    lights: LightSet<W::Scalar>,
     dummy: PhantomData<B>,
 }
 
@@ -55,42 +166,92 @@ impl<'a, 'c,
     B: cgmath::Bound<W::Scalar>,
     C: Culler<W::Scalar, B>,
 > Context<'a, 'c, W, B, C> {
-    /// Create a new context.
+    /// Create a new context. Fails if the camera transformation
+    /// can not be inverted.
     pub fn new<P>(world: &'a W, culler: &'c mut C, camera: &::Camera<P, W::NodePtr>)
-               -> Context<'a, 'c, W, B, C> where
+               -> Result<Context<'a, 'c, W, B, C>, ::Error> where
         P: cgmath::Projection<W::Scalar> + Clone,
     {
-        use cgmath::{Matrix, Transform};
-        let cam_inverse = world.get_transform(&camera.node)
-                               .invert().unwrap();
-        let projection = camera.projection.clone().into()
-                               .mul_m(&cam_inverse.clone().into());
+        let view = try!(::View::from_camera(world, camera));
+        Ok(Context::from_view(world, culler, &view))
+    }
+
+    /// Create a new context from a resolved view.
+    pub fn from_view(world: &'a W, culler: &'c mut C,
+                     view: &::View<W::Scalar, W::Transform>)
+                     -> Context<'a, 'c, W, B, C> {
         culler.init();
         Context {
             world: world,
             culler: culler,
-            cam_inverse: cam_inverse,
-            projection: projection,
+            cam_inverse: view.transform.clone(),
+            projection: view.get_view_projection(),
+            layers: view.layers,
+            lights: LightSet::new(),
             dummy: PhantomData,
         }
     }
 
-    /// Check entity visibility.
-    pub fn is_visible<V>(&mut self, node: &W::NodePtr, bound: &B)
-                      -> Option<V> where
+    /// Set the lights to be assigned to entities. The lights are culled
+    /// against the view, and up to `max` most relevant of them are passed
+    /// to the view info of each entity they intersect.
+    pub fn set_lights(&mut self, lights: &[::Light<W::Scalar, W::NodePtr>], max: usize) {
+        self.lights.set(self.world, lights, &self.projection, max);
+    }
+
+    /// Cull a model-space bound, adjusted by the bone palette.
+    fn cull_bound(&mut self, bound: &B, bones: &[cgmath::Matrix4<W::Scalar>],
+                  mvp: &cgmath::Matrix4<W::Scalar>) -> cgmath::Relation where
+        B: SkinBound<W::Scalar>,
+    {
+        cull_bound(self.culler, bound, bones, mvp)
+    }
+
+    /// Cull a node bound and compute the view info for it, returning also
+    /// the bone palette and the model-view-projection matrix.
+    fn relate<V>(&mut self, node: &W::NodePtr,
+                 skeleton: Option<&W::SkeletonPtr>, bound: &B)
+                 -> (cgmath::Relation, Option<V>,
+                     &'a [cgmath::Matrix4<W::Scalar>], cgmath::Matrix4<W::Scalar>) where
+        B: SkinBound<W::Scalar>,
         V: ::ViewInfo<W::Scalar, W::Transform>,
     {
         use cgmath::{Matrix, Transform};
-        let model = self.world.get_transform(node);
-        let view = self.cam_inverse.concat(&model);
+        let world = self.world;
+        let model = world.get_transform(node);
         let mvp = self.projection.mul_m(&model.clone().into());
-        if self.culler.cull(bound, &mvp) != cgmath::Relation::Out {
-            Some(::ViewInfo::new(mvp, view, model))
+        let bones = match skeleton {
+            Some(s) => world.get_bones(s),
+            None => &[][..],
+        };
+        let skinned;
+        let bound = if bones.is_empty() {
+            bound
+        }else {
+            skinned = bound.skin(bones);
+            &skinned
+        };
+        let relation = self.culler.cull(bound, &mvp);
+        if relation != cgmath::Relation::Out {
+            let view = self.cam_inverse.concat(&model);
+            let lights = self.lights.assign(&model, bound);
+            (relation, Some(::ViewInfo::new(mvp, view, model, bones, lights)), bones, mvp)
         }else {
-            None
+            (relation, None, bones, mvp)
         }
     }
 
+    /// Check entity visibility. The bound is adjusted by the bone palette
+    /// if a skeleton is given.
+    pub fn is_visible<V>(&mut self, node: &W::NodePtr,
+                      skeleton: Option<&W::SkeletonPtr>, bound: &B)
+                      -> Option<V> where
+        B: SkinBound<W::Scalar>,
+        V: ::ViewInfo<W::Scalar, W::Transform>,
+    {
+        self.relate(node, skeleton, bound).1
+    }
+
     /// Cull and draw the entities into a stream.
     pub fn draw<'b, R, M, V, I, H, S>(&mut self, entities: I, phase: &mut H, stream: &mut S)
                 -> Result<::Report, ::Error> where
@@ -109,6 +270,7 @@ impl<'a, 'c,
         R::Texture: 'b,
         R::Sampler: 'b,
         M: 'b,
+        B: SkinBound<W::Scalar>,
         V: ::ViewInfo<W::Scalar, W::Transform>,
         I: Iterator<Item = &'b ::Entity<R, M, W, B>>,
         H: gfx_phase::AbstractPhase<R, M, V>,
@@ -121,19 +283,37 @@ impl<'a, 'c,
                 report.calls_invisible += ent.fragments.len() as ::Count;
                 continue
             }
-            if let Some(view_info) = self.is_visible(&ent.node, &ent.bound) {
-                for frag in ent.fragments.iter() {
-                    match phase.enqueue(&ent.mesh, &frag.slice, &frag.material, &view_info) {
-                        Ok(true)  => {
-                            report.primitives_rendered += frag.slice.get_prim_count();
-                            report.calls_passed += 1;
-                        },
-                        Ok(false) => report.calls_rejected += 1,
-                        Err(e)    => return Err(::Error::Batch(e)),
+            if ent.layers & self.layers == 0 {
+                report.calls_masked += ent.fragments.len() as ::Count;
+                continue
+            }
+            let (relation, view_info, bones, mvp) = self.relate(&ent.node,
+                ent.skeleton.as_ref(), &ent.bound);
+            let view_info: V = match view_info {
+                Some(v) => v,
+                None => {
+                    report.calls_culled += ent.fragments.len() as ::Count;
+                    continue
+                },
+            };
+            for frag in ent.fragments.iter() {
+                // only partially visible entities need the fragments culled
+                if relation == cgmath::Relation::Cross {
+                    if let Some(ref bound) = frag.bound {
+                        if self.cull_bound(bound, bones, &mvp) == cgmath::Relation::Out {
+                            report.calls_culled += 1;
+                            continue
+                        }
                     }
                 }
-            }else {
-                report.calls_culled += ent.fragments.len() as ::Count;
+                match phase.enqueue(&ent.mesh, &frag.slice, &frag.material, &view_info) {
+                    Ok(true)  => {
+                        report.primitives_rendered += frag.slice.get_prim_count();
+                        report.calls_passed += 1;
+                    },
+                    Ok(false) => report.calls_rejected += 1,
+                    Err(e)    => return Err(::Error::Batch(e)),
+                }
             }
         }
         // flush into the renderer
@@ -143,3 +323,159 @@ impl<'a, 'c,
         }
     }
 }
+
+
+/// A set of entity fragments visible by a single view.
+pub struct VisibleSet<V> {
+    /// Indices of visible entities, with their view info.
+    pub entities: Vec<(usize, V)>,
+    /// Visible fragments, as pairs of an index into `entities`
+    /// and an index into the fragments of the entity.
+    pub fragments: Vec<(usize, usize)>,
+    /// Culling report of the view.
+    pub report: ::Report,
+}
+
+impl<V> VisibleSet<V> {
+    /// Draw the visible fragments into a stream, given the same slice
+    /// of entities the set was culled from.
+    pub fn draw<R, M, W, B, H, S>(&self, entities: &[::Entity<R, M, W, B>],
+                phase: &mut H, stream: &mut S)
+                -> Result<::Report, ::Error> where
+        R: gfx::Resources,
+        W: ::World,
+        H: gfx_phase::AbstractPhase<R, M, V>,
+        S: gfx::Stream<R>,
+    {
+        let mut report = self.report.clone();
+        for &(index, fi) in self.fragments.iter() {
+            let (ei, ref view_info) = self.entities[index];
+            let ent = &entities[ei];
+            let frag = &ent.fragments[fi];
+            match phase.enqueue(&ent.mesh, &frag.slice, &frag.material, view_info) {
+                Ok(true)  => {
+                    report.primitives_rendered += frag.slice.get_prim_count();
+                    report.calls_passed += 1;
+                },
+                Ok(false) => report.calls_rejected += 1,
+                Err(e)    => return Err(::Error::Batch(e)),
+            }
+        }
+        match phase.flush(stream) {
+            Ok(()) => Ok(report),
+            Err(e) => Err(::Error::Flush(e)),
+        }
+    }
+}
+
+/// Culler context for multiple views, such as shadow cascades or cube
+/// map faces. Entities are traversed only once for all the views.
+pub struct MultiContext<'a, 'c, W, B, C> where
+    W: ::World + 'a,
+    B: cgmath::Bound<W::Scalar>,
+    C: Culler<W::Scalar, B> + 'c,
+{
+    world: &'a W,
+    culler: &'c mut C,
+    views: Vec<(::View<W::Scalar, W::Transform>, cgmath::Matrix4<W::Scalar>)>,
+    lights: Vec<LightSet<W::Scalar>>,
+    dummy: PhantomData<B>,
+}
+
+impl<'a, 'c,
+    W: ::World,
+    B: SkinBound<W::Scalar>,
+    C: Culler<W::Scalar, B>,
+> MultiContext<'a, 'c, W, B, C> {
+    /// Create a new context for the given views.
+    pub fn new(world: &'a W, culler: &'c mut C,
+               views: &[::View<W::Scalar, W::Transform>])
+               -> MultiContext<'a, 'c, W, B, C> {
+        culler.init();
+        MultiContext {
+            world: world,
+            culler: culler,
+            views: views.iter().map(|v| (v.clone(), v.get_view_projection()))
+                               .collect(),
+            lights: views.iter().map(|_| LightSet::new()).collect(),
+            dummy: PhantomData,
+        }
+    }
+
+    /// Set the lights to be assigned to entities. The lights are culled
+    /// against each view separately, and up to `max` most relevant of them
+    /// are passed to the view info of each entity they intersect.
+    pub fn set_lights(&mut self, lights: &[::Light<W::Scalar, W::NodePtr>], max: usize) {
+        for (&(_, ref projection), set) in self.views.iter().zip(self.lights.iter_mut()) {
+            set.set(self.world, lights, projection, max);
+        }
+    }
+
+    /// Cull the entities against all the views, producing a visible set
+    /// per view, in the order of views.
+    pub fn cull<R, M, V>(&mut self, entities: &[::Entity<R, M, W, B>])
+                -> Vec<VisibleSet<V>> where
+        R: gfx::Resources,
+        V: ::ViewInfo<W::Scalar, W::Transform>,
+    {
+        use cgmath::{Matrix, Transform};
+        let mut sets: Vec<_> = self.views.iter().map(|_| VisibleSet {
+            entities: Vec::new(),
+            fragments: Vec::new(),
+            report: ::Report::new(),
+        }).collect();
+        let world = self.world;
+        for (ei, ent) in entities.iter().enumerate() {
+            let num = ent.fragments.len() as ::Count;
+            if !ent.visible {
+                for set in sets.iter_mut() {
+                    set.report.calls_invisible += num;
+                }
+                continue
+            }
+            let model = world.get_transform(&ent.node);
+            let model_mx: cgmath::Matrix4<W::Scalar> = model.clone().into();
+            let bones = match ent.skeleton {
+                Some(ref s) => world.get_bones(s),
+                None => &[][..],
+            };
+            let skinned;
+            let bound = if bones.is_empty() {
+                &ent.bound
+            }else {
+                skinned = ent.bound.skin(bones);
+                &skinned
+            };
+            for ((&(ref view, ref projection), set), lights) in self.views.iter()
+                    .zip(sets.iter_mut()).zip(self.lights.iter_mut()) {
+                if ent.layers & view.layers == 0 {
+                    set.report.calls_masked += num;
+                    continue
+                }
+                let mvp = projection.mul_m(&model_mx);
+                let relation = self.culler.cull(bound, &mvp);
+                if relation == cgmath::Relation::Out {
+                    set.report.calls_culled += num;
+                    continue
+                }
+                let index = set.entities.len();
+                let view_info = ::ViewInfo::new(mvp, view.transform.concat(&model),
+                                                model.clone(), bones,
+                                                lights.assign(&model, bound));
+                set.entities.push((ei, view_info));
+                for (fi, frag) in ent.fragments.iter().enumerate() {
+                    if relation == cgmath::Relation::Cross {
+                        if let Some(ref bound) = frag.bound {
+                            if cull_bound(self.culler, bound, bones, &mvp) == cgmath::Relation::Out {
+                                set.report.calls_culled += 1;
+                                continue
+                            }
+                        }
+                    }
+                    set.fragments.push((index, fi));
+                }
+            }
+        }
+        sets
+    }
+}
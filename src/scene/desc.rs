@@ -0,0 +1,354 @@
+//! Serializable scene description.
+//!
+//! A scene is described in JSON by a `SceneDesc`, for example:
+//!
+//! ```json
+//! {
+//!     "entities": [{
+//!         "name": "box",
+//!         "visible": true,
+//!         "layers": 1,
+//!         "node": {"scale": 1.0, "rotation": [1.0, 0.0, 0.0, 0.0], "translation": [0.0, 0.0, 0.0]},
+//!         "bound": {"min": [-1.0, -1.0, -1.0], "max": [1.0, 1.0, 1.0]},
+//!         "mesh": "box.mesh",
+//!         "fragments": [{"material": "wood", "range": null}]
+//!     }],
+//!     "cameras": [{
+//!         "name": "main",
+//!         "layers": 4294967295,
+//!         "node": {"scale": 1.0, "rotation": [1.0, 0.0, 0.0, 0.0], "translation": [0.0, 0.0, 5.0]},
+//!         "projection": {"variant": "Perspective", "fields": [[1.0, 1.5, 0.1, 100.0]]}
+//!     }]
+//! }
+//! ```
+//!
+//! Rotations are quaternions in the `[w, x, y, z]` order. Meshes, materials,
+//! nodes, and projections are resolved by a user `Loader`, so the description
+//! can be loaded without any GPU.
+
+use rustc_serialize::json;
+use cgmath;
+use gfx;
+
+/// Node transformation: scale, then rotate, then translate.
+#[derive(Clone, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct TransformDesc {
+    /// Uniform scale.
+    pub scale: f32,
+    /// Rotation quaternion, `[w, x, y, z]`.
+    pub rotation: [f32; 4],
+    /// Translation vector.
+    pub translation: [f32; 3],
+}
+
+/// Axis-aligned bounding box.
+#[derive(Clone, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct BoundDesc {
+    /// Minimum corner.
+    pub min: [f32; 3],
+    /// Maximum corner.
+    pub max: [f32; 3],
+}
+
+/// Fragment description.
+#[derive(Clone, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct FragmentDesc {
+    /// Material identifier.
+    pub material: String,
+    /// Start and end of the fragment within the mesh slice.
+    /// The whole slice is used if not specified.
+    pub range: Option<[u32; 2]>,
+    /// Optional bound of the fragment.
+    pub bound: Option<BoundDesc>,
+}
+
+/// Entity description.
+#[derive(Clone, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct EntityDesc {
+    /// Name of the entity.
+    pub name: String,
+    /// Visibility flag.
+    pub visible: bool,
+    /// Render layers.
+    pub layers: ::Layers,
+    /// Node transformation.
+    pub node: TransformDesc,
+    /// Spatial bound.
+    pub bound: BoundDesc,
+    /// Mesh identifier.
+    pub mesh: String,
+    /// List of fragments.
+    pub fragments: Vec<FragmentDesc>,
+}
+
+/// Camera projection description.
+#[derive(Clone, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum ProjectionDesc {
+    /// Perspective projection: vertical field of view in radians,
+    /// aspect ratio, near and far planes.
+    Perspective([f32; 4]),
+    /// Orthographic projection: left, right, bottom, top,
+    /// near and far planes.
+    Ortho([f32; 6]),
+}
+
+/// Camera description.
+#[derive(Clone, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct CameraDesc {
+    /// Name of the camera.
+    pub name: String,
+    /// Render layers.
+    pub layers: ::Layers,
+    /// Node transformation.
+    pub node: TransformDesc,
+    /// Projection.
+    pub projection: ProjectionDesc,
+}
+
+/// Scene description.
+#[derive(Clone, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct SceneDesc {
+    /// List of entities.
+    pub entities: Vec<EntityDesc>,
+    /// List of cameras.
+    pub cameras: Vec<CameraDesc>,
+}
+
+/// Scene loading error.
+#[derive(Debug)]
+pub enum LoadError {
+    /// Error in parsing the text.
+    Decode(json::DecoderError),
+    /// Mesh could not be resolved, with the entity name.
+    Mesh(String),
+    /// Material could not be resolved, with the entity name.
+    Material(String),
+    /// Fragment range is out of the mesh slice, with the entity name.
+    Range(String, [u32; 2]),
+}
+
+/// User-provided resolver of the scene description references.
+pub trait Loader<R: gfx::Resources, M, W: ::World, P> {
+    /// Create a new world node with a given transformation.
+    fn add_node(&mut self, &mut W, &TransformDesc) -> W::NodePtr;
+    /// Find a mesh and the full slice of it by identifier.
+    fn get_mesh(&mut self, &str) -> Option<(gfx::Mesh<R>, gfx::Slice<R>)>;
+    /// Find a material by identifier.
+    fn get_material(&mut self, &str) -> Option<M>;
+    /// Create a projection.
+    fn get_projection(&mut self, &ProjectionDesc) -> P;
+}
+
+/// User-provided producer of the scene description references,
+/// the counterpart of `Loader`.
+pub trait Saver<R: gfx::Resources, M, W: ::World, P> {
+    /// Describe the transformation of a world node.
+    fn get_node(&mut self, &W, &W::NodePtr) -> TransformDesc;
+    /// Find the identifier of a mesh and the full slice of it.
+    fn get_mesh(&mut self, &gfx::Mesh<R>) -> Option<(String, gfx::Slice<R>)>;
+    /// Find the identifier of a material.
+    fn get_material(&mut self, &M) -> Option<String>;
+    /// Describe a projection.
+    fn get_projection(&mut self, &P) -> ProjectionDesc;
+}
+
+fn make_bound<S: cgmath::BaseFloat>(desc: &BoundDesc) -> cgmath::Aabb3<S> {
+    let p = |v: &[f32; 3]| cgmath::Point3::new(
+        S::from(v[0]).unwrap(), S::from(v[1]).unwrap(), S::from(v[2]).unwrap());
+    cgmath::Aabb3::new(p(&desc.min), p(&desc.max))
+}
+
+fn make_bound_desc<S: cgmath::BaseFloat>(bound: &cgmath::Aabb3<S>) -> BoundDesc {
+    let v = |p: &cgmath::Point3<S>| [
+        p.x.to_f32().unwrap(), p.y.to_f32().unwrap(), p.z.to_f32().unwrap()];
+    BoundDesc {
+        min: v(&bound.min),
+        max: v(&bound.max),
+    }
+}
+
+impl SceneDesc {
+    /// Parse a description from JSON text.
+    pub fn from_json(text: &str) -> Result<SceneDesc, LoadError> {
+        json::decode(text).map_err(|e| LoadError::Decode(e))
+    }
+
+    /// Write the description into JSON text.
+    pub fn to_json(&self) -> String {
+        json::as_pretty_json(self).to_string()
+    }
+
+    /// Create a scene from the description, resolving the references
+    /// with a given loader. Errors carry the name of the failing entity.
+    pub fn load<R, M, W, P, V, L>(&self, world: W, loader: &mut L)
+                -> Result<::Scene<R, M, W, cgmath::Aabb3<W::Scalar>, P, V>, LoadError> where
+        R: gfx::Resources,
+        W: ::World,
+        L: Loader<R, M, W, P>,
+    {
+        let mut scene = ::Scene::new(world);
+        for ed in self.entities.iter() {
+            let (mesh, slice) = match loader.get_mesh(&ed.mesh) {
+                Some(ms) => ms,
+                None => return Err(LoadError::Mesh(ed.name.clone())),
+            };
+            let node = loader.add_node(&mut scene.world, &ed.node);
+            let mut ent = ::Entity::new(mesh, node, make_bound(&ed.bound));
+            ent.name = ed.name.clone();
+            ent.visible = ed.visible;
+            ent.layers = ed.layers;
+            for fd in ed.fragments.iter() {
+                let material = match loader.get_material(&fd.material) {
+                    Some(m) => m,
+                    None => return Err(LoadError::Material(ed.name.clone())),
+                };
+                let mut frag_slice = slice.clone();
+                if let Some(range) = fd.range {
+                    let (start, end) = match (slice.start.checked_add(range[0]),
+                                              slice.start.checked_add(range[1])) {
+                        (Some(s), Some(e)) if s <= e && e <= slice.end => (s, e),
+                        _ => return Err(LoadError::Range(ed.name.clone(), range)),
+                    };
+                    frag_slice.start = start;
+                    frag_slice.end = end;
+                }
+                let mut frag = ::Fragment::new(material, frag_slice);
+                frag.bound = fd.bound.as_ref().map(|b| make_bound(b));
+                ent.fragments.push(frag);
+            }
+            scene.entities.push(ent);
+        }
+        for cd in self.cameras.iter() {
+            scene.cameras.push(::Camera {
+                name: cd.name.clone(),
+                projection: loader.get_projection(&cd.projection),
+                node: loader.add_node(&mut scene.world, &cd.node),
+                layers: cd.layers,
+            });
+        }
+        Ok(scene)
+    }
+
+    /// Describe an existing scene, producing the references with a given
+    /// saver. Errors carry the name of the failing entity.
+    pub fn from_scene<R, M, W, P, V, X>(scene: &::Scene<R, M, W, cgmath::Aabb3<W::Scalar>, P, V>,
+                      saver: &mut X) -> Result<SceneDesc, LoadError> where
+        R: gfx::Resources,
+        W: ::World,
+        X: Saver<R, M, W, P>,
+    {
+        let mut desc = SceneDesc {
+            entities: Vec::new(),
+            cameras: Vec::new(),
+        };
+        for ent in scene.entities.iter() {
+            let (mesh, slice) = match saver.get_mesh(&ent.mesh) {
+                Some(ms) => ms,
+                None => return Err(LoadError::Mesh(ent.name.clone())),
+            };
+            let mut fragments = Vec::new();
+            for frag in ent.fragments.iter() {
+                let material = match saver.get_material(&frag.material) {
+                    Some(m) => m,
+                    None => return Err(LoadError::Material(ent.name.clone())),
+                };
+                let range = if frag.slice.start == slice.start && frag.slice.end == slice.end {
+                    None
+                }else if frag.slice.start >= slice.start && frag.slice.start <= frag.slice.end &&
+                        frag.slice.end <= slice.end {
+                    Some([frag.slice.start - slice.start, frag.slice.end - slice.start])
+                }else {
+                    return Err(LoadError::Range(ent.name.clone(),
+                                                [frag.slice.start, frag.slice.end]))
+                };
+                fragments.push(FragmentDesc {
+                    material: material,
+                    range: range,
+                    bound: frag.bound.as_ref().map(|b| make_bound_desc(b)),
+                });
+            }
+            desc.entities.push(EntityDesc {
+                name: ent.name.clone(),
+                visible: ent.visible,
+                layers: ent.layers,
+                node: saver.get_node(&scene.world, &ent.node),
+                bound: make_bound_desc(&ent.bound),
+                mesh: mesh,
+                fragments: fragments,
+            });
+        }
+        for cam in scene.cameras.iter() {
+            desc.cameras.push(CameraDesc {
+                name: cam.name.clone(),
+                layers: cam.layers,
+                node: saver.get_node(&scene.world, &cam.node),
+                projection: saver.get_projection(&cam.projection),
+            });
+        }
+        Ok(desc)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn make_desc() -> SceneDesc {
+        let node = TransformDesc {
+            scale: 2.0,
+            rotation: [1.0, 0.0, 0.0, 0.0],
+            translation: [0.5, -1.0, 3.0],
+        };
+        SceneDesc {
+            entities: vec![EntityDesc {
+                name: "box".to_string(),
+                visible: true,
+                layers: 1,
+                node: node.clone(),
+                bound: BoundDesc { min: [-1.0; 3], max: [1.0; 3] },
+                mesh: "box.mesh".to_string(),
+                fragments: vec![
+                    FragmentDesc {
+                        material: "wood".to_string(),
+                        range: None,
+                        bound: None,
+                    },
+                    FragmentDesc {
+                        material: "metal".to_string(),
+                        range: Some([6, 12]),
+                        bound: Some(BoundDesc { min: [0.0; 3], max: [1.0; 3] }),
+                    },
+                ],
+            }],
+            cameras: vec![
+                CameraDesc {
+                    name: "main".to_string(),
+                    layers: ::ALL_LAYERS,
+                    node: node.clone(),
+                    projection: ProjectionDesc::Perspective([1.0, 1.5, 0.1, 100.0]),
+                },
+                CameraDesc {
+                    name: "map".to_string(),
+                    layers: 2,
+                    node: node,
+                    projection: ProjectionDesc::Ortho([-1.0, 1.0, -1.0, 1.0, 0.0, 10.0]),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let desc = make_desc();
+        let text = desc.to_json();
+        assert_eq!(SceneDesc::from_json(&text).unwrap(), desc);
+    }
+
+    #[test]
+    fn decode_error() {
+        match SceneDesc::from_json("{\"entities\": []}") {
+            Err(LoadError::Decode(_)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+}
@@ -5,13 +5,31 @@
 extern crate gfx_phase;
 extern crate gfx;
 extern crate cgmath;
+#[cfg(feature = "serialize")]
+extern crate rustc_serialize;
+#[cfg(test)]
+extern crate gfx_mock;
 
 use std::fmt::Debug;
 use std::marker::PhantomData;
 
+mod cluster;
 mod cull;
+#[cfg(feature = "serialize")]
+mod desc;
+mod light;
+mod pick;
+mod view;
 
-pub use self::cull::{Culler, Frustum, Context};
+pub use self::cluster::{ClusterGrid, Clusters};
+pub use self::cull::{Culler, Frustum, Context, SkinBound,
+                     MultiContext, VisibleSet};
+#[cfg(feature = "serialize")]
+pub use self::desc::{TransformDesc, BoundDesc, FragmentDesc, EntityDesc,
+                     ProjectionDesc, CameraDesc, SceneDesc, LoadError, Loader, Saver};
+pub use self::light::{LightKind, Light, LightInfo};
+pub use self::pick::{RayBound, Hit};
+pub use self::view::{View, cascade_splits};
 
 /// Scene drawing error.
 #[derive(Debug)]
@@ -20,16 +38,27 @@ pub enum Error {
     Batch(gfx::batch::Error),
     /// Error in sending a batch for drawing.
     Flush(gfx_phase::FlushError),
+    /// Camera node transformation can not be inverted.
+    SingularCamera,
 }
 
 /// Type of the call counter.
 pub type Count = u32;
 
+/// Bit mask of render layers. An entity is only drawn by a camera
+/// if their layer masks intersect.
+pub type Layers = u32;
+
+/// Layer mask that matches everything.
+pub const ALL_LAYERS: Layers = !0;
+
 /// Rendering success report.
 #[derive(Clone, Debug)]
 pub struct Report {
     /// Number of calls in invisible entities.
     pub calls_invisible: Count,
+    /// Number of calls in entities rejected by the camera layer mask.
+    pub calls_masked: Count,
     /// Number of calls that got culled out.
     pub calls_culled: Count,
     /// Number of calls that the phase doesn't apply to.
@@ -50,16 +79,28 @@ impl Report {
             calls_failed: 0,
             calls_culled: 0,
             calls_invisible: 0,
+            calls_masked: 0,
             calls_passed: 0,
             primitives_rendered: 0,
         }
     }
 
+    /// Add the counters of another report to this one.
+    pub fn accumulate(&mut self, other: &Report) {
+        self.calls_invisible += other.calls_invisible;
+        self.calls_masked += other.calls_masked;
+        self.calls_culled += other.calls_culled;
+        self.calls_rejected += other.calls_rejected;
+        self.calls_failed += other.calls_failed;
+        self.calls_passed += other.calls_passed;
+        self.primitives_rendered += other.primitives_rendered;
+    }
+
     /// Get total number of draw calls.
     pub fn get_calls_total(&self) -> Count {
-        self.calls_invisible + self.calls_culled +
-        self.calls_rejected  + self.calls_failed +
-        self.calls_passed
+        self.calls_invisible + self.calls_masked +
+        self.calls_culled    + self.calls_rejected +
+        self.calls_failed    + self.calls_passed
     }
 
     /// Get the rendered/submitted calls ratio.
@@ -99,23 +140,34 @@ pub trait World {
     type SkeletonPtr;
     /// Get the transformation of a specific node pointer.
     fn get_transform(&self, &Self::NodePtr) -> Self::Transform;
+    /// Get the bone matrix palette of a specific skeleton pointer.
+    /// Each matrix transforms from the bind pose into the current pose,
+    /// relative to the model space of the entity. Worlds without
+    /// skinning don't need to implement it.
+    fn get_bones(&self, _: &Self::SkeletonPtr) -> &[cgmath::Matrix4<Self::Scalar>] {
+        &[]
+    }
 }
 
 /// A fragment of an entity, contains a single draw call.
 #[derive(Clone, Debug)]
-pub struct Fragment<R: gfx::Resources, M> {
+pub struct Fragment<R: gfx::Resources, M, B> {
     /// Fragment material.
     pub material: M,
     /// Mesh slice.
     pub slice: gfx::Slice<R>,
+    /// Optional spatial bound of the slice, in the entity space.
+    /// It's only checked when the entity bound crosses the frustum.
+    pub bound: Option<B>,
 }
 
-impl<R: gfx::Resources, M> Fragment<R, M> {
+impl<R: gfx::Resources, M, B> Fragment<R, M, B> {
     /// Create a new fragment.
-    pub fn new(mat: M, slice: gfx::Slice<R>) -> Fragment<R, M> {
+    pub fn new(mat: M, slice: gfx::Slice<R>) -> Fragment<R, M, B> {
         Fragment {
             material: mat,
             slice: slice,
+            bound: None,
         }
     }
 }
@@ -128,6 +180,8 @@ pub struct Entity<R: gfx::Resources, M, W: World, B> {
     pub name: String,
     /// Visibility flag.
     pub visible: bool,
+    /// Render layers the entity belongs to.
+    pub layers: Layers,
     /// Mesh.
     pub mesh: gfx::Mesh<R>,
     /// Node pointer into the world.
@@ -137,7 +191,7 @@ pub struct Entity<R: gfx::Resources, M, W: World, B> {
     /// Associated spatial bound of the entity.
     pub bound: B,
     /// Vector of fragments, each of a different material.
-    pub fragments: Vec<Fragment<R, M>>,
+    pub fragments: Vec<Fragment<R, M, B>>,
 }
 
 impl<R: gfx::Resources, M, W: World, B> Entity<R, M, W, B> {
@@ -146,6 +200,7 @@ impl<R: gfx::Resources, M, W: World, B> Entity<R, M, W, B> {
         Entity {
             name: String::new(),
             visible: true,
+            layers: ALL_LAYERS,
             mesh: mesh,
             node: node,
             skeleton: None,
@@ -164,6 +219,8 @@ pub struct Camera<P, N> {
     pub projection: P,
     /// Generic spatial node.
     pub node: N,
+    /// Render layers visible by the camera.
+    pub layers: Layers,
 }
 
 impl<
@@ -173,18 +230,41 @@ impl<
     P: Into<cgmath::Matrix4<S>> + Clone,
 > Camera<P, W::NodePtr> {
     /// Get the view-projection matrix, given the `World`.
-    pub fn get_view_projection(&self, world: &W) -> cgmath::Matrix4<S> {
+    pub fn get_view_projection(&self, world: &W) -> Result<cgmath::Matrix4<S>, Error> {
         use cgmath::{Matrix, Transform};
-        let node_inverse = world.get_transform(&self.node).invert().unwrap();
-        self.projection.clone().into().mul_m(&node_inverse.into())
+        match world.get_transform(&self.node).invert() {
+            Some(node_inverse) => Ok(self.projection.clone().into()
+                                         .mul_m(&node_inverse.into())),
+            None => Err(Error::SingularCamera),
+        }
+    }
+
+    /// Get a normalized world-space ray going through a point on the screen,
+    /// given in normalized device coordinates from -1 to 1. Fails with
+    /// `SingularCamera` if the view-projection can not be inverted.
+    pub fn get_ray(&self, world: &W, x: S, y: S) -> Result<cgmath::Ray3<S>, Error> {
+        use cgmath::{EuclideanVector, Matrix, Point};
+        let inverse = match try!(self.get_view_projection(world)).invert() {
+            Some(m) => m,
+            None => return Err(Error::SingularCamera),
+        };
+        let one: S = cgmath::one();
+        let unproject = |z: S| cgmath::Point3::from_homogeneous(
+            &inverse.mul_v(&cgmath::Vector4::new(x, y, z, one)));
+        let near = unproject(-one);
+        let far = unproject(one);
+        Ok(cgmath::Ray3::new(near, far.sub_p(&near).normalize()))
     }
 }
 
 /// Abstract information about the view. Supposed to containt at least
 /// Model-View-Projection transform for the shader.
 pub trait ViewInfo<S, T: cgmath::Transform3<S>>: gfx_phase::ToDepth<Depth = S> {
-    /// Construct a new information block.
-    fn new(mvp: cgmath::Matrix4<S>, view: T, model: T) -> Self;
+    /// Construct a new information block. The bone palette is empty
+    /// for entities without a skeleton. The lights affecting the entity
+    /// are sorted by relevance, most relevant first.
+    fn new(mvp: cgmath::Matrix4<S>, view: T, model: T,
+           bones: &[cgmath::Matrix4<S>], lights: &[LightInfo<S>]) -> Self;
 }
 
 /// An example scene type.
@@ -194,6 +274,10 @@ pub struct Scene<R: gfx::Resources, M, W: World, B, P, V> {
     /// A list of cameras. It's not really useful, but `P` needs to be
     /// constrained in order to be able to implement `AbstractScene`.
     pub cameras: Vec<Camera<P, W::NodePtr>>,
+    /// A list of light sources.
+    pub lights: Vec<Light<W::Scalar, W::NodePtr>>,
+    /// Maximum number of lights passed to the view info of an entity.
+    pub max_lights: usize,
     /// Spatial world.
     pub world: W,
     _view_dummy: PhantomData<V>,
@@ -205,6 +289,8 @@ impl<R: gfx::Resources, M, W: World, B, P, V> Scene<R, M, W, B, P, V> {
         Scene {
             entities: Vec::new(),
             cameras: Vec::new(),
+            lights: Vec::new(),
+            max_lights: 4,
             world: world,
             _view_dummy: PhantomData,
         }
@@ -215,7 +301,7 @@ impl<
     R: gfx::Resources,
     M: gfx_phase::Material,
     W: World,
-    B: cgmath::Bound<W::Scalar> + Debug,
+    B: SkinBound<W::Scalar> + Debug,
     P: cgmath::Projection<W::Scalar> + Clone,
     V: ViewInfo<W::Scalar, W::Transform>,
 > AbstractScene<R> for Scene<R, M, W, B, P, V> {
@@ -230,8 +316,9 @@ impl<
         S: gfx::Stream<R>,
     {
         let mut culler = Frustum::new();
-        Context::new(&self.world, &mut culler, camera)
-                .draw(self.entities.iter(), phase, stream)
+        let mut context = try!(Context::new(&self.world, &mut culler, camera));
+        context.set_lights(&self.lights, self.max_lights);
+        context.draw(self.entities.iter(), phase, stream)
     }
 }
 
@@ -240,3 +327,32 @@ pub type PerspectiveCam<W: World> = Camera<
     cgmath::PerspectiveFov<W::Scalar, cgmath::Rad<W::Scalar>>,
     W::NodePtr
 >;
+
+#[cfg(test)]
+mod test {
+    use cgmath;
+
+    /// Transformation used by the tests.
+    pub type Transform = cgmath::Decomposed<f32, cgmath::Vector3<f32>, cgmath::Quaternion<f32>>;
+
+    /// Translation by a vector.
+    pub fn translate(x: f32, y: f32, z: f32) -> Transform {
+        use cgmath::Transform;
+        let mut t: Transform = Transform::identity();
+        t.disp = cgmath::Vector3::new(x, y, z);
+        t
+    }
+
+    /// World of a flat list of node transforms.
+    pub struct World(pub Vec<Transform>);
+
+    impl ::World for World {
+        type Scalar = f32;
+        type Transform = Transform;
+        type NodePtr = usize;
+        type SkeletonPtr = ();
+        fn get_transform(&self, node: &usize) -> Transform {
+            self.0[*node].clone()
+        }
+    }
+}
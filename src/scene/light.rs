@@ -0,0 +1,135 @@
+//! Light sources and their assignment to entities.
+
+use std::f64;
+use cgmath;
+
+/// Type of a light source.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind<S> {
+    /// Infinitely far light, shining along the -Z axis of the node.
+    /// It affects everything regardless of the range.
+    Directional,
+    /// Omni-directional light.
+    Point,
+    /// Cone light, shining along the -Z axis of the node,
+    /// with the given half-angle of the cone.
+    Spot(cgmath::Rad<S>),
+}
+
+/// A light source with a spatial relation.
+#[derive(Clone, Debug)]
+pub struct Light<S, N> {
+    /// Name of the light.
+    pub name: String,
+    /// Type of the light.
+    pub kind: LightKind<S>,
+    /// Light color, with the intensity in the alpha channel.
+    pub color: [f32; 4],
+    /// Distance at which the light stops affecting anything.
+    pub range: S,
+    /// Generic spatial node.
+    pub node: N,
+}
+
+impl<S: cgmath::BaseFloat + 'static, N> Light<S, N> {
+    /// Resolve the light in the given world.
+    pub fn resolve<W>(&self, world: &W) -> LightInfo<S> where
+        W: ::World<Scalar = S, NodePtr = N>,
+    {
+        use cgmath::{EuclideanVector, Point, Transform};
+        let transform = world.get_transform(&self.node);
+        LightInfo {
+            kind: self.kind,
+            color: self.color,
+            position: transform.transform_point(&cgmath::Point3::origin()),
+            direction: transform.transform_vector(&-cgmath::Vector3::unit_z())
+                                .normalize(),
+            range: self.range,
+        }
+    }
+}
+
+/// A light resolved into the world space, as passed to the view info.
+#[derive(Clone, Copy, Debug)]
+pub struct LightInfo<S> {
+    /// Type of the light.
+    pub kind: LightKind<S>,
+    /// Light color, with the intensity in the alpha channel.
+    pub color: [f32; 4],
+    /// World-space position.
+    pub position: cgmath::Point3<S>,
+    /// World-space normalized direction.
+    pub direction: cgmath::Vector3<S>,
+    /// Distance at which the light stops affecting anything.
+    pub range: S,
+}
+
+impl<S: cgmath::BaseFloat + 'static> LightInfo<S> {
+    /// Get the matrix that transforms the affected volume of the light
+    /// into the clip space cube. Returns `None` for directional lights,
+    /// which affect everything. Spot lights with a half-angle of 90 degrees
+    /// or more get the volume of a point light.
+    pub fn get_volume(&self) -> Option<cgmath::Matrix4<S>> {
+        use cgmath::{Matrix, Point};
+        let (p, r) = (self.position, self.range);
+        match self.kind {
+            LightKind::Directional => None,
+            LightKind::Spot(angle) if angle.s + angle.s < S::from(f64::consts::PI).unwrap() => {
+                let one: S = cgmath::one();
+                let up = if self.direction.z.abs() < self.direction.x.abs() + self.direction.y.abs() {
+                    cgmath::Vector3::unit_z()
+                }else {
+                    cgmath::Vector3::unit_x()
+                };
+                let view = cgmath::Matrix4::look_at(&p, &p.add_v(&self.direction), &up);
+                let near = r / S::from(1000).unwrap();
+                let proj = cgmath::perspective(cgmath::rad(angle.s + angle.s), one, near, r);
+                Some(proj.mul_m(&view))
+            },
+            // spot lights as wide as a half-space can't be projected,
+            // so they are bound by the range like point lights
+            LightKind::Spot(_) | LightKind::Point => Some(cgmath::ortho(
+                p.x - r, p.x + r, p.y - r, p.y + r, -(p.z + r), -(p.z - r))),
+        }
+    }
+
+    /// Check if the light can affect anything visible by a view-projection.
+    pub fn is_visible(&self, view_projection: &cgmath::Matrix4<S>) -> bool {
+        use cgmath::{Bound, Point, Vector};
+        match self.kind {
+            LightKind::Directional => true,
+            _ => {
+                let extent = cgmath::Vector3::from_value(self.range);
+                let aabb = cgmath::Aabb3::new(self.position.sub_v(&extent),
+                                              self.position.add_v(&extent));
+                aabb.relate_clip_space(view_projection) != cgmath::Relation::Out
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath;
+    use super::{LightKind, LightInfo};
+
+    fn make_light(kind: LightKind<f32>) -> LightInfo<f32> {
+        LightInfo {
+            kind: kind,
+            color: [1.0; 4],
+            position: cgmath::Point3::new(1.0, 2.0, 3.0),
+            direction: cgmath::Vector3::new(0.0, 0.0, -1.0),
+            range: 10.0,
+        }
+    }
+
+    #[test]
+    fn wide_spot_volume() {
+        let point = make_light(LightKind::Point).get_volume();
+        let narrow = make_light(LightKind::Spot(cgmath::rad(0.5))).get_volume();
+        let wide = make_light(LightKind::Spot(cgmath::rad(2.0))).get_volume();
+        assert!(narrow.is_some() && narrow != point);
+        assert_eq!(wide, point);
+        assert_eq!(make_light(LightKind::Directional).get_volume(), None);
+    }
+}
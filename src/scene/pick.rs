@@ -0,0 +1,211 @@
+//! Spatial queries for picking entities.
+
+use std::cmp::Ordering;
+use cgmath;
+use gfx;
+
+/// A bound that can be intersected with a ray.
+pub trait RayBound<S>: cgmath::Bound<S> {
+    /// Intersect with a ray, returning the ray parameter of the nearest
+    /// hit point, or `None` if there is no intersection. A ray starting
+    /// inside the bound hits it at zero.
+    fn intersect_ray(&self, &cgmath::Ray3<S>) -> Option<S>;
+}
+
+impl<S: cgmath::BaseFloat + 'static> RayBound<S> for cgmath::Aabb3<S> {
+    fn intersect_ray(&self, ray: &cgmath::Ray3<S>) -> Option<S> {
+        use cgmath::FixedArray;
+        let zero: S = cgmath::zero();
+        let (origin, dir) = (ray.origin.as_fixed(), ray.direction.as_fixed());
+        let (min, max) = (self.min.as_fixed(), self.max.as_fixed());
+        let mut t_near = zero;
+        let mut t_far = None;
+        // the slab test
+        for i in 0..3 {
+            if dir[i] == zero {
+                if origin[i] < min[i] || origin[i] > max[i] {
+                    return None
+                }
+                continue
+            }
+            let t0 = (min[i] - origin[i]) / dir[i];
+            let t1 = (max[i] - origin[i]) / dir[i];
+            let (t0, t1) = if t0 < t1 {(t0, t1)} else {(t1, t0)};
+            if t0 > t_near {
+                t_near = t0;
+            }
+            t_far = Some(match t_far {
+                Some(t) if t < t1 => t,
+                _ => t1,
+            });
+        }
+        match t_far {
+            Some(t) if t < t_near => None,
+            _ => Some(t_near),
+        }
+    }
+}
+
+/// A result of picking.
+pub struct Hit<'a, R: gfx::Resources + 'a, M: 'a, W: ::World + 'a, B: 'a> {
+    /// The entity that got hit.
+    pub entity: &'a ::Entity<R, M, W, B>,
+    /// Index of the fragment that got hit, if refined.
+    pub fragment: Option<usize>,
+    /// Distance along the ray to the hit point.
+    pub distance: W::Scalar,
+}
+
+impl<
+    R: gfx::Resources,
+    M,
+    W: ::World,
+    B: ::SkinBound<W::Scalar> + RayBound<W::Scalar>,
+    P,
+    V,
+> ::Scene<R, M, W, B, P, V> {
+    /// Cast a world-space ray against the bounds of visible entities with
+    /// matching layers. Returns the hits sorted by distance, nearest first.
+    /// If `refine` is set, the fragment bounds are checked as well: the
+    /// nearest hit fragment is reported, and entities whose fragments are
+    /// all bound but missed are discarded.
+    pub fn pick<'a>(&'a self, ray: &cgmath::Ray3<W::Scalar>, layers: ::Layers,
+                refine: bool) -> Vec<Hit<'a, R, M, W, B>> {
+        use cgmath::Transform;
+        let mut hits = Vec::new();
+        for ent in self.entities.iter() {
+            if !ent.visible || ent.layers & layers == 0 {
+                continue
+            }
+            // bring the ray into the model space, keeping the parameter scale
+            let model = self.world.get_transform(&ent.node);
+            let inverse = match model.invert() {
+                Some(t) => t,
+                None => continue,
+            };
+            let local = cgmath::Ray3::new(
+                inverse.transform_point(&ray.origin),
+                inverse.transform_vector(&ray.direction),
+            );
+            let bones = match ent.skeleton {
+                Some(ref s) => self.world.get_bones(s),
+                None => &[][..],
+            };
+            let intersect = |bound: &B| if bones.is_empty() {
+                bound.intersect_ray(&local)
+            }else {
+                bound.skin(bones).intersect_ray(&local)
+            };
+            let distance = match intersect(&ent.bound) {
+                Some(d) => d,
+                None => continue,
+            };
+            let mut hit = Hit {
+                entity: ent,
+                fragment: None,
+                distance: distance,
+            };
+            if refine {
+                let mut all_bound = true;
+                let mut nearest = None;
+                for (i, frag) in ent.fragments.iter().enumerate() {
+                    let d = match frag.bound {
+                        Some(ref b) => match intersect(b) {
+                            Some(d) => d,
+                            None => continue,
+                        },
+                        None => {
+                            all_bound = false;
+                            continue
+                        },
+                    };
+                    nearest = match nearest {
+                        Some((_, nd)) if nd <= d => nearest,
+                        _ => Some((i, d)),
+                    };
+                }
+                match nearest {
+                    Some((i, d)) => {
+                        hit.fragment = Some(i);
+                        hit.distance = d;
+                    },
+                    None if all_bound && !ent.fragments.is_empty() => continue,
+                    None => (),
+                }
+            }
+            hits.push(hit);
+        }
+        hits.sort_by(|a, b| a.distance.partial_cmp(&b.distance)
+                                      .unwrap_or(Ordering::Equal));
+        hits
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath;
+    use gfx;
+    use gfx_mock;
+    use super::RayBound;
+    use test::{World, translate};
+
+    type Scene = ::Scene<gfx_mock::Resources, (), World, cgmath::Aabb3<f32>,
+                         cgmath::Matrix4<f32>, ()>;
+
+    fn make_box() -> cgmath::Aabb3<f32> {
+        cgmath::Aabb3::new(cgmath::Point3::new(-1.0, -1.0, -1.0),
+                           cgmath::Point3::new(1.0, 1.0, 1.0))
+    }
+
+    fn make_ray(origin: [f32; 3], dir: [f32; 3]) -> cgmath::Ray3<f32> {
+        cgmath::Ray3::new(cgmath::Point3::new(origin[0], origin[1], origin[2]),
+                          cgmath::Vector3::new(dir[0], dir[1], dir[2]))
+    }
+
+    #[test]
+    fn slab() {
+        let b = make_box();
+        assert_eq!(b.intersect_ray(&make_ray([0.0, 0.0, -5.0], [0.0, 0.0, 1.0])), Some(4.0));
+        assert_eq!(b.intersect_ray(&make_ray([0.5, 0.0, 0.0], [1.0, 0.0, 0.0])), Some(0.0));
+        // parallel to a slab, outside of it
+        assert_eq!(b.intersect_ray(&make_ray([2.0, 0.0, -5.0], [0.0, 0.0, 1.0])), None);
+        // diagonal passing by the corner
+        assert_eq!(b.intersect_ray(&make_ray([0.0, 3.0, -5.0], [0.0, -1.0, 1.0])), None);
+        // pointing away
+        assert_eq!(b.intersect_ray(&make_ray([0.0, 0.0, 5.0], [0.0, 0.0, 1.0])), None);
+    }
+
+    fn make_scene() -> Scene {
+        let mut scene = Scene::new(World(vec![
+            translate(0.0, 0.0, -10.0),
+            translate(0.0, 0.0, -5.0),
+            translate(5.0, 0.0, -5.0),
+        ]));
+        for i in 0 .. 3 {
+            let mut ent = ::Entity::new(gfx::Mesh::new(0), i, make_box());
+            ent.name = format!("{}", i);
+            scene.entities.push(ent);
+        }
+        scene.entities[0].layers = 2;
+        scene
+    }
+
+    #[test]
+    fn pick() {
+        let scene = make_scene();
+        let ray = make_ray([0.0, 0.0, 0.0], [0.0, 0.0, -1.0]);
+        let hits = scene.pick(&ray, ::ALL_LAYERS, false);
+        let found: Vec<_> = hits.iter().map(|h| (&h.entity.name[..], h.distance)).collect();
+        assert_eq!(found, vec![("1", 4.0), ("0", 9.0)]);
+    }
+
+    #[test]
+    fn pick_layers() {
+        let scene = make_scene();
+        let ray = make_ray([0.0, 0.0, 0.0], [0.0, 0.0, -1.0]);
+        let hits = scene.pick(&ray, 2, false);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].entity.name, "0");
+        assert!(scene.pick(&ray, 4, false).is_empty());
+    }
+}
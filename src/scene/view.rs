@@ -0,0 +1,205 @@
+//! Resolved views and helpers for building shadow views.
+
+use cgmath;
+
+/// A camera resolved into the world space.
+#[derive(Clone, Debug)]
+pub struct View<S, T> {
+    /// World-to-view transformation, inverse of the camera node.
+    pub transform: T,
+    /// Projection matrix.
+    pub projection: cgmath::Matrix4<S>,
+    /// Render layers visible in the view.
+    pub layers: ::Layers,
+}
+
+impl<
+    S: cgmath::BaseFloat + 'static,
+    T: cgmath::Transform3<S> + Clone,
+> View<S, T> {
+    /// Resolve a camera in the given world. Fails if the camera
+    /// transformation can not be inverted.
+    pub fn from_camera<W, P>(world: &W, camera: &::Camera<P, W::NodePtr>)
+                       -> Result<View<S, T>, ::Error> where
+        W: ::World<Scalar = S, Transform = T>,
+        P: cgmath::Projection<S> + Clone,
+    {
+        use cgmath::Transform;
+        match world.get_transform(&camera.node).invert() {
+            Some(t) => Ok(View {
+                transform: t,
+                projection: camera.projection.clone().into(),
+                layers: camera.layers,
+            }),
+            None => Err(::Error::SingularCamera),
+        }
+    }
+
+    /// Get the view-projection matrix.
+    pub fn get_view_projection(&self) -> cgmath::Matrix4<S> {
+        use cgmath::Matrix;
+        self.projection.mul_m(&self.transform.clone().into())
+    }
+
+    /// Build the six cube map face views around a point, in the order of
+    /// +X, -X, +Y, -Y, +Z, -Z, following the GL cube map conventions.
+    pub fn cube_faces(center: cgmath::Point3<S>, near: S, far: S,
+                      layers: ::Layers) -> Vec<View<S, T>> {
+        use cgmath::{Angle, Point};
+        let one: S = cgmath::one();
+        let projection = cgmath::perspective(cgmath::Rad::turn_div_4(), one, near, far);
+        let x = cgmath::Vector3::unit_x();
+        let y = cgmath::Vector3::unit_y();
+        let z = cgmath::Vector3::unit_z();
+        [(x, -y), (-x, -y), (y, z), (-y, -z), (z, -y), (-z, -y)]
+            .iter().map(|&(dir, up)| View {
+                transform: T::look_at(&center, &center.add_v(&dir), &up),
+                projection: projection,
+                layers: layers,
+            }).collect()
+    }
+
+    /// Build a directional light view, looking along `direction`, that
+    /// encloses the slice of a perspective camera frustum between the
+    /// `near` and `far` distances. The near plane is pulled towards the
+    /// light by `backoff`, so that casters outside of the slice are kept.
+    /// Fails if the camera view can not be inverted.
+    pub fn cascade(fov: &cgmath::PerspectiveFov<S, cgmath::Rad<S>>, camera: &T,
+                   near: S, far: S, direction: cgmath::Vector3<S>, backoff: S,
+                   layers: ::Layers) -> Result<View<S, T>, ::Error> {
+        use cgmath::{Matrix, Point, Transform, Vector};
+        let (zero, one): (S, S) = (cgmath::zero(), cgmath::one());
+        let slice: cgmath::Matrix4<S> = cgmath::PerspectiveFov {
+            near: near,
+            far: far,
+            .. fov.clone()
+        }.into();
+        let inverse = match slice.mul_m(&camera.clone().into()).invert() {
+            Some(m) => m,
+            None => return Err(::Error::SingularCamera),
+        };
+        // find the slice corners in world space
+        let mut corners = Vec::with_capacity(8);
+        for &x in [-one, one].iter() {
+            for &y in [-one, one].iter() {
+                for &z in [-one, one].iter() {
+                    corners.push(cgmath::Point3::from_homogeneous(
+                        &inverse.mul_v(&cgmath::Vector4::new(x, y, z, one))));
+                }
+            }
+        }
+        let center = corners.iter().fold(cgmath::Vector3::new(zero, zero, zero),
+            |sum, c| sum.add_v(&c.to_vec()))
+            .div_s(S::from(corners.len()).unwrap());
+        let center = cgmath::Point3::from_vec(&center);
+        // look at the center along the light direction
+        let up = if direction.z.abs() < direction.x.abs() + direction.y.abs() {
+            cgmath::Vector3::unit_z()
+        }else {
+            cgmath::Vector3::unit_x()
+        };
+        let transform = T::look_at(&center.sub_v(&direction), &center, &up);
+        // fit the orthographic projection around the corners
+        let first = transform.transform_point(&corners[0]);
+        let (mut min, mut max) = (first, first);
+        for c in corners[1..].iter() {
+            let p = transform.transform_point(c);
+            min = cgmath::Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = cgmath::Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        Ok(View {
+            transform: transform,
+            projection: cgmath::ortho(min.x, max.x, min.y, max.y,
+                                      -max.z - backoff, -min.z),
+            layers: layers,
+        })
+    }
+}
+
+/// Compute the split distances of shadow cascades between `near` and `far`,
+/// blending the logarithmic and uniform distributions by `lambda`, where
+/// one is fully logarithmic. Returns `count + 1` distances, starting with
+/// `near` and ending with `far`, or nothing if `count` is zero.
+pub fn cascade_splits<S: cgmath::BaseFloat>(near: S, far: S, count: usize, lambda: S)
+                      -> Vec<S> {
+    if count == 0 {
+        return Vec::new()
+    }
+    let one: S = cgmath::one();
+    let total = S::from(count).unwrap();
+    (0 .. count + 1).map(|i| {
+        let k = S::from(i).unwrap() / total;
+        let log = near * (far / near).powf(k);
+        let uniform = near + (far - near) * k;
+        lambda * log + (one - lambda) * uniform
+    }).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath;
+    use cgmath::{Angle, Matrix, Point, Transform};
+    use test;
+    use super::{View, cascade_splits};
+
+    fn project(view: &View<f32, test::Transform>, p: cgmath::Point3<f32>) -> cgmath::Point3<f32> {
+        cgmath::Point3::from_homogeneous(&view.get_view_projection().mul_v(&p.to_homogeneous()))
+    }
+
+    fn is_inside(p: cgmath::Point3<f32>) -> bool {
+        let e = 1.0 + 1e-4;
+        p.x.abs() <= e && p.y.abs() <= e && p.z.abs() <= e
+    }
+
+    #[test]
+    fn splits() {
+        assert_eq!(cascade_splits(1.0f32, 4.0, 3, 0.0), vec![1.0, 2.0, 3.0, 4.0]);
+        let log = cascade_splits(1.0f32, 8.0, 3, 1.0);
+        for (a, b) in log.iter().zip([1.0, 2.0, 4.0, 8.0].iter()) {
+            assert!((a - b).abs() < 1e-5);
+        }
+        assert!(cascade_splits(1.0f32, 8.0, 0, 0.5).is_empty());
+    }
+
+    #[test]
+    fn cube_faces() {
+        let center = cgmath::Point3::new(1.0f32, 2.0, 3.0);
+        let views: Vec<View<f32, test::Transform>> = View::cube_faces(center, 0.5, 10.0, 5);
+        let dirs = [[1.0, 0.0, 0.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0],
+                    [0.0, -1.0, 0.0], [0.0, 0.0, 1.0], [0.0, 0.0, -1.0]];
+        assert_eq!(views.len(), 6);
+        for (view, d) in views.iter().zip(dirs.iter()) {
+            assert_eq!(view.layers, 5);
+            let v = cgmath::Vector3::new(d[0], d[1], d[2]);
+            // the point along the face direction is in the middle of the face
+            let p = view.transform.transform_point(&center.add_v(&v.mul_s(2.0)));
+            assert!(p.x.abs() < 1e-5 && p.y.abs() < 1e-5 && (p.z + 2.0).abs() < 1e-5);
+            // and the opposite one is behind
+            let q = view.transform.transform_point(&center.add_v(&v.mul_s(-2.0)));
+            assert!(q.z > 0.0);
+        }
+    }
+
+    #[test]
+    fn cascade() {
+        let fov = cgmath::PerspectiveFov {
+            fovy: cgmath::Rad::turn_div_4(),
+            aspect: 1.0f32,
+            near: 1.0,
+            far: 100.0,
+        };
+        let camera: test::Transform = Transform::identity();
+        let dir = cgmath::Vector3::new(0.0, -1.0, -1.0);
+        let view = View::cascade(&fov, &camera, 1.0, 2.0, dir, 10.0, 1).unwrap();
+        // all the corners of the slice are covered
+        for &z in [-1.0f32, -2.0].iter() {
+            for &x in [-z, z].iter() {
+                for &y in [-z, z].iter() {
+                    assert!(is_inside(project(&view, cgmath::Point3::new(x, y, z))));
+                }
+            }
+        }
+        // a caster between the light and the slice is kept by the backoff
+        assert!(is_inside(project(&view, cgmath::Point3::new(0.0, 2.0, 0.5))));
+    }
+}